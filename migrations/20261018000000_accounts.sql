@@ -0,0 +1,7 @@
+-- wallets created before migrations existed already have this table
+create table if not exists accounts (
+    id serial primary key,
+    ciphertext bytea not null,
+    nonce bytea not null,
+    die_at bigint not null
+);
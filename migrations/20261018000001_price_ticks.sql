@@ -0,0 +1,17 @@
+-- every pool state change a listener saw, candles are aggregated from these
+create table price_ticks (
+    id bigserial primary key,
+    pool text not null,
+    slot bigint not null,
+    -- unix time in milliseconds the update arrived at
+    observed_at bigint not null,
+    sqrt_price_x64 numeric(39, 0) not null,
+    tick integer not null,
+    liquidity numeric(39, 0) not null,
+    -- of mint0 in mint1, decimals applied
+    price double precision not null,
+    -- estimated mint0 swapped since the previous tick of the pool, decimals applied
+    volume0 double precision not null
+);
+
+create index price_ticks_pool_observed_at on price_ticks (pool, observed_at);
@@ -0,0 +1,11 @@
+-- progress of `backfill` runs, so an interrupted one picks up where it stopped
+create table backfill_cursors (
+    pool text not null,
+    -- unix seconds, to_time excluded
+    from_time bigint not null,
+    to_time bigint not null,
+    -- oldest signature already processed, the history is walked backwards
+    before_signature text,
+    done boolean not null default false,
+    primary key (pool, from_time, to_time)
+);
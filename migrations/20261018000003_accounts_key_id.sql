@@ -0,0 +1,3 @@
+-- fingerprint of the master key a wallet is sealed with, null for wallets sealed before
+-- keys had ids, those are tried against every configured key
+alter table accounts add column key_id text;
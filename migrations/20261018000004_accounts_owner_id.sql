@@ -0,0 +1,3 @@
+-- the account that created a wallet through the API and may manage it, null for wallets
+-- of `new wallet`
+alter table accounts add column owner_id integer references accounts (id);
@@ -0,0 +1,11 @@
+-- where a tick comes from: 'live' ticks are pool updates a listener saw with an estimated
+-- volume, 'backfill' ticks are swap events with the swapped amount as volume. Rows written
+-- before count as live.
+alter table price_ticks add column source text not null default 'live'
+    check (source in ('live', 'backfill'));
+-- the transaction and the position among the swap events of the pool in it, backfill only
+alter table price_ticks add column signature text;
+alter table price_ticks add column event_index integer;
+-- every swap event is written once, however many backfills cover it
+create unique index price_ticks_pool_signature_event_index
+    on price_ticks (pool, signature, event_index);
@@ -0,0 +1,160 @@
+use std::str::FromStr;
+
+use futures::{stream, StreamExt, TryStreamExt};
+use raydium_amm_v3::states::PoolState;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient, rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::RpcTransactionConfig, rpc_response::RpcConfirmedTransactionStatusWithSignature,
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::UiTransactionEncoding;
+use sqlx::PgPool;
+
+use crate::{
+    models::{backfill_cursor::BackfillCursor, price_tick},
+    solclient::{
+        events::swap_events,
+        pool::{deserialize_anchor_account, PriceTick},
+    },
+};
+
+/// Signatures per `getSignaturesForAddress` page, the most the RPC hands out
+const PAGE_SIZE: usize = 1000;
+/// Transactions fetched at the same time
+const CONCURRENCY: usize = 8;
+
+/// Records a price tick for every swap of the CLMM `pool` between `from` and `to` (unix
+/// seconds, `to` excluded), read from the pool's transaction history. Every page of
+/// signatures is written together with the cursor, so an interrupted run over the same
+/// range resumes after the last page it wrote. Swaps already written by an overlapping
+/// run are skipped, and live ticks of the slots a page covers are replaced since their
+/// volume is only estimated. Returns the ticks written by this run.
+pub async fn backfill(
+    rpc: &RpcClient,
+    db: &PgPool,
+    clmm_program: &Pubkey,
+    pool: Pubkey,
+    from: i64,
+    to: i64,
+) -> anyhow::Result<usize> {
+    if from >= to {
+        anyhow::bail!("from has to be before to");
+    }
+    let account = rpc.get_account(&pool).await?;
+    if account.owner != *clmm_program {
+        anyhow::bail!("{} is not a CLMM pool", pool);
+    }
+    let pool_state = deserialize_anchor_account::<PoolState>(&account)?;
+    let (decimals_0, decimals_1) = (pool_state.mint_decimals_0, pool_state.mint_decimals_1);
+
+    let mut cursor = BackfillCursor::find_or_create(db, &pool, from, to).await?;
+    let mut written = 0;
+    while !cursor.done {
+        let before = cursor
+            .before_signature
+            .as_deref()
+            .map(Signature::from_str)
+            .transpose()?;
+        let page = rpc
+            .get_signatures_for_address_with_config(
+                &pool,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until: None,
+                    limit: Some(PAGE_SIZE),
+                    commitment: Some(CommitmentConfig::finalized()),
+                },
+            )
+            .await?;
+        let Some(oldest) = page.last() else {
+            let before = cursor.before_signature.clone();
+            cursor.advance(db, before, true).await?;
+            break;
+        };
+        let done = page.len() < PAGE_SIZE || oldest.block_time.is_some_and(|time| time < from);
+
+        let in_range: Vec<_> = page
+            .iter()
+            .filter(|status| {
+                status
+                    .block_time
+                    .is_some_and(|time| (from..to).contains(&time))
+            })
+            .collect();
+        // newest first like the page, failed transactions changed nothing
+        let ticks: Vec<Vec<PriceTick>> =
+            stream::iter(in_range.iter().filter(|status| status.err.is_none()))
+                .map(|status| swap_ticks(rpc, &pool, status, decimals_0, decimals_1))
+                .buffered(CONCURRENCY)
+                .try_collect()
+                .await?;
+        // oldest first, ticks of the same second keep their order through their ids
+        let ticks: Vec<PriceTick> = ticks.into_iter().rev().flatten().collect();
+
+        let mut tx = db.begin().await?;
+        if let (Some(last), Some(first)) = (in_range.first(), in_range.last()) {
+            price_tick::delete_live(&mut *tx, &pool, first.slot, last.slot).await?;
+        }
+        let inserted = if ticks.is_empty() {
+            0
+        } else {
+            price_tick::save_all(&mut *tx, &ticks).await?
+        };
+        cursor
+            .advance(&mut *tx, Some(oldest.signature.clone()), done)
+            .await?;
+        tx.commit().await?;
+
+        written += inserted as usize;
+        log::info!(
+            "Backfilled {} ticks of {} down to unix time {}",
+            written,
+            pool,
+            oldest.block_time.unwrap_or_default()
+        );
+    }
+    Ok(written)
+}
+
+/// Price ticks of the swaps of `pool` in one transaction
+async fn swap_ticks(
+    rpc: &RpcClient,
+    pool: &Pubkey,
+    status: &RpcConfirmedTransactionStatusWithSignature,
+    decimals_0: u8,
+    decimals_1: u8,
+) -> anyhow::Result<Vec<PriceTick>> {
+    let signature = Signature::from_str(&status.signature)?;
+    let transaction = rpc
+        .get_transaction_with_config(
+            &signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Json),
+                commitment: Some(CommitmentConfig::finalized()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .await?;
+    let logs: Option<Vec<String>> = transaction
+        .transaction
+        .meta
+        .and_then(|meta| meta.log_messages.into());
+    let block_time = transaction
+        .block_time
+        .or(status.block_time)
+        .unwrap_or_default();
+
+    Ok(swap_events(&logs.unwrap_or_default(), pool)
+        .enumerate()
+        .map(|(index, event)| {
+            event.price_tick(
+                signature,
+                index as u32,
+                transaction.slot,
+                block_time,
+                decimals_0,
+                decimals_1,
+            )
+        })
+        .collect())
+}
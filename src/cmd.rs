@@ -1,12 +1,20 @@
-use std::str::FromStr;
+use std::{str::FromStr, time::UNIX_EPOCH};
 
 use actix_web::{middleware::Logger, web, App, HttpServer};
 use aes_gcm::{aead::OsRng, Aes256Gcm, KeyInit};
 use clap::{Parser, Subcommand};
-use solana_sdk::pubkey::Pubkey;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
 use sqlx::PgPool;
 
-use crate::{models::account::Account, routes::routes, state::AppState};
+use crate::{
+    backfill::backfill,
+    fixture,
+    models::account::{self, Account, Keyring, Masterkey},
+    routes::routes,
+    solclient::market::RaydiumPrograms,
+    state::AppState,
+};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -33,10 +41,78 @@ enum Commands {
         /// Raydium CLMM program ID, useful for testing on devnet
         #[arg(long, default_value = crate::constants::RAYDIUM_CLMM_PUBKEY)]
         raydium_clmm: String,
-        // #[arg(long, value_parser = parse_masterkey)]
-        // masterkey: std::vec::Vec<u8>,
-        // #[arg(long)]
-        // jwt_secret: String,
+        /// Raydium CPMM program ID
+        #[arg(long, default_value = crate::constants::RAYDIUM_CPMM_PUBKEY)]
+        raydium_cpmm: String,
+        /// Raydium AMM v4 program ID
+        #[arg(long, default_value = crate::constants::RAYDIUM_AMM_V4_PUBKEY)]
+        raydium_amm_v4: String,
+        /// How many tick arrays a single quote may load before giving up
+        #[arg(long, default_value_t = crate::constants::MAX_TICK_ARRAYS)]
+        max_tick_arrays: usize,
+        /// How long a pool nobody subscribes to anymore keeps being listened to
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "60s")]
+        grace_period: std::time::Duration,
+        /// Keys the wallets are sealed with, see `new masterkey`. The first one seals new
+        /// wallets, the others only unseal wallets not rotated to it yet.
+        #[arg(long, value_parser = parse_masterkey, value_delimiter = ',', required = true)]
+        masterkey: Vec<Masterkey>,
+        /// Secret the access tokens are signed with
+        #[arg(long)]
+        jwt_secret: String,
+        /// Accounts allowed to use the admin endpoints, none by default
+        #[arg(long, value_delimiter = ',')]
+        admin_account: Vec<i32>,
+    },
+    /// Records the swaps of a CLMM pool over a time range from its transaction history,
+    /// an interrupted run over the same range resumes where it stopped
+    Backfill {
+        /// Solana RPC URL, has to keep the history of the range
+        #[arg(short, long)]
+        rpc: String,
+        /// Raydium CLMM program ID
+        #[arg(long, default_value = crate::constants::RAYDIUM_CLMM_PUBKEY)]
+        raydium_clmm: String,
+        /// The CLMM pool
+        #[arg(long)]
+        pool: String,
+        /// Start of the range, e.g. 2024-06-01T00:00:00Z
+        #[arg(long, value_parser = parse_timestamp)]
+        from: i64,
+        /// End of the range, excluded
+        #[arg(long, value_parser = parse_timestamp)]
+        to: i64,
+    },
+    /// Snapshots a CLMM pool together with the next swap against it as a quote fixture
+    /// for `tests/fixtures`, kept only when the quote engine reproduces that swap
+    CaptureFixture {
+        /// Solana RPC URL
+        #[arg(short, long)]
+        rpc: String,
+        /// Raydium CLMM program ID
+        #[arg(long, default_value = crate::constants::RAYDIUM_CLMM_PUBKEY)]
+        raydium_clmm: String,
+        /// The CLMM pool, a busy one is captured sooner
+        #[arg(long)]
+        pool: String,
+        /// How long to keep trying swaps
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "5m")]
+        wait: std::time::Duration,
+        /// Tick arrays the swap has to cross at least, 2 for one crossing a boundary
+        #[arg(long, default_value_t = 1)]
+        min_tick_arrays: usize,
+        /// File the fixture is written to, e.g. tests/fixtures/mainnet_sol_usdc.json
+        #[arg(long)]
+        out: std::path::PathBuf,
+    },
+    /// Re-encrypts every wallet under a new master key, all or nothing
+    RotateMasterkey {
+        /// The keys the wallets are sealed with now
+        #[arg(long, value_parser = parse_masterkey, value_delimiter = ',', required = true)]
+        masterkey: Vec<Masterkey>,
+        /// The key to seal them with from now on, see `new masterkey`
+        #[arg(long, value_parser = parse_masterkey)]
+        new_masterkey: Masterkey,
     },
 }
 
@@ -49,18 +125,24 @@ enum NewCommands {
         /// How long this account will be valid for
         #[arg(long, value_parser = parse_duration)]
         duration: chrono::Duration,
-        // #[arg(long, value_parser = parse_masterkey)]
-        // masterkey: std::vec::Vec<u8>,
-        // #[arg(long)]
-        // jwt_secret: String,
+        /// Key the wallet is sealed with, the first one of the server
+        #[arg(long, value_parser = parse_masterkey)]
+        masterkey: Masterkey,
+        /// Secret the access token of the wallet is signed with, the server's one
+        #[arg(long)]
+        jwt_secret: String,
     },
 }
 
 fn parse_duration(s: &str) -> anyhow::Result<chrono::Duration> {
     Ok(chrono::Duration::from_std(humantime::parse_duration(s)?)?)
 }
-fn parse_masterkey(s: &str) -> anyhow::Result<Vec<u8>> {
-    Ok(bs58::decode(s).into_vec()?)
+fn parse_timestamp(s: &str) -> anyhow::Result<i64> {
+    let time = humantime::parse_rfc3339_weak(s)?;
+    Ok(time.duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+fn parse_masterkey(s: &str) -> anyhow::Result<Masterkey> {
+    Masterkey::new(bs58::decode(s).into_vec()?)
 }
 
 impl Cmd {
@@ -69,12 +151,29 @@ impl Cmd {
             Some(Commands::Server {
                 rpc,
                 raydium_clmm,
+                raydium_cpmm,
+                raydium_amm_v4,
+                max_tick_arrays,
+                grace_period,
+                masterkey,
+                jwt_secret,
+                admin_account,
             }) => {
                 let pool = create_pool(&self.database_url).await?;
                 let state = AppState::new(
                     rpc.to_string(),
                     pool,
-                    Pubkey::from_str(raydium_clmm).expect("RAYDIUM_CLMM_PUBKEY invalid"),
+                    jwt_secret.to_string(),
+                    admin_account.clone(),
+                    Keyring::new(masterkey.clone())?,
+                    RaydiumPrograms {
+                        clmm: Pubkey::from_str(raydium_clmm).expect("RAYDIUM_CLMM_PUBKEY invalid"),
+                        cpmm: Pubkey::from_str(raydium_cpmm).expect("RAYDIUM_CPMM_PUBKEY invalid"),
+                        amm_v4: Pubkey::from_str(raydium_amm_v4)
+                            .expect("RAYDIUM_AMM_V4_PUBKEY invalid"),
+                    },
+                    *max_tick_arrays,
+                    *grace_period,
                 )
                 .await
                 .expect("Unable to create state");
@@ -88,6 +187,46 @@ impl Cmd {
                 .run()
                 .await?;
             }
+            Some(Commands::Backfill {
+                rpc,
+                raydium_clmm,
+                pool,
+                from,
+                to,
+            }) => {
+                let db = create_pool(&self.database_url).await?;
+                let rpc =
+                    RpcClient::new_with_commitment(rpc.to_string(), CommitmentConfig::finalized());
+                let clmm = Pubkey::from_str(raydium_clmm).expect("RAYDIUM_CLMM_PUBKEY invalid");
+                let pool = Pubkey::from_str(pool)?;
+                let written = backfill(&rpc, &db, &clmm, pool, *from, *to).await?;
+                println!("Price ticks written: {}", written);
+            }
+            Some(Commands::CaptureFixture {
+                rpc,
+                raydium_clmm,
+                pool,
+                wait,
+                min_tick_arrays,
+                out,
+            }) => {
+                let rpc =
+                    RpcClient::new_with_commitment(rpc.to_string(), CommitmentConfig::finalized());
+                let clmm = Pubkey::from_str(raydium_clmm).expect("RAYDIUM_CLMM_PUBKEY invalid");
+                let pool = Pubkey::from_str(pool)?;
+                let fixture = fixture::capture(&rpc, &clmm, pool, *wait, *min_tick_arrays).await?;
+                std::fs::write(out, serde_json::to_string_pretty(&fixture)? + "\n")?;
+                println!("Fixture written: {}", fixture.description);
+            }
+            Some(Commands::RotateMasterkey {
+                masterkey,
+                new_masterkey,
+            }) => {
+                let db = create_pool(&self.database_url).await?;
+                let masterkeys = Keyring::new(masterkey.clone())?;
+                let rotated = account::rotate_masterkey(&db, &masterkeys, new_masterkey).await?;
+                println!("Wallets rotated to key {}: {}", new_masterkey.id, rotated);
+            }
             Some(Commands::New(cmd)) => match cmd {
                 NewCommands::Masterkey => {
                     let masterkey = Aes256Gcm::generate_key(OsRng);
@@ -95,12 +234,18 @@ impl Cmd {
                 }
                 NewCommands::Wallet {
                     duration,
+                    masterkey,
+                    jwt_secret,
                 } => {
                     let pool = create_pool(&self.database_url).await?;
-                    let mut account = Account::new_unique(&[], *duration).unwrap();
+                    let mut account = Account::new_unique(masterkey, *duration)?;
                     let id = account.save(&pool).await?;
                     println!("Account id: {}", id);
                     println!("Public key: {}", account.pubkey());
+                    println!(
+                        "Access token: {}",
+                        account.access_token(jwt_secret.as_bytes())?
+                    );
                 }
             },
             None => todo!(),
@@ -111,15 +256,6 @@ impl Cmd {
 
 async fn create_pool(database_url: &str) -> anyhow::Result<PgPool> {
     let pool = PgPool::connect(database_url).await?;
-    sqlx::query!(
-        "create table if not exists accounts (
-                id serial primary key,
-                ciphertext bytea not null,
-                nonce bytea not null,
-                die_at bigint not null
-            );"
-    )
-    .execute(&pool)
-    .await?;
+    sqlx::migrate!().run(&pool).await?;
     Ok(pool)
 }
@@ -6,3 +6,29 @@ pub const POOL_TICK_ARRAY_BITMAP_SEED: &str = "pool_tick_array_bitmap_extension"
 
 // #[cfg(not(feature = "devnet"))]
 pub const RAYDIUM_CLMM_PUBKEY: &str = "CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK";
+
+/// Tick arrays fetched per `getMultipleAccounts` round trip while a quote walks the price
+pub const TICK_ARRAY_BATCH: usize = 5;
+/// Default cap of tick arrays a single quote may load, see `--max-tick-arrays`
+pub const MAX_TICK_ARRAYS: usize = 30;
+/// Tick arrays a swap instruction passes at most, more do not fit into a transaction
+pub const MAX_SWAP_TICK_ARRAYS: usize = 8;
+/// How long tick arrays a quote fetched outside the listened window are reused, nothing
+/// keeps them up to date
+pub const FETCHED_TICK_ARRAY_TTL: std::time::Duration = std::time::Duration::from_secs(30);
+/// Sizes a price feed ladder may quote at once
+pub const MAX_LADDER_RUNGS: usize = 20;
+/// Pools one multiplexed price feed socket may stream, and subscribe to at once
+pub const MAX_SOCKET_SUBSCRIPTIONS: usize = 20;
+/// Longest a wallet created or extended over the API lives, counted from now
+pub const MAX_WALLET_DAYS: i64 = 365;
+/// Longest route the router searches, in pools
+pub const MAX_HOPS: usize = 3;
+/// Routes the router actually quotes, the most promising ones by depth and length
+pub const MAX_ROUTE_CANDIDATES: usize = 20;
+/// Chunks a split order is divided into, each one goes to the pool paying most for it
+pub const SPLIT_STEPS: u64 = 20;
+/// Raydium constant product pools, token-2022 aware, see `--raydium-cpmm`
+pub const RAYDIUM_CPMM_PUBKEY: &str = "CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C";
+/// Raydium legacy AMM v4 pools backed by an OpenBook market, see `--raydium-amm-v4`
+pub const RAYDIUM_AMM_V4_PUBKEY: &str = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8";
@@ -10,6 +10,36 @@ pub enum AppError {
     Unknown,
     #[error("Account doesn't exists")]
     AccountDoesntExist,
+    #[error("Account expired at {0}")]
+    AccountExpired(chrono::DateTime<chrono::Utc>),
     #[error("Error while decoding account")]
     AccountDecodingError,
+    #[error("no pool found for {0} / {1}")]
+    PoolNotFound(solana_sdk::pubkey::Pubkey, solana_sdk::pubkey::Pubkey),
+    #[error("no route found from {0} to {1}")]
+    NoRoute(solana_sdk::pubkey::Pubkey, solana_sdk::pubkey::Pubkey),
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapError {
+    #[error("{0}")]
+    InvalidInput(&'static str),
+    #[error("tick array {0} is not loaded")]
+    TickArrayNotLoaded(i32),
+    #[error("swap needs more than {0} tick arrays")]
+    TickArrayLimit(usize),
+    #[error("swap crosses {0} tick arrays, a transaction fits {1}")]
+    TooManyTickArrays(usize, usize),
+    #[error("insufficient liquidity in range")]
+    InsufficientLiquidity,
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OracleError {
+    #[error("the pool has no observations yet")]
+    NotInitialized,
+    #[error("window must not be empty")]
+    EmptyWindow,
+    #[error("observations only reach back to unix time {0}")]
+    HistoryTooShort(u32),
 }
@@ -1,37 +1,89 @@
-use actix_web::error::{ErrorBadRequest, ErrorUnauthorized};
+use actix_web::error::{ErrorForbidden, ErrorUnauthorized};
 use actix_web::{dev, web, Error, FromRequest, HttpRequest};
-use futures::future::{err, ok, Ready};
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use futures::future::{ready, Ready};
+use jsonwebtoken::{decode, errors::ErrorKind, Algorithm, DecodingKey, Validation};
 
 use crate::models::account::AccountClaims;
 use crate::state::AppState;
 
+/// The id of the account a request is authenticated as, taken from the `Authorization:
+/// Bearer <JWT>` header
 pub struct AuthorizationGuard(pub i32);
 
+impl AuthorizationGuard {
+    /// Verifies the bearer token of `req` against `jwt_secret`, its `exp` included
+    pub fn from_header(req: &HttpRequest, jwt_secret: &[u8]) -> Result<Self, Error> {
+        let token = req
+            .headers()
+            .get("Authorization")
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .ok_or_else(|| ErrorUnauthorized("missing bearer token"))?;
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        // the token lives exactly as long as its wallet
+        validation.leeway = 0;
+        let token_data = decode::<AccountClaims>(
+            token.trim(),
+            &DecodingKey::from_secret(jwt_secret),
+            &validation,
+        )
+        .map_err(|e| match e.kind() {
+            ErrorKind::ExpiredSignature => ErrorUnauthorized("token expired"),
+            _ => ErrorUnauthorized("invalid token"),
+        })?;
+
+        let account_id = token_data
+            .claims
+            .sub
+            .parse()
+            .map_err(|_| ErrorUnauthorized("invalid token"))?;
+        Ok(Self(account_id))
+    }
+}
+
+/// An account of `--admin-account`, authenticated the same way as [`AuthorizationGuard`]
+pub struct AdminGuard(pub i32);
+
+impl AdminGuard {
+    /// Verifies the bearer token of `req` and that its account is one of `admins`
+    pub fn from_header(
+        req: &HttpRequest,
+        jwt_secret: &[u8],
+        admins: &[i32],
+    ) -> Result<Self, Error> {
+        let AuthorizationGuard(account_id) = AuthorizationGuard::from_header(req, jwt_secret)?;
+        if !admins.contains(&account_id) {
+            return Err(ErrorForbidden("not an admin account"));
+        }
+        Ok(Self(account_id))
+    }
+}
+
 impl FromRequest for AuthorizationGuard {
     type Error = Error;
     type Future = Ready<Result<AuthorizationGuard, Self::Error>>;
 
     fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
-        // let auth = req.headers().get("Authorization");
-        // match auth {
-        //     Some(header) => {
-        //         let state: &web::Data<AppState> = req
-        //             .app_data()
-        //             .expect("AppState None in AuthorizationGuard, this should never happen");
-        //         let token = header.to_str().map_err(ErrorBadRequest).unwrap();
-        //         let token_data = decode::<AccountClaims>(
-        //             &token[7..],
-        //             &DecodingKey::from_secret(state.jwt_secret.as_bytes()),
-        //             &Validation::new(Algorithm::HS256),
-        //         );
-        //         match token_data {
-        //             Ok(data) => ok(Self(data.claims.sub.parse().unwrap())),
-        //             Err(_) => err(ErrorUnauthorized("")),
-        //         }
-        //     }
-        //     None => err(ErrorUnauthorized("")),
-        // }
-        ok(Self(1)) // 临时返回一个固定的 account_id
+        let state: &web::Data<AppState> = req
+            .app_data()
+            .expect("AppState None in AuthorizationGuard, this should never happen");
+        ready(Self::from_header(req, state.jwt_secret.as_bytes()))
+    }
+}
+
+impl FromRequest for AdminGuard {
+    type Error = Error;
+    type Future = Ready<Result<AdminGuard, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
+        let state: &web::Data<AppState> = req
+            .app_data()
+            .expect("AppState None in AdminGuard, this should never happen");
+        ready(Self::from_header(
+            req,
+            state.jwt_secret.as_bytes(),
+            &state.admin_accounts,
+        ))
     }
 }
@@ -0,0 +1,405 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use raydium_amm_v3::states::{
+    AmmConfig, PoolState, TickArrayBitmapExtension, TickArrayState, POOL_TICK_ARRAY_BITMAP_SEED,
+};
+use serde::Serialize;
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_client::{
+    nonblocking::rpc_client::RpcClient, rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::RpcTransactionConfig,
+};
+use solana_sdk::{
+    account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature,
+};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, UiInnerInstructions, UiInstruction,
+    UiLoadedAddresses, UiTransactionEncoding,
+};
+
+use crate::{
+    constants::{MAX_TICK_ARRAYS, TICK_ARRAY_BATCH},
+    solclient::{
+        engine::{PoolSnapshot, QuoteEngine},
+        events::{swap_events, SwapEvent},
+        pool::{deserialize_anchor_account, tick_array_keys_around},
+        utils,
+    },
+};
+
+/// Signatures per `getSignaturesForAddress` page, the most the RPC hands out
+const PAGE_SIZE: usize = 1000;
+/// Pause between looking for the swap that follows the snapshot
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A quote fixture of `tests/fixtures`, a pool snapshot with the swap that followed it
+#[derive(Serialize)]
+pub struct Fixture {
+    pub description: String,
+    pub snapshot: PoolSnapshot,
+    pub swaps: Vec<FixtureSwap>,
+}
+
+#[derive(Serialize)]
+pub struct FixtureSwap {
+    pub amount: u64,
+    pub sqrt_price_limit_x64: Option<u128>,
+    pub zero_for_one: bool,
+    pub is_base_input: bool,
+    pub observed: Observed,
+}
+
+/// What the swap did on-chain, in the terms of the swap math
+#[derive(Serialize)]
+pub struct Observed {
+    pub amount_calculated: u64,
+    pub amount_specified_remaining: u64,
+    pub sqrt_price_x64: u128,
+}
+
+/// Arguments of a `swap`/`swap_v2` instruction, both share the layout
+#[derive(Debug, Clone, Copy)]
+struct SwapArgs {
+    amount: u64,
+    sqrt_price_limit_x64: u128,
+    is_base_input: bool,
+}
+
+/// A capture that did not work out for this swap but may for the next one
+#[derive(Debug)]
+struct Retry(String);
+
+impl std::fmt::Display for Retry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Retry {}
+
+/// Snapshots the CLMM `pool` at a finalized slot and waits for the first transaction
+/// that changes it afterwards, which has to be a single swap crossing at least
+/// `min_tick_arrays` tick arrays. The fixture is only returned when the offline quote
+/// engine reproduces that swap from the snapshot exactly, otherwise it starts over with
+/// a new snapshot for up to `wait`. Mints with transfer fees are not supported.
+pub async fn capture(
+    rpc: &RpcClient,
+    clmm_program: &Pubkey,
+    pool: Pubkey,
+    wait: Duration,
+    min_tick_arrays: usize,
+) -> anyhow::Result<Fixture> {
+    let started = Instant::now();
+    loop {
+        let left = wait.saturating_sub(started.elapsed());
+        match capture_once(rpc, clmm_program, pool, left, min_tick_arrays).await {
+            Err(e) if e.is::<Retry>() && started.elapsed() < wait => {
+                log::info!("{}, trying the next swap", e)
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn capture_once(
+    rpc: &RpcClient,
+    clmm_program: &Pubkey,
+    pool: Pubkey,
+    wait: Duration,
+    min_tick_arrays: usize,
+) -> anyhow::Result<Fixture> {
+    let (slot, snapshot) = snapshot(rpc, clmm_program, &pool).await?;
+    let engine = QuoteEngine::from_snapshot(&snapshot)?;
+    log::info!(
+        "Snapshot of {} at slot {}, waiting for its next swap",
+        pool,
+        slot
+    );
+
+    let signature = next_transaction(rpc, &pool, slot, wait).await?;
+    let transaction = rpc
+        .get_transaction_with_config(
+            &Signature::from_str(&signature)?,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(CommitmentConfig::finalized()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .await?;
+    let logs: Option<Vec<String>> = transaction
+        .transaction
+        .meta
+        .as_ref()
+        .and_then(|meta| meta.log_messages.clone().into());
+    let events: Vec<SwapEvent> = swap_events(&logs.unwrap_or_default(), &pool).collect();
+    let args = swap_args(&transaction, clmm_program, &pool)?;
+    let (event, args) = match (events.as_slice(), args.as_slice()) {
+        ([event], [args]) => (*event, *args),
+        _ => {
+            return Err(Retry(format!(
+                "transaction {} after the snapshot is not a single swap of {}",
+                signature, pool
+            ))
+            .into())
+        }
+    };
+    if event.transfer_fee_0 != 0 || event.transfer_fee_1 != 0 {
+        anyhow::bail!("pools of mints with a transfer fee are not supported");
+    }
+
+    let (amount_in, amount_out) = if event.zero_for_one {
+        (event.amount_0, event.amount_1)
+    } else {
+        (event.amount_1, event.amount_0)
+    };
+    let (amount_calculated, amount_used) = if args.is_base_input {
+        (amount_out, amount_in)
+    } else {
+        (amount_in, amount_out)
+    };
+    let swap = FixtureSwap {
+        amount: args.amount,
+        // the program treats no limit as the far end of the price range
+        sqrt_price_limit_x64: Some(args.sqrt_price_limit_x64).filter(|limit| *limit != 0),
+        zero_for_one: event.zero_for_one,
+        is_base_input: args.is_base_input,
+        observed: Observed {
+            amount_calculated,
+            amount_specified_remaining: args.amount.saturating_sub(amount_used),
+            sqrt_price_x64: event.sqrt_price_x64,
+        },
+    };
+
+    let (state, tick_arrays) = engine.quote(
+        swap.amount,
+        swap.sqrt_price_limit_x64,
+        swap.zero_for_one,
+        swap.is_base_input,
+    )?;
+    if (
+        state.amount_calculated,
+        state.amount_specified_remaining,
+        state.sqrt_price_x64,
+    ) != (
+        swap.observed.amount_calculated,
+        swap.observed.amount_specified_remaining,
+        swap.observed.sqrt_price_x64,
+    ) {
+        return Err(Retry(format!(
+            "quote of {} does not reproduce swap {}, the pool changed in between",
+            pool, signature
+        ))
+        .into());
+    }
+    if tick_arrays.len() < min_tick_arrays {
+        return Err(Retry(format!(
+            "swap {} crosses {} tick arrays, less than {}",
+            signature,
+            tick_arrays.len(),
+            min_tick_arrays
+        ))
+        .into());
+    }
+
+    Ok(Fixture {
+        description: format!(
+            "Mainnet pool {} at slot {}, followed by the swap of transaction {} across {} \
+            tick arrays",
+            pool,
+            slot,
+            signature,
+            tick_arrays.len()
+        ),
+        snapshot,
+        swaps: vec![swap],
+    })
+}
+
+/// Raw accounts of the pool, all read at the same slot
+async fn snapshot(
+    rpc: &RpcClient,
+    clmm_program: &Pubkey,
+    pool: &Pubkey,
+) -> anyhow::Result<(u64, PoolSnapshot)> {
+    let pool_account = rpc.get_account(pool).await?;
+    if pool_account.owner != *clmm_program {
+        anyhow::bail!("{} is not a CLMM pool", pool);
+    }
+    let pool_state = deserialize_anchor_account::<PoolState>(&pool_account)?;
+    let (extension_key, _) = Pubkey::find_program_address(
+        &[POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(), pool.as_ref()],
+        clmm_program,
+    );
+    let extension = rpc.get_account(&extension_key).await?;
+    let extension = deserialize_anchor_account::<TickArrayBitmapExtension>(&extension)?;
+
+    let mut keys = vec![*pool, pool_state.amm_config, extension_key];
+    keys.extend(tick_array_keys_around(
+        clmm_program,
+        pool,
+        &pool_state,
+        &extension,
+        MAX_TICK_ARRAYS.min(TICK_ARRAY_BATCH * 2),
+    ));
+    let response = rpc
+        .get_multiple_accounts_with_commitment(&keys, CommitmentConfig::finalized())
+        .await?;
+    let accounts = keys
+        .iter()
+        .zip(response.value)
+        .map(|(key, account)| {
+            let account = account.ok_or_else(|| anyhow::anyhow!("account {} is gone", key))?;
+            Ok((*key, account))
+        })
+        .collect::<anyhow::Result<Vec<(Pubkey, Account)>>>()?;
+    // the tick arrays are the ones around the price the first read saw
+    if accounts[0].1.data != pool_account.data {
+        return Err(Retry(format!("pool {} changed while taking the snapshot", pool)).into());
+    }
+    deserialize_anchor_account::<AmmConfig>(&accounts[1].1)?;
+    for (_, tick_array) in &accounts[3..] {
+        deserialize_anchor_account::<TickArrayState>(tick_array)?;
+    }
+
+    let encode = |(key, account): &(Pubkey, Account)| {
+        UiAccount::encode(key, account, UiAccountEncoding::Base64, None, None)
+    };
+    Ok((
+        response.context.slot,
+        PoolSnapshot {
+            amm_config: encode(&accounts[1]),
+            pool_state: encode(&accounts[0]),
+            tick_array_bitmap_extension: encode(&accounts[2]),
+            tick_arrays: accounts[3..].iter().map(encode).collect(),
+        },
+    ))
+}
+
+/// Signature of the first successful transaction of `pool` after `slot`
+async fn next_transaction(
+    rpc: &RpcClient,
+    pool: &Pubkey,
+    slot: u64,
+    wait: Duration,
+) -> anyhow::Result<String> {
+    let started = Instant::now();
+    loop {
+        let mut after = vec![];
+        let mut before = None;
+        loop {
+            let page = rpc
+                .get_signatures_for_address_with_config(
+                    pool,
+                    GetConfirmedSignaturesForAddress2Config {
+                        before,
+                        until: None,
+                        limit: Some(PAGE_SIZE),
+                        commitment: Some(CommitmentConfig::finalized()),
+                    },
+                )
+                .await?;
+            let reached = page.len() < PAGE_SIZE || page.last().map_or(true, |s| s.slot <= slot);
+            before = page
+                .last()
+                .map(|status| Signature::from_str(&status.signature))
+                .transpose()?;
+            after.extend(page.into_iter().filter(|status| status.slot > slot));
+            if reached {
+                break;
+            }
+        }
+
+        // newest first, failed transactions changed nothing
+        if let Some(status) = after.iter().rev().find(|status| status.err.is_none()) {
+            return Ok(status.signature.clone());
+        }
+        if started.elapsed() > wait {
+            anyhow::bail!("no transaction of {} within {:?}", pool, wait);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Arguments of the swap instructions of a transaction against `pool`, inner ones
+/// included. Any other CLMM instruction on the pool, e.g. a liquidity change, fails.
+fn swap_args(
+    transaction: &EncodedConfirmedTransactionWithStatusMeta,
+    clmm_program: &Pubkey,
+    pool: &Pubkey,
+) -> anyhow::Result<Vec<SwapArgs>> {
+    let versioned = transaction
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| anyhow::anyhow!("unable to decode transaction"))?;
+    let meta = transaction
+        .transaction
+        .meta
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("transaction without status"))?;
+
+    let mut keys = versioned.message.static_account_keys().to_vec();
+    let loaded: Option<UiLoadedAddresses> = meta.loaded_addresses.clone().into();
+    if let Some(UiLoadedAddresses { writable, readonly }) = loaded {
+        for key in writable.iter().chain(&readonly) {
+            keys.push(Pubkey::from_str(key)?);
+        }
+    }
+
+    let mut instructions: Vec<(u8, Vec<u8>, Vec<u8>)> = versioned
+        .message
+        .instructions()
+        .iter()
+        .map(|ix| (ix.program_id_index, ix.accounts.clone(), ix.data.clone()))
+        .collect();
+    let inner: Option<Vec<UiInnerInstructions>> = meta.inner_instructions.clone().into();
+    for ix in inner
+        .into_iter()
+        .flatten()
+        .flat_map(|inner| inner.instructions)
+    {
+        if let UiInstruction::Compiled(ix) = ix {
+            let data = bs58::decode(&ix.data).into_vec()?;
+            instructions.push((ix.program_id_index, ix.accounts, data));
+        }
+    }
+
+    let key = |index: u8| {
+        keys.get(index as usize)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("account index {} out of range", index))
+    };
+    let swap = utils::anchor_discriminator("global:swap");
+    let swap_v2 = utils::anchor_discriminator("global:swap_v2");
+    let mut args = vec![];
+    for (program_id_index, accounts, data) in instructions {
+        if key(program_id_index)? != *clmm_program {
+            continue;
+        }
+        let accounts = accounts
+            .into_iter()
+            .map(key)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if !accounts.contains(pool) {
+            continue;
+        }
+        // payer, amm config, pool state, ... for both swap instructions
+        let is_swap = data.len() >= 41 && (data[..8] == swap || data[..8] == swap_v2);
+        if !is_swap || accounts.get(2) != Some(pool) {
+            anyhow::bail!(
+                "transaction changes {} through something else than a swap",
+                pool
+            );
+        }
+        args.push(SwapArgs {
+            amount: u64::from_le_bytes(data[8..16].try_into()?),
+            sqrt_price_limit_x64: u128::from_le_bytes(data[24..40].try_into()?),
+            is_base_input: data[40] != 0,
+        });
+    }
+    Ok(args)
+}
@@ -1,7 +1,9 @@
+pub mod backfill;
 pub mod cmd;
 pub mod constants;
 pub mod error;
 pub mod extractors;
+pub mod fixture;
 pub mod models;
 pub mod routes;
 pub mod solclient;
@@ -1,5 +1,3 @@
-use core::str;
-
 use aes_gcm::{
     aead::{Aead, AeadCore, KeyInit, OsRng},
     Aes256Gcm,
@@ -8,17 +6,64 @@ use aes_gcm::{
 };
 use jsonwebtoken::Header;
 use serde::{Deserialize, Serialize};
-use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+use solana_sdk::{hash::hash, pubkey::Pubkey, signature::Keypair, signer::Signer};
 use sqlx::PgPool;
 
+use crate::{constants::MAX_WALLET_DAYS, error::AppError};
+
 pub struct Account {
     pub id: i32,
     pub keypair: Keypair,
     ciphertext: Vec<u8>,
     nonce: Vec<u8>,
+    /// The master key the keypair is sealed with
+    pub key_id: String,
+    /// The account that created this one through the API, it may manage it
+    pub owner_id: Option<i32>,
     pub die_at: chrono::DateTime<chrono::Utc>,
 }
 
+/// A row of `accounts`, still sealed
+struct AccountRow {
+    id: i32,
+    ciphertext: Vec<u8>,
+    nonce: Vec<u8>,
+    key_id: Option<String>,
+    owner_id: Option<i32>,
+    die_at: i64,
+}
+
+impl AccountRow {
+    fn unseal(self, masterkeys: &Keyring) -> anyhow::Result<Account> {
+        let die_at = chrono::DateTime::from_timestamp(self.die_at, 0)
+            .ok_or(AppError::AccountDecodingError)?;
+        let (keypair, masterkey) =
+            masterkeys.unseal_keypair(self.key_id.as_deref(), &self.ciphertext, &self.nonce)?;
+        Ok(Account {
+            id: self.id,
+            keypair,
+            ciphertext: self.ciphertext,
+            nonce: self.nonce,
+            key_id: masterkey.id.clone(),
+            owner_id: self.owner_id,
+            die_at,
+        })
+    }
+}
+
+/// An AES-256-GCM key wallets are sealed with. Its id is a fingerprint of the key, stored
+/// next to every ciphertext so loading knows which key to use.
+#[derive(Clone)]
+pub struct Masterkey {
+    pub id: String,
+    key: Vec<u8>,
+}
+
+/// The master keys the server knows, the first one seals new wallets and the others only
+/// unseal wallets that were not rotated to it yet
+#[derive(Clone)]
+pub struct Keyring(Vec<Masterkey>);
+
 #[derive(Serialize, Deserialize)]
 pub struct AccountClaims {
     pub sub: String,
@@ -26,24 +71,32 @@ pub struct AccountClaims {
 }
 
 impl Account {
-    pub fn new_unique(masterkey: &[u8], duration: chrono::Duration) -> anyhow::Result<Self> {
+    pub fn new_unique(masterkey: &Masterkey, duration: chrono::Duration) -> anyhow::Result<Self> {
+        let die_at = chrono::Utc::now()
+            .checked_add_signed(duration)
+            .ok_or_else(|| anyhow::anyhow!("duration {} is out of range", duration))?;
         let keypair = Keypair::new();
-        let (ciphertext, nonce) = seal(keypair.to_base58_string().as_bytes(), masterkey)?;
+        let (ciphertext, nonce) = masterkey.seal_keypair(&keypair)?;
 
         Ok(Self {
             id: 0,
             keypair,
             ciphertext,
             nonce,
-            die_at: chrono::Utc::now() + duration,
+            key_id: masterkey.id.clone(),
+            owner_id: None,
+            die_at,
         })
     }
 
     pub async fn save(&mut self, pool: &PgPool) -> anyhow::Result<i32> {
         let rec = sqlx::query!(
-            "INSERT INTO accounts(ciphertext,nonce,die_at) values ($1,$2,$3) returning id",
+            "INSERT INTO accounts(ciphertext,nonce,key_id,owner_id,die_at)
+            values ($1,$2,$3,$4,$5) returning id",
             self.ciphertext,
             self.nonce,
+            self.key_id,
+            self.owner_id,
             self.die_at.timestamp()
         )
         .fetch_one(pool)
@@ -52,25 +105,89 @@ impl Account {
         Ok(rec.id)
     }
 
-    pub async fn find_one(pool: &PgPool, id: i32, masterkey: &[u8]) -> anyhow::Result<Self> {
-        dbg!("{}", chrono::Utc::now().timestamp());
-        dbg!(id);
-        let rec = sqlx::query!(
-            "SELECT ciphertext, nonce FROM accounts WHERE id = $1 and die_at > $2",
-            id,
-            chrono::Utc::now().timestamp()
+    /// The account with its keypair unsealed, as long as it is alive
+    pub async fn find_one(pool: &PgPool, id: i32, masterkeys: &Keyring) -> anyhow::Result<Self> {
+        let account = Self::find_any(pool, id, masterkeys).await?;
+        if account.is_expired() {
+            return Err(AppError::AccountExpired(account.die_at).into());
+        }
+        Ok(account)
+    }
+
+    /// The account with its keypair unsealed, expired or not
+    pub async fn find_any(pool: &PgPool, id: i32, masterkeys: &Keyring) -> anyhow::Result<Self> {
+        sqlx::query_as!(
+            AccountRow,
+            "SELECT id, ciphertext, nonce, key_id, owner_id, die_at FROM accounts WHERE id = $1",
+            id
         )
-        .fetch_one(pool)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AppError::AccountDoesntExist)?
+        .unseal(masterkeys)
+    }
+
+    /// `owner_id` itself and the accounts it created, expired ones included
+    pub async fn find_managed_by(
+        pool: &PgPool,
+        owner_id: i32,
+        masterkeys: &Keyring,
+    ) -> anyhow::Result<Vec<Self>> {
+        sqlx::query_as!(
+            AccountRow,
+            "SELECT id, ciphertext, nonce, key_id, owner_id, die_at FROM accounts
+            WHERE id = $1 OR owner_id = $1 ORDER BY id",
+            owner_id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| row.unseal(masterkeys))
+        .collect()
+    }
+
+    /// Moves the end of the account, to now to expire it
+    pub async fn set_die_at(
+        &mut self,
+        pool: &PgPool,
+        die_at: chrono::DateTime<chrono::Utc>,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE accounts SET die_at = $2 WHERE id = $1",
+            self.id,
+            die_at.timestamp()
+        )
+        .execute(pool)
         .await?;
-        let secret = unseal(&rec.ciphertext, masterkey, &rec.nonce)?;
-        let keypair = Keypair::from_base58_string(str::from_utf8(&secret)?);
-        Ok(Self {
-            id,
-            keypair,
-            ciphertext: vec![],
-            nonce: vec![],
-            die_at: chrono::Utc::now(),
-        })
+        self.die_at = die_at;
+        Ok(())
+    }
+
+    /// Whether `account_id` may see, expire and sweep this account, it is either this one
+    /// or its owner
+    pub fn is_managed_by(&self, account_id: i32) -> bool {
+        self.id == account_id || self.is_owned_by(account_id)
+    }
+
+    /// Whether `account_id` created this account and may extend it. An account does not own
+    /// itself, its token would otherwise keep itself alive forever.
+    pub fn is_owned_by(&self, account_id: i32) -> bool {
+        self.owner_id == Some(account_id)
+    }
+
+    /// The `die_at` after extending by `duration`, counted from now once expired. None
+    /// when it would end more than `MAX_WALLET_DAYS` from now.
+    pub fn extended_die_at(
+        &self,
+        duration: chrono::Duration,
+    ) -> Option<chrono::DateTime<chrono::Utc>> {
+        let now = chrono::Utc::now();
+        let die_at = self.die_at.max(now).checked_add_signed(duration)?;
+        (die_at - now <= chrono::Duration::days(MAX_WALLET_DAYS)).then_some(die_at)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.die_at <= chrono::Utc::now()
     }
 
     pub fn access_token(&self, jwt_secret: &[u8]) -> anyhow::Result<String> {
@@ -92,6 +209,127 @@ impl Account {
     }
 }
 
+impl Masterkey {
+    pub fn new(key: Vec<u8>) -> anyhow::Result<Self> {
+        if key.len() != 32 {
+            anyhow::bail!("expected a 32 byte key, got {} bytes", key.len());
+        }
+        Ok(Self {
+            id: hex::encode(&hash(&key).to_bytes()[..8]),
+            key,
+        })
+    }
+
+    /// Encrypts the keypair, returns the ciphertext and its nonce
+    pub fn seal_keypair(&self, keypair: &Keypair) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        seal(keypair.to_base58_string().as_bytes(), &self.key)
+    }
+}
+
+impl Keyring {
+    pub fn new(masterkeys: Vec<Masterkey>) -> anyhow::Result<Self> {
+        if masterkeys.is_empty() {
+            anyhow::bail!("at least one master key is required");
+        }
+        Ok(Self(masterkeys))
+    }
+
+    /// The key new wallets are sealed with
+    pub fn current(&self) -> &Masterkey {
+        &self.0[0]
+    }
+
+    /// Decrypts a keypair with the key of `key_id`. Wallets sealed before keys had ids
+    /// have none, every key is tried on them.
+    pub fn unseal_keypair(
+        &self,
+        key_id: Option<&str>,
+        ciphertext: &[u8],
+        nonce: &[u8],
+    ) -> anyhow::Result<(Keypair, &Masterkey)> {
+        let candidates: Vec<&Masterkey> = match key_id {
+            Some(key_id) => self.0.iter().filter(|key| key.id == key_id).collect(),
+            None => self.0.iter().collect(),
+        };
+        if candidates.is_empty() {
+            anyhow::bail!(
+                "master key {} is not configured",
+                key_id.unwrap_or_default()
+            );
+        }
+        for masterkey in candidates {
+            if let Ok(secret) = unseal(ciphertext, &masterkey.key, nonce) {
+                let keypair = Keypair::from_bytes(&bs58::decode(&secret).into_vec()?)?;
+                return Ok((keypair, masterkey));
+            }
+        }
+        anyhow::bail!("none of the master keys decrypts the wallet")
+    }
+}
+
+/// Re-encrypts every wallet under `new`, in one transaction. Each row is decrypted with
+/// `masterkeys` and sealed again, and the rows as written are read back and decrypted with
+/// `new` alone before committing, so a failure leaves every wallet as it was.
+/// Returns the number of wallets rotated.
+pub async fn rotate_masterkey(
+    db: &PgPool,
+    masterkeys: &Keyring,
+    new: &Masterkey,
+) -> anyhow::Result<usize> {
+    // wallets created while `new` already sealed them decrypt as well
+    let mut known = vec![new.clone()];
+    known.extend(masterkeys.0.iter().cloned());
+    let known = Keyring::new(known)?;
+
+    let mut tx = db.begin().await?;
+    let rows =
+        sqlx::query!("SELECT id, ciphertext, nonce, key_id FROM accounts ORDER BY id FOR UPDATE")
+            .fetch_all(&mut *tx)
+            .await?;
+
+    let mut pubkeys = Vec::with_capacity(rows.len());
+    for row in &rows {
+        let (keypair, _) = known
+            .unseal_keypair(row.key_id.as_deref(), &row.ciphertext, &row.nonce)
+            .map_err(|e| e.context(format!("unable to decrypt account {}", row.id)))?;
+        let (ciphertext, nonce) = new.seal_keypair(&keypair)?;
+        sqlx::query!(
+            "UPDATE accounts SET ciphertext = $2, nonce = $3, key_id = $4 WHERE id = $1",
+            row.id,
+            ciphertext,
+            nonce,
+            new.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        pubkeys.push((row.id, keypair.pubkey()));
+    }
+
+    let written = sqlx::query!("SELECT id, ciphertext, nonce, key_id FROM accounts ORDER BY id")
+        .fetch_all(&mut *tx)
+        .await?;
+    let only_new = Keyring::new(vec![new.clone()])?;
+    if written.len() != pubkeys.len() {
+        anyhow::bail!("accounts changed during the rotation");
+    }
+    for (row, (expected_id, pubkey)) in written.iter().zip(&pubkeys) {
+        let (keypair, _) = only_new
+            .unseal_keypair(row.key_id.as_deref(), &row.ciphertext, &row.nonce)
+            .map_err(|e| {
+                e.context(format!(
+                    "account {} does not decrypt after rotation",
+                    row.id
+                ))
+            })?;
+        if row.id != *expected_id || keypair.pubkey() != *pubkey {
+            anyhow::bail!("account {} changed its keypair during the rotation", row.id);
+        }
+    }
+
+    tx.commit().await?;
+    Ok(pubkeys.len())
+}
+
 fn seal(secret: &[u8], key: &[u8]) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
     // The encryption key can be generated randomly:
     // let key = Aes256Gcm::generate_key(OsRng);
@@ -0,0 +1,61 @@
+use solana_sdk::pubkey::Pubkey;
+use sqlx::{PgExecutor, PgPool};
+
+/// How far a backfill of a pool over a time range got. The history is walked from the
+/// newest transaction backwards, `before_signature` is the oldest signature already processed.
+#[derive(Debug, Clone)]
+pub struct BackfillCursor {
+    pub pool: String,
+    /// Unix seconds, `to_time` excluded
+    pub from_time: i64,
+    pub to_time: i64,
+    pub before_signature: Option<String>,
+    pub done: bool,
+}
+
+impl BackfillCursor {
+    /// The cursor of a previous run over the same range, or a fresh one
+    pub async fn find_or_create(
+        db: &PgPool,
+        pool: &Pubkey,
+        from_time: i64,
+        to_time: i64,
+    ) -> anyhow::Result<Self> {
+        let cursor = sqlx::query_as!(
+            Self,
+            "INSERT INTO backfill_cursors (pool, from_time, to_time) VALUES ($1, $2, $3)
+            ON CONFLICT (pool, from_time, to_time) DO UPDATE SET pool = excluded.pool
+            RETURNING pool, from_time, to_time, before_signature, done",
+            pool.to_string(),
+            from_time,
+            to_time
+        )
+        .fetch_one(db)
+        .await?;
+        Ok(cursor)
+    }
+
+    /// Moves the cursor past `before`, meant to run in the transaction that writes the
+    /// ticks found up to there. `None` keeps a run that found no history at all at the start.
+    pub async fn advance(
+        &mut self,
+        db: impl PgExecutor<'_>,
+        before: Option<String>,
+        done: bool,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE backfill_cursors SET before_signature = $4, done = $5
+            WHERE pool = $1 AND from_time = $2 AND to_time = $3",
+            self.pool,
+            self.from_time,
+            self.to_time,
+            before,
+            done
+        )
+        .execute(db)
+        .await?;
+        self.before_signature = before;
+        self.done = done;
+        Ok(())
+    }
+}
@@ -1 +1,3 @@
 pub mod account;
+pub mod backfill_cursor;
+pub mod price_tick;
@@ -0,0 +1,193 @@
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::{PgExecutor, PgPool};
+use tokio::sync::mpsc;
+
+use crate::solclient::pool::{PriceTick, TickSource};
+
+/// Upper bound of ticks written with a single statement
+const MAX_BATCH: usize = 500;
+
+/// Bucket size of a candle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Resolution {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl Resolution {
+    pub fn secs(&self) -> i64 {
+        match self {
+            Self::OneMinute => 60,
+            Self::FiveMinutes => 5 * 60,
+            Self::OneHour => 60 * 60,
+            Self::OneDay => 24 * 60 * 60,
+        }
+    }
+
+    /// Start of the candle `timestamp` (unix seconds) falls into
+    pub fn bucket(&self, timestamp: i64) -> i64 {
+        timestamp.div_euclid(self.secs()) * self.secs()
+    }
+}
+
+/// Prices of mint0 in mint1 over one bucket, `volume` in mint0
+#[derive(Debug, Clone, Serialize)]
+pub struct Candle {
+    /// Unix time in seconds the bucket starts at
+    pub open_time: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    /// Price updates the candle is made of
+    pub ticks: i64,
+}
+
+impl Candle {
+    /// The last `limit` candles of `pool` between `from` and `to` (unix seconds, `to`
+    /// excluded), oldest first. Buckets without a tick are left out.
+    pub async fn find(
+        db: &PgPool,
+        pool: &Pubkey,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+        limit: i64,
+    ) -> anyhow::Result<Vec<Self>> {
+        // every group has a tick, none of the aggregates is null
+        let mut candles = sqlx::query_as!(
+            Self,
+            r#"SELECT observed_at / 1000 / $2 * $2 AS "open_time!",
+                (array_agg(price ORDER BY observed_at, slot, id))[1] AS "open!",
+                max(price) AS "high!",
+                min(price) AS "low!",
+                (array_agg(price ORDER BY observed_at DESC, slot DESC, id DESC))[1] AS "close!",
+                sum(volume0) AS "volume!",
+                count(*) AS "ticks!"
+            FROM price_ticks
+            WHERE pool = $1 AND observed_at >= $3::bigint * 1000 AND observed_at < $4::bigint * 1000
+            GROUP BY 1
+            ORDER BY 1 DESC
+            LIMIT $5"#,
+            pool.to_string(),
+            resolution.secs(),
+            from,
+            to,
+            limit
+        )
+        .fetch_all(db)
+        .await?;
+
+        candles.reverse();
+        Ok(candles)
+    }
+}
+
+/// Inserts `ticks` with a single statement, skipping swap events that were written
+/// before. Returns the ticks actually inserted.
+pub async fn save_all(db: impl PgExecutor<'_>, ticks: &[PriceTick]) -> anyhow::Result<u64> {
+    let mut pools = Vec::with_capacity(ticks.len());
+    let mut slots = Vec::with_capacity(ticks.len());
+    let mut observed_ats = Vec::with_capacity(ticks.len());
+    let mut sqrt_prices_x64 = Vec::with_capacity(ticks.len());
+    let mut tick_indexes = Vec::with_capacity(ticks.len());
+    let mut liquidities = Vec::with_capacity(ticks.len());
+    let mut prices = Vec::with_capacity(ticks.len());
+    let mut volumes0 = Vec::with_capacity(ticks.len());
+    let mut sources = Vec::with_capacity(ticks.len());
+    let mut signatures = Vec::with_capacity(ticks.len());
+    let mut event_indexes = Vec::with_capacity(ticks.len());
+    for tick in ticks {
+        pools.push(tick.pool.to_string());
+        slots.push(tick.slot as i64);
+        observed_ats.push(tick.observed_at);
+        // u128 has no Postgres type, numeric takes its decimal string
+        sqrt_prices_x64.push(tick.sqrt_price_x64.to_string());
+        tick_indexes.push(tick.tick);
+        liquidities.push(tick.liquidity.to_string());
+        prices.push(tick.price);
+        volumes0.push(tick.volume0);
+        sources.push(tick.source.name().to_string());
+        match tick.source {
+            TickSource::Live => {
+                signatures.push(None);
+                event_indexes.push(None);
+            }
+            TickSource::Backfill {
+                signature,
+                event_index,
+            } => {
+                signatures.push(Some(signature.to_string()));
+                event_indexes.push(Some(event_index as i32));
+            }
+        }
+    }
+
+    // the type check expects arrays without nulls, the nullable columns opt out of it
+    let inserted = sqlx::query!(
+        "INSERT INTO price_ticks (pool, slot, observed_at, sqrt_price_x64, tick, liquidity,
+            price, volume0, source, signature, event_index)
+        SELECT pool, slot, observed_at, sqrt_price_x64::numeric, tick, liquidity::numeric,
+            price, volume0, source, signature, event_index
+        FROM UNNEST($1::text[], $2::bigint[], $3::bigint[], $4::text[], $5::integer[],
+            $6::text[], $7::double precision[], $8::double precision[], $9::text[],
+            $10::text[], $11::integer[])
+            AS t (pool, slot, observed_at, sqrt_price_x64, tick, liquidity, price, volume0,
+                source, signature, event_index)
+        ON CONFLICT DO NOTHING",
+        &pools,
+        &slots,
+        &observed_ats,
+        &sqrt_prices_x64,
+        &tick_indexes,
+        &liquidities,
+        &prices,
+        &volumes0,
+        &sources,
+        &signatures as _,
+        &event_indexes as _
+    )
+    .execute(db)
+    .await?;
+    Ok(inserted.rows_affected())
+}
+
+/// Deletes the live ticks of `pool` from `first_slot` to `last_slot`, both included, the
+/// swap events of those slots replace them
+pub async fn delete_live(
+    db: impl PgExecutor<'_>,
+    pool: &Pubkey,
+    first_slot: u64,
+    last_slot: u64,
+) -> anyhow::Result<u64> {
+    let deleted = sqlx::query!(
+        "DELETE FROM price_ticks
+        WHERE pool = $1 AND source = 'live' AND slot BETWEEN $2 AND $3",
+        pool.to_string(),
+        first_slot as i64,
+        last_slot as i64
+    )
+    .execute(db)
+    .await?;
+    Ok(deleted.rows_affected())
+}
+
+/// Writes the ticks the listeners report for as long as the server runs, whatever queued
+/// up while a write was in flight goes into the next one
+pub async fn record(db: PgPool, mut ticks: mpsc::UnboundedReceiver<PriceTick>) {
+    let mut batch = Vec::with_capacity(MAX_BATCH);
+    while ticks.recv_many(&mut batch, MAX_BATCH).await > 0 {
+        if let Err(e) = save_all(&db, &batch).await {
+            log::warn!("Dropping {} price ticks: {}", batch.len(), e);
+        }
+        batch.clear();
+    }
+}
@@ -0,0 +1,14 @@
+use actix_web::{
+    get,
+    web::{self, Json},
+    Responder,
+};
+
+use crate::{extractors::account::AdminGuard, state::AppState};
+
+/// Pools currently listened to, with their subscriber counts, for admin accounts only
+#[get("/admin/subscriptions")]
+async fn list_subscriptions(state: web::Data<AppState>, _admin: AdminGuard) -> impl Responder {
+    let broadcast = state.broadcast.lock().await;
+    Json(broadcast.active_subscriptions())
+}
@@ -0,0 +1,123 @@
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    error::{ErrorBadGateway, ErrorBadRequest},
+    get,
+    web::{self, Json},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    extractors::account::AuthorizationGuard,
+    models::price_tick::{Candle, Resolution},
+    routes::lookup_error,
+    solclient::market::PoolKind,
+    state::AppState,
+};
+
+/// Upper bound of candles a single request may ask for
+const MAX_CANDLES: i64 = 1000;
+
+#[derive(Deserialize)]
+struct CandlesQuery {
+    /// The pool, or the pair in any order to take its deepest pool
+    pool: Option<String>,
+    mint0: Option<String>,
+    mint1: Option<String>,
+    fee_index: Option<u16>,
+    #[serde(default = "default_resolution")]
+    resolution: Resolution,
+    /// Unix seconds, defaults to `limit` candles before `to`
+    from: Option<i64>,
+    /// Unix seconds, excluded, defaults to now
+    to: Option<i64>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+impl CandlesQuery {
+    async fn pool(&self, state: &AppState) -> Result<Pubkey> {
+        match (&self.pool, &self.mint0, &self.mint1) {
+            (Some(pool), None, None) => Pubkey::from_str(pool).map_err(ErrorBadRequest),
+            (None, Some(mint0), Some(mint1)) => {
+                let mint0 = Pubkey::from_str(mint0).map_err(ErrorBadRequest)?;
+                let mint1 = Pubkey::from_str(mint1).map_err(ErrorBadRequest)?;
+                // only CLMM listeners record their prices
+                let pair = state
+                    .registry
+                    .deepest_pool(
+                        &state.rpc,
+                        mint0,
+                        mint1,
+                        Some(PoolKind::Clmm),
+                        self.fee_index,
+                    )
+                    .await
+                    .map_err(lookup_error)?;
+                Ok(pair.pool)
+            }
+            _ => Err(ErrorBadRequest(
+                "either pool or mint0 and mint1 have to be given",
+            )),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CandlesResponse {
+    pool: String,
+    resolution: Resolution,
+    candles: Vec<Candle>,
+}
+
+/// OHLCV candles of a CLMM pool built from the price ticks its listener recorded
+#[get("/candles")]
+async fn get_candles(
+    query: web::Query<CandlesQuery>,
+    state: web::Data<AppState>,
+    _auth: AuthorizationGuard,
+) -> Result<Json<CandlesResponse>> {
+    if !(1..=MAX_CANDLES).contains(&query.limit) {
+        return Err(ErrorBadRequest(format!(
+            "limit has to be between 1 and {}",
+            MAX_CANDLES
+        )));
+    }
+    let pool = query.pool(&state).await?;
+
+    let resolution = query.resolution;
+    let to = query.to.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs() as i64)
+    });
+    let from = query
+        .from
+        .unwrap_or_else(|| resolution.bucket(to) - (query.limit - 1) * resolution.secs());
+    if from >= to {
+        return Err(ErrorBadRequest("from has to be before to"));
+    }
+
+    let candles = Candle::find(&state.pool, &pool, resolution, from, to, query.limit)
+        .await
+        .map_err(ErrorBadGateway)?;
+
+    Ok(Json(CandlesResponse {
+        pool: pool.to_string(),
+        resolution,
+        candles,
+    }))
+}
+
+fn default_resolution() -> Resolution {
+    Resolution::OneMinute
+}
+
+fn default_limit() -> i64 {
+    300
+}
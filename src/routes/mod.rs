@@ -1,24 +1,365 @@
+pub mod admin;
+pub mod candles;
+pub mod pools;
 pub mod price_feed;
+pub mod route;
+pub mod split;
 pub mod swap;
+pub mod twap;
+pub mod wallets;
 pub mod ws;
 
-use actix_web::web;
+use std::{str::FromStr, sync::Arc};
+
+use actix_web::{error::ErrorBadRequest, web};
+use raydium_amm_v3::libraries::tick_math;
 use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use spl_token::{amount_to_ui_amount, amount_to_ui_amount_string, ui_amount_to_amount};
+
+use crate::{
+    constants::MAX_LADDER_RUNGS,
+    error::{AppError, OracleError, SwapError},
+    solclient::{
+        compute_budget::PriorityFee,
+        market::{PoolKind, SwapPool},
+        pool::Quote,
+        registry::PairPool,
+        utils,
+    },
+    state::AppState,
+};
 
 #[derive(Deserialize)]
 struct PriceFeedQuery {
+    /// The pair in any order, `zero_for_one` sells `mint0` as given here
     mint0: String,
     mint1: String,
-    fee_index: u16,
+    /// Only considers pools of that kind, all kinds by default
+    kind: Option<PoolKind>,
+    /// Picks the pool of that amm config instead of the deepest one of the pair
+    fee_index: Option<u16>,
+    /// Size of the quote, of the input mint for exact input and of the output mint otherwise
+    #[serde(default = "default_amount")]
+    amount: f64,
+    /// Comma separated sizes, e.g. `1,10,100,1000`, quoted together instead of `amount`,
+    /// [`MAX_LADDER_RUNGS`] at most
+    ladder: Option<String>,
+    /// `false` quotes mint1 -> mint0
+    #[serde(default = "default_zero_for_one")]
+    zero_for_one: bool,
+    /// `false` quotes how much input is needed to receive exactly `amount` output
+    #[serde(default = "default_is_base_input")]
+    is_base_input: bool,
+    /// Price of the pool's mint0 in its mint1 the quote may not move past
+    price_limit: Option<f64>,
+    /// Same as `price_limit` but expressed as a tick index
+    tick_limit: Option<i32>,
+    /// Comma separated windows in seconds whose TWAPs come along with every WebSocket
+    /// update, CLMM pools only
+    twap_windows: Option<String>,
+}
+
+impl PriceFeedQuery {
+    /// The pool the query is about
+    async fn resolve(&self, state: &AppState) -> actix_web::Result<PairPool> {
+        let mint0 = Pubkey::from_str(&self.mint0).map_err(ErrorBadRequest)?;
+        let mint1 = Pubkey::from_str(&self.mint1).map_err(ErrorBadRequest)?;
+        if mint0 == mint1 {
+            return Err(ErrorBadRequest("mint0 and mint1 have to differ"));
+        }
+
+        state
+            .registry
+            .deepest_pool(&state.rpc, mint0, mint1, self.kind, self.fee_index)
+            .await
+            .map_err(lookup_error)
+    }
+
+    /// TWAP windows to send along with the updates, none unless asked for
+    fn twap_windows(&self) -> anyhow::Result<Vec<u32>> {
+        self.twap_windows
+            .as_deref()
+            .map_or(Ok(Vec::new()), twap::parse_windows)
+    }
+
+    /// Sizes to quote, `ladder` takes precedence over `amount`. Each one has to be a
+    /// positive number.
+    fn amounts(&self) -> anyhow::Result<Vec<f64>> {
+        let amounts = match &self.ladder {
+            None => vec![self.amount],
+            Some(ladder) => ladder
+                .split(',')
+                .map(|size| {
+                    size.trim()
+                        .parse::<f64>()
+                        .map_err(|e| anyhow::anyhow!("invalid ladder size {}: {}", size, e))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        };
+        if amounts.len() > MAX_LADDER_RUNGS {
+            anyhow::bail!("ladder has more than {} sizes", MAX_LADDER_RUNGS);
+        }
+        if let Some(amount) = amounts
+            .iter()
+            .find(|amount| !amount.is_finite() || **amount <= 0.0)
+        {
+            anyhow::bail!("amount {} is not a positive number", amount);
+        }
+        Ok(amounts)
+    }
+}
+
+/// A single quote for `amount`, or one per size of the `ladder`
+#[derive(Serialize)]
+#[serde(untagged)]
+enum PriceFeed {
+    Single(PriceFeedResponse),
+    Ladder(Vec<PriceFeedResponse>),
+}
+
+impl PriceFeed {
+    async fn quote(
+        rpc: Arc<RpcClient>,
+        pool: &impl SwapPool,
+        query: &PriceFeedQuery,
+        amounts: &[f64],
+        zero_for_one: bool,
+        sqrt_price_limit_x64: Option<u128>,
+    ) -> anyhow::Result<Self> {
+        // one epoch for all sizes, the transfer fees cannot change in between
+        let epoch = rpc.get_epoch_info().await?.epoch;
+        let mut responses = Vec::with_capacity(amounts.len());
+        for amount in amounts {
+            responses.push(
+                PriceFeedResponse::quote(
+                    rpc.clone(),
+                    pool,
+                    epoch,
+                    *amount,
+                    zero_for_one,
+                    query.is_base_input,
+                    sqrt_price_limit_x64,
+                )
+                .await?,
+            );
+        }
+
+        Ok(match query.ladder {
+            Some(_) => Self::Ladder(responses),
+            None => Self::Single(responses.remove(0)),
+        })
+    }
+}
+
+/// Sent over the websocket when the pool feed drops and when it is back
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum StatusResponse {
+    Disconnected,
+    /// Updates of the last `gap_ms` were missed, the next quote is based on a fresh state
+    Resynced { gap_ms: u64 },
+}
+
+/// Sent over the websocket instead of a quote when quoting fails
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
 }
 
 #[derive(Serialize)]
 struct PriceFeedResponse {
+    /// Direction of the quote in pool order, mint0 -> mint1 when `true`. Prices are always
+    /// the ones of the pool's mint0 in its mint1.
+    zero_for_one: bool,
+    amount_in: String,
     amount_out: String,
+    /// `true` when the price limit was hit before the requested amount was filled
+    limited: bool,
+    /// Price of mint0 in mint1 before the swap
+    spot_price_before: f64,
+    /// Price of mint0 in mint1 after the swap
+    spot_price_after: f64,
+    /// Average price of mint0 in mint1 the swap fills at, fees included
+    execution_price: Option<f64>,
+    /// Distance between `execution_price` and `spot_price_before` in basis points
+    price_impact_bps: Option<f64>,
+    /// Trade fee paid to the liquidity providers, in the input mint
+    lp_fee: String,
+    ticks_crossed: u32,
+}
+
+impl PriceFeedResponse {
+    /// Quotes `amount` of input for exact input, or of output for exact output
+    async fn quote(
+        rpc: Arc<RpcClient>,
+        pool: &impl SwapPool,
+        epoch: u64,
+        amount: f64,
+        zero_for_one: bool,
+        is_base_input: bool,
+        sqrt_price_limit_x64: Option<u128>,
+    ) -> anyhow::Result<Self> {
+        // amount is denominated in the input mint for exact input, in the output mint otherwise
+        let (decimals_0, decimals_1) = pool.mint_decimals();
+        let decimals = if zero_for_one == is_base_input {
+            decimals_0
+        } else {
+            decimals_1
+        };
+        let quote = pool
+            .quote(
+                rpc,
+                epoch,
+                ui_amount_to_amount(amount, decimals),
+                sqrt_price_limit_x64,
+                zero_for_one,
+                is_base_input,
+            )
+            .await?;
+
+        Ok(Self::from_quote(pool, &quote, zero_for_one))
+    }
+
+    fn from_quote(pool: &impl SwapPool, quote: &Quote, zero_for_one: bool) -> Self {
+        let (decimals_0, decimals_1) = pool.mint_decimals();
+        let (decimals_in, decimals_out) = if zero_for_one {
+            (decimals_0, decimals_1)
+        } else {
+            (decimals_1, decimals_0)
+        };
+        let spot_price_before =
+            utils::sqrt_price_x64_to_price(quote.sqrt_price_before_x64, decimals_0, decimals_1);
+        let spot_price_after =
+            utils::sqrt_price_x64_to_price(quote.sqrt_price_after_x64, decimals_0, decimals_1);
+
+        let ui_amount_in = amount_to_ui_amount(quote.amount_in, decimals_in);
+        let ui_amount_out = amount_to_ui_amount(quote.amount_out, decimals_out);
+        // nothing is filled when the limit is right at the current price
+        let execution_price = match (quote.amount_in, quote.amount_out) {
+            (0, _) | (_, 0) => None,
+            _ if zero_for_one => Some(ui_amount_out / ui_amount_in),
+            _ => Some(ui_amount_in / ui_amount_out),
+        };
+        let price_impact_bps = execution_price
+            .map(|price| (price - spot_price_before).abs() / spot_price_before * 10_000f64);
+
+        Self {
+            zero_for_one,
+            amount_in: amount_to_ui_amount_string(quote.amount_in, decimals_in),
+            amount_out: amount_to_ui_amount_string(quote.amount_out, decimals_out),
+            limited: quote.limited,
+            spot_price_before,
+            spot_price_after,
+            execution_price,
+            price_impact_bps,
+            lp_fee: amount_to_ui_amount_string(quote.fee_amount, decimals_in),
+            ticks_crossed: quote.ticks_crossed,
+        }
+    }
+}
+
+/// A `price_limit`/`tick_limit` parameter, validated before any pool is at hand so that
+/// feeds fail before they start instead of on every update
+#[derive(Debug, Clone, Copy)]
+enum PriceLimit {
+    /// Of the pool's mint0 in its mint1, converted with the decimals of the pool
+    Price(f64),
+    /// Converted from a tick right away
+    SqrtPriceX64(u128),
+}
+
+impl PriceLimit {
+    /// The mutually exclusive `price_limit`/`tick_limit` parameters, none without either
+    fn parse(price_limit: Option<f64>, tick_limit: Option<i32>) -> anyhow::Result<Option<Self>> {
+        match (price_limit, tick_limit) {
+            (None, None) => Ok(None),
+            (Some(price), None) if price.is_finite() && price > 0f64 => {
+                Ok(Some(Self::Price(price)))
+            }
+            (Some(price), None) => Err(anyhow::anyhow!(
+                "price_limit {} has to be a positive number",
+                price
+            )),
+            (None, Some(tick)) => tick_math::get_sqrt_price_at_tick(tick)
+                .map(|sqrt_price_x64| Some(Self::SqrtPriceX64(sqrt_price_x64)))
+                .map_err(|e| anyhow::anyhow!("invalid tick_limit {}: {}", tick, e)),
+            (Some(_), Some(_)) => Err(anyhow::anyhow!(
+                "price_limit and tick_limit are mutually exclusive"
+            )),
+        }
+    }
+
+    /// The sqrt price limit understood by the swap math of `pool`
+    fn sqrt_price_x64(self, pool: &impl SwapPool) -> anyhow::Result<u128> {
+        match self {
+            Self::Price(price) => {
+                let (decimals_0, decimals_1) = pool.mint_decimals();
+                utils::price_to_sqrt_price_x64(price, decimals_0, decimals_1)
+                    .map_err(|e| anyhow::anyhow!("invalid price_limit: {}", e))
+            }
+            Self::SqrtPriceX64(sqrt_price_x64) => Ok(sqrt_price_x64),
+        }
+    }
+}
+
+/// The `priority_fee` of a swap body, recent fee percentiles go up to 100
+fn priority_fee(priority_fee: Option<PriorityFee>) -> anyhow::Result<Option<PriorityFee>> {
+    if let Some(PriorityFee::Recent(percentile @ 101..)) = priority_fee {
+        anyhow::bail!("priority fee percentile {} is above 100", percentile);
+    }
+    Ok(priority_fee)
+}
+
+/// Quotes the pool cannot fill, or price history it does not have, are the caller's
+/// problem, anything else is upstream
+fn quote_error(err: anyhow::Error) -> actix_web::Error {
+    if err.downcast_ref::<SwapError>().is_some() || err.downcast_ref::<OracleError>().is_some() {
+        actix_web::error::ErrorUnprocessableEntity(err.to_string())
+    } else {
+        actix_web::error::ErrorBadGateway(err.to_string())
+    }
+}
+
+/// Pairs without a pool or route are not found, failing to look them up is upstream
+fn lookup_error(err: anyhow::Error) -> actix_web::Error {
+    if let Some(AppError::PoolNotFound(..) | AppError::NoRoute(..)) = err.downcast_ref() {
+        actix_web::error::ErrorNotFound(err.to_string())
+    } else {
+        actix_web::error::ErrorBadGateway(err.to_string())
+    }
+}
+
+fn default_amount() -> f64 {
+    1f64
+}
+
+fn default_zero_for_one() -> bool {
+    true
+}
+
+fn default_is_base_input() -> bool {
+    true
 }
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
     cfg.service(price_feed::price_feed_pooling);
     cfg.service(swap::swap_route);
     cfg.service(ws::price_feed::stream_price_feed);
+    cfg.service(admin::list_subscriptions);
+    cfg.service(pools::list_pools);
+    cfg.service(pools::resolve_pair);
+    cfg.service(route::quote_route);
+    cfg.service(route::swap_along_route);
+    cfg.service(split::quote_split);
+    cfg.service(split::swap_split);
+    cfg.service(twap::get_twap);
+    cfg.service(candles::get_candles);
+    cfg.service(wallets::create_wallet);
+    cfg.service(wallets::list_wallets);
+    cfg.service(wallets::get_wallet);
+    cfg.service(wallets::expire_wallet);
+    cfg.service(wallets::extend_wallet);
+    cfg.service(wallets::sweep_wallet);
 }
@@ -0,0 +1,162 @@
+use std::str::FromStr;
+
+use actix_web::{
+    error::{ErrorBadRequest, ErrorServiceUnavailable},
+    get,
+    web::{self, Json},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    extractors::account::AuthorizationGuard,
+    routes::lookup_error,
+    solclient::{
+        market::PoolKind,
+        registry::{PairPool, PoolFilter, PoolInfo},
+    },
+    state::AppState,
+};
+
+#[derive(Deserialize)]
+struct PoolsQuery {
+    /// Pools with this mint on either side
+    mint: Option<String>,
+    /// Pools of the pair, `mint0` and `mint1` go together and may be given in any order
+    mint0: Option<String>,
+    mint1: Option<String>,
+    fee_index: Option<u16>,
+    /// Fee tier in hundredths of a basis point, e.g. `2500` for 0.25%
+    trade_fee_rate: Option<u32>,
+}
+
+impl PoolsQuery {
+    fn filter(&self) -> anyhow::Result<PoolFilter> {
+        let pair = match (&self.mint0, &self.mint1) {
+            (None, None) => None,
+            (Some(mint0), Some(mint1)) => {
+                Some((Pubkey::from_str(mint0)?, Pubkey::from_str(mint1)?))
+            }
+            _ => anyhow::bail!("mint0 and mint1 have to be given together"),
+        };
+
+        Ok(PoolFilter {
+            mint: self.mint.as_deref().map(Pubkey::from_str).transpose()?,
+            pair,
+            fee_index: self.fee_index,
+            trade_fee_rate: self.trade_fee_rate,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct PoolResponse {
+    pool: String,
+    mint0: String,
+    mint1: String,
+    amm_config: String,
+    fee_index: u16,
+    trade_fee_rate: u32,
+    tick_spacing: u16,
+    liquidity: String,
+    sqrt_price_x64: String,
+}
+
+impl From<PoolInfo> for PoolResponse {
+    fn from(pool: PoolInfo) -> Self {
+        Self {
+            pool: pool.pool.to_string(),
+            mint0: pool.mint0.to_string(),
+            mint1: pool.mint1.to_string(),
+            amm_config: pool.amm_config.to_string(),
+            fee_index: pool.fee_index,
+            trade_fee_rate: pool.trade_fee_rate,
+            tick_spacing: pool.tick_spacing,
+            liquidity: pool.liquidity.to_string(),
+            sqrt_price_x64: pool.sqrt_price_x64.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PairQuery {
+    /// The pair in any order
+    mint0: String,
+    mint1: String,
+    /// Pools of that kind only, all kinds by default
+    kind: Option<PoolKind>,
+    fee_index: Option<u16>,
+}
+
+#[derive(Serialize)]
+struct PairPoolResponse {
+    pool: String,
+    kind: PoolKind,
+    /// The mints in pool order
+    mint0: String,
+    mint1: String,
+    fee_index: u16,
+    trade_fee_rate: u32,
+    liquidity: String,
+    /// Whether selling the requested `mint0` for `mint1` is mint0 -> mint1 in pool order
+    zero_for_one: bool,
+}
+
+impl From<PairPool> for PairPoolResponse {
+    fn from(pool: PairPool) -> Self {
+        Self {
+            pool: pool.pool.to_string(),
+            kind: pool.kind,
+            mint0: pool.mint0.to_string(),
+            mint1: pool.mint1.to_string(),
+            fee_index: pool.fee_index,
+            trade_fee_rate: pool.trade_fee_rate,
+            liquidity: pool.liquidity.to_string(),
+            zero_for_one: pool.zero_for_one,
+        }
+    }
+}
+
+/// CLMM pools of the program, optionally narrowed down by mint, pair and fee tier
+#[get("/pools")]
+async fn list_pools(
+    state: web::Data<AppState>,
+    query: web::Query<PoolsQuery>,
+    _auth: AuthorizationGuard,
+) -> Result<Json<Vec<PoolResponse>>> {
+    let filter = query.filter().map_err(ErrorBadRequest)?;
+    if !state.registry.is_ready() {
+        return Err(ErrorServiceUnavailable("pool registry is still loading"));
+    }
+
+    let mut pools = state.registry.find(&filter).await;
+    pools.sort_by_key(|pool| (pool.mint0, pool.mint1, pool.fee_index));
+
+    Ok(Json(pools.into_iter().map(PoolResponse::from).collect()))
+}
+
+/// Every pool of a pair across the pool kinds, deepest first. CLMM pools are found by
+/// probing all known amm configs, the others through their program accounts.
+#[get("/pools/pair")]
+async fn resolve_pair(
+    state: web::Data<AppState>,
+    query: web::Query<PairQuery>,
+    _auth: AuthorizationGuard,
+) -> Result<Json<Vec<PairPoolResponse>>> {
+    let mint0 = Pubkey::from_str(&query.mint0).map_err(ErrorBadRequest)?;
+    let mint1 = Pubkey::from_str(&query.mint1).map_err(ErrorBadRequest)?;
+    if mint0 == mint1 {
+        return Err(ErrorBadRequest("mint0 and mint1 have to differ"));
+    }
+
+    let pools = state
+        .registry
+        .resolve_pools(&state.rpc, mint0, mint1, query.kind, query.fee_index)
+        .await
+        .map_err(lookup_error)?;
+
+    Ok(Json(
+        pools.into_iter().map(PairPoolResponse::from).collect(),
+    ))
+}
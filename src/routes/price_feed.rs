@@ -1,16 +1,14 @@
-use std::str::FromStr;
-
 use actix_web::{
     get,
     web::{self, Json},
-    Responder,
+    Result,
 };
-use solana_sdk::pubkey::Pubkey;
-use spl_token::{amount_to_ui_amount_string, ui_amount_to_amount};
+use tokio::sync::broadcast::error::RecvError;
 
 use crate::{
     extractors::account::AuthorizationGuard,
-    routes::{PriceFeedQuery, PriceFeedResponse},
+    routes::{quote_error, PriceFeed, PriceFeedQuery, PriceLimit},
+    solclient::pubsub::PoolBroadcast,
     state::AppState,
 };
 
@@ -19,32 +17,45 @@ async fn price_feed_pooling(
     query: web::Query<PriceFeedQuery>,
     state: web::Data<AppState>,
     _auth: AuthorizationGuard,
-) -> impl Responder {
-    let mint0 = Pubkey::from_str(&query.mint0).unwrap();
-    let mint1 = Pubkey::from_str(&query.mint1).unwrap();
+) -> Result<Json<PriceFeed>> {
+    let amounts = query.amounts().map_err(actix_web::error::ErrorBadRequest)?;
+    let price_limit = PriceLimit::parse(query.price_limit, query.tick_limit)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let pair = query.resolve(&state).await?;
 
     // Get current state and wait till update
-    let mut broadcast = state.broadcast.lock().await;
-    let mut subscription = broadcast
-        .subscribe(&state.raydium_program_id, mint0, mint1, query.fee_index)
-        .await
-        .unwrap();
-    drop(broadcast);
+    let subscription = PoolBroadcast::subscribe(&state.broadcast, &pair).await;
+    let mut subscription = subscription.map_err(actix_web::error::ErrorBadGateway)?;
+
+    let pool = loop {
+        match subscription.recv().await {
+            Ok(event) => {
+                if let Some(pool) = event.pool() {
+                    break pool;
+                }
+            }
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => {
+                return Err(actix_web::error::ErrorBadGateway("pool subscription closed"))
+            }
+        }
+    };
 
-    let pool = subscription.recv().await.unwrap();
+    let sqrt_price_limit_x64 = price_limit
+        .map(|limit| limit.sqrt_price_x64(&pool))
+        .transpose()
+        .map_err(actix_web::error::ErrorBadRequest)?;
 
-    let amount_out = pool
-        .quote(
-            state.rpc.clone(),
-            ui_amount_to_amount(1f64, pool.state.mint_decimals_0),
-            0,
-            true,
-            true,
-        )
-        .await
-        .unwrap();
+    let response = PriceFeed::quote(
+        state.rpc.clone(),
+        &pool,
+        &query,
+        &amounts,
+        pair.direction(query.zero_for_one),
+        sqrt_price_limit_x64,
+    )
+    .await
+    .map_err(quote_error)?;
 
-    Json(PriceFeedResponse {
-        amount_out: amount_to_ui_amount_string(amount_out, pool.state.mint_decimals_1),
-    })
+    Ok(Json(response))
 }
@@ -0,0 +1,234 @@
+use std::str::FromStr;
+
+use actix_web::{
+    error::{ErrorBadRequest, ErrorServiceUnavailable},
+    get, post,
+    web::{self, Json},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use spl_token::{amount_to_ui_amount_string, ui_amount_to_amount};
+
+use crate::{
+    constants::MAX_HOPS,
+    extractors::account::AuthorizationGuard,
+    routes::{
+        lookup_error, priority_fee, quote_error,
+        swap::{payer_keypair, swap_accounts},
+    },
+    solclient::{
+        compute_budget::PriorityFee,
+        registry::PoolFilter,
+        router::{RouteQuote, RouteSwapParams, Router},
+        utils::{self, TransactionResult},
+    },
+    state::AppState,
+};
+
+#[derive(Deserialize)]
+struct RouteQuery {
+    input_mint: String,
+    output_mint: String,
+    /// Exact input, in the input mint
+    #[serde(default = "crate::routes::default_amount")]
+    amount: f64,
+    /// Longest route to consider, 1 to 3 pools
+    #[serde(default = "default_max_hops")]
+    max_hops: usize,
+}
+
+#[derive(Deserialize)]
+struct RouteSwapBody {
+    input_mint: String,
+    output_mint: String,
+    /// Accounts of the payer to use instead of the derived ones, see `SwapAccounts::resolve`
+    input_account: Option<String>,
+    output_account: Option<String>,
+    amount: f64,
+    /// Applied to every leg of the route
+    slippage: f64,
+    #[serde(default = "default_max_hops")]
+    max_hops: usize,
+    /// See `PriorityFee`
+    priority_fee: Option<PriorityFee>,
+    simulate: bool,
+}
+
+#[derive(Serialize)]
+struct RouteResponse {
+    amount_in: String,
+    amount_out: String,
+    hops: Vec<HopResponse>,
+}
+
+#[derive(Serialize)]
+struct HopResponse {
+    pool: String,
+    fee_index: u16,
+    input_mint: String,
+    output_mint: String,
+    /// Direction of the hop in pool order
+    zero_for_one: bool,
+    amount_in: String,
+    amount_out: String,
+    /// Trade fee paid to the pool, in the input mint of the hop
+    lp_fee: String,
+    ticks_crossed: u32,
+}
+
+impl RouteResponse {
+    fn new(router: &Router, route: &RouteQuote) -> anyhow::Result<Self> {
+        let mut hops = Vec::with_capacity(route.legs.len());
+        for leg in &route.legs {
+            let state = router.pool(&leg.hop)?.state();
+            let (decimals_in, decimals_out) = if leg.hop.zero_for_one {
+                (state.mint_decimals_0, state.mint_decimals_1)
+            } else {
+                (state.mint_decimals_1, state.mint_decimals_0)
+            };
+            hops.push(HopResponse {
+                pool: leg.hop.pool.to_string(),
+                fee_index: leg.hop.fee_index,
+                input_mint: leg.hop.input_mint.to_string(),
+                output_mint: leg.hop.output_mint.to_string(),
+                zero_for_one: leg.hop.zero_for_one,
+                amount_in: amount_to_ui_amount_string(leg.quote.amount_in, decimals_in),
+                amount_out: amount_to_ui_amount_string(leg.quote.amount_out, decimals_out),
+                lp_fee: amount_to_ui_amount_string(leg.quote.fee_amount, decimals_in),
+                ticks_crossed: leg.quote.ticks_crossed,
+            });
+        }
+
+        Ok(Self {
+            amount_in: hops
+                .first()
+                .map(|hop| hop.amount_in.clone())
+                .unwrap_or_default(),
+            amount_out: hops
+                .last()
+                .map(|hop| hop.amount_out.clone())
+                .unwrap_or_default(),
+            hops,
+        })
+    }
+}
+
+/// Best route of up to `max_hops` pools through the pools known to the registry
+async fn best_route(
+    state: &AppState,
+    input_mint: &str,
+    output_mint: &str,
+    amount: f64,
+    max_hops: usize,
+) -> Result<(Router, RouteQuote)> {
+    let input_mint = Pubkey::from_str(input_mint).map_err(ErrorBadRequest)?;
+    let output_mint = Pubkey::from_str(output_mint).map_err(ErrorBadRequest)?;
+    if input_mint == output_mint {
+        return Err(ErrorBadRequest("input_mint and output_mint have to differ"));
+    }
+    if !(1..=MAX_HOPS).contains(&max_hops) {
+        return Err(ErrorBadRequest(format!(
+            "max_hops has to be between 1 and {}",
+            MAX_HOPS
+        )));
+    }
+    if !state.registry.is_ready() {
+        return Err(ErrorServiceUnavailable("pool registry is still loading"));
+    }
+
+    let decimals = utils::mint_decimals(&state.rpc, &[input_mint])
+        .await
+        .map_err(ErrorBadRequest)?[0];
+    let pools = state.registry.find(&PoolFilter::default()).await;
+    let mut router = Router::new(
+        state.rpc.clone(),
+        state.raydium_program_id,
+        state.max_tick_arrays,
+    );
+    let route = router
+        .best_route(
+            &pools,
+            input_mint,
+            output_mint,
+            max_hops,
+            ui_amount_to_amount(amount, decimals),
+        )
+        .await
+        .map_err(lookup_error)?;
+
+    Ok((router, route))
+}
+
+/// Quotes the route with the most output for an exact input, with its per hop breakdown
+#[get("/route")]
+async fn quote_route(
+    state: web::Data<AppState>,
+    query: web::Query<RouteQuery>,
+    _auth: AuthorizationGuard,
+) -> Result<Json<RouteResponse>> {
+    let (router, route) = best_route(
+        &state,
+        &query.input_mint,
+        &query.output_mint,
+        query.amount,
+        query.max_hops,
+    )
+    .await?;
+
+    let response = RouteResponse::new(&router, &route).map_err(quote_error)?;
+    Ok(Json(response))
+}
+
+/// Swaps along the best route in one transaction
+#[post("/swap/route")]
+async fn swap_along_route(
+    state: web::Data<AppState>,
+    AuthorizationGuard(account_id): AuthorizationGuard,
+    body: web::Json<RouteSwapBody>,
+) -> Result<Json<TransactionResult>> {
+    let payer = payer_keypair(&state, account_id).await?;
+    let priority_fee = priority_fee(body.priority_fee).map_err(ErrorBadRequest)?;
+    let input_mint = Pubkey::from_str(&body.input_mint).map_err(ErrorBadRequest)?;
+    let output_mint = Pubkey::from_str(&body.output_mint).map_err(ErrorBadRequest)?;
+    let accounts = swap_accounts(
+        &state,
+        &payer,
+        &input_mint,
+        &output_mint,
+        &body.input_account,
+        &body.output_account,
+    )
+    .await?;
+
+    let (router, route) = best_route(
+        &state,
+        &body.input_mint,
+        &body.output_mint,
+        body.amount,
+        body.max_hops,
+    )
+    .await?;
+
+    let res = router
+        .swap(
+            &route,
+            payer,
+            RouteSwapParams {
+                input_token: accounts.input_token,
+                output_token: accounts.output_token,
+                setup: &accounts.setup,
+                slippage: body.slippage,
+                priority_fee,
+            },
+            body.simulate,
+        )
+        .await
+        .map_err(quote_error)?;
+
+    Ok(Json(res))
+}
+
+fn default_max_hops() -> usize {
+    MAX_HOPS
+}
@@ -0,0 +1,252 @@
+use std::str::FromStr;
+
+use actix_web::{
+    error::{ErrorBadGateway, ErrorBadRequest},
+    get, post,
+    web::{self, Json},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use spl_token::{amount_to_ui_amount_string, ui_amount_to_amount};
+
+use crate::{
+    error::AppError,
+    extractors::account::AuthorizationGuard,
+    routes::{
+        lookup_error, priority_fee, quote_error,
+        swap::{payer_keypair, swap_accounts},
+    },
+    solclient::{
+        compute_budget::{self, PriorityFee},
+        pool::Pool,
+        registry::PairPool,
+        split::{self, SplitQuote},
+        utils::{self, TransactionResult},
+    },
+    state::AppState,
+};
+
+#[derive(Deserialize)]
+struct SplitQuery {
+    /// The pair in any order, `zero_for_one` sells `mint0` as given here
+    mint0: String,
+    mint1: String,
+    /// Exact input, in the input mint
+    #[serde(default = "crate::routes::default_amount")]
+    amount: f64,
+    #[serde(default = "crate::routes::default_zero_for_one")]
+    zero_for_one: bool,
+}
+
+#[derive(Deserialize)]
+struct SplitSwapBody {
+    mint0: String,
+    mint1: String,
+    /// Accounts of the payer to use instead of the derived ones, see `SwapAccounts::resolve`
+    input_account: Option<String>,
+    output_account: Option<String>,
+    amount: f64,
+    /// Applied to every leg
+    slippage: f64,
+    zero_for_one: bool,
+    /// See `PriorityFee`
+    priority_fee: Option<PriorityFee>,
+    simulate: bool,
+}
+
+#[derive(Serialize)]
+struct SplitResponse {
+    /// Direction of all legs in pool order
+    zero_for_one: bool,
+    amount_in: String,
+    amount_out: String,
+    legs: Vec<SplitLegResponse>,
+}
+
+#[derive(Serialize)]
+struct SplitLegResponse {
+    pool: String,
+    fee_index: u16,
+    trade_fee_rate: u32,
+    amount_in: String,
+    amount_out: String,
+    /// Trade fee paid to the pool, in the input mint
+    lp_fee: String,
+    ticks_crossed: u32,
+}
+
+impl SplitResponse {
+    fn new(split: &SplitQuote, decimals_in: u8, decimals_out: u8) -> Self {
+        Self {
+            zero_for_one: split.zero_for_one,
+            amount_in: amount_to_ui_amount_string(split.amount_in(), decimals_in),
+            amount_out: amount_to_ui_amount_string(split.amount_out(), decimals_out),
+            legs: split
+                .legs
+                .iter()
+                .map(|leg| SplitLegResponse {
+                    pool: leg.pool.pool.to_string(),
+                    fee_index: leg.pool.fee_index,
+                    trade_fee_rate: leg.pool.trade_fee_rate,
+                    amount_in: amount_to_ui_amount_string(leg.quote.amount_in, decimals_in),
+                    amount_out: amount_to_ui_amount_string(leg.quote.amount_out, decimals_out),
+                    lp_fee: amount_to_ui_amount_string(leg.quote.fee_amount, decimals_in),
+                    ticks_crossed: leg.quote.ticks_crossed,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A split order and what it needs to be priced and executed
+struct Split {
+    pools: Vec<(PairPool, Pool)>,
+    quote: SplitQuote,
+    decimals_in: u8,
+    decimals_out: u8,
+}
+
+/// Splits an exact input across every pool of the pair
+async fn split_order(
+    state: &AppState,
+    mint0: &str,
+    mint1: &str,
+    amount: f64,
+    zero_for_one: bool,
+) -> Result<Split> {
+    let mint0 = Pubkey::from_str(mint0).map_err(ErrorBadRequest)?;
+    let mint1 = Pubkey::from_str(mint1).map_err(ErrorBadRequest)?;
+    if mint0 == mint1 {
+        return Err(ErrorBadRequest("mint0 and mint1 have to differ"));
+    }
+
+    let pairs = state
+        .registry
+        .resolve_pair(&state.rpc, mint0, mint1, None)
+        .await
+        .map_err(lookup_error)?;
+    let Some(pair) = pairs.first() else {
+        return Err(lookup_error(AppError::PoolNotFound(mint0, mint1).into()));
+    };
+    let zero_for_one = pair.direction(zero_for_one);
+
+    // all pools of the pair share the mints, any one of them tells the decimals
+    let decimals = utils::mint_decimals(&state.rpc, &[pair.mint0, pair.mint1])
+        .await
+        .map_err(ErrorBadGateway)?;
+    let (decimals_in, decimals_out) = if zero_for_one {
+        (decimals[0], decimals[1])
+    } else {
+        (decimals[1], decimals[0])
+    };
+    let amount = ui_amount_to_amount(amount, decimals_in);
+
+    let epoch = state
+        .rpc
+        .get_epoch_info()
+        .await
+        .map_err(ErrorBadGateway)?
+        .epoch;
+    let pools = split::load_pools(
+        &state.rpc,
+        &state.raydium_program_id,
+        &pairs,
+        state.max_tick_arrays,
+        epoch,
+        amount,
+        zero_for_one,
+    )
+    .await;
+    let quote = split::split_order(&pools, epoch, amount, zero_for_one).map_err(quote_error)?;
+
+    Ok(Split {
+        pools,
+        quote,
+        decimals_in,
+        decimals_out,
+    })
+}
+
+/// Quotes an exact input split across all fee tiers of a pair for the most output
+#[get("/split")]
+async fn quote_split(
+    state: web::Data<AppState>,
+    query: web::Query<SplitQuery>,
+    _auth: AuthorizationGuard,
+) -> Result<Json<SplitResponse>> {
+    let split = split_order(
+        &state,
+        &query.mint0,
+        &query.mint1,
+        query.amount,
+        query.zero_for_one,
+    )
+    .await?;
+
+    Ok(Json(SplitResponse::new(
+        &split.quote,
+        split.decimals_in,
+        split.decimals_out,
+    )))
+}
+
+/// Swaps a split order in one transaction, one `swap_v2` per pool
+#[post("/swap/split")]
+async fn swap_split(
+    state: web::Data<AppState>,
+    AuthorizationGuard(account_id): AuthorizationGuard,
+    body: web::Json<SplitSwapBody>,
+) -> Result<Json<TransactionResult>> {
+    let payer = payer_keypair(&state, account_id).await?;
+    let priority_fee = priority_fee(body.priority_fee).map_err(ErrorBadRequest)?;
+
+    let split = split_order(
+        &state,
+        &body.mint0,
+        &body.mint1,
+        body.amount,
+        body.zero_for_one,
+    )
+    .await?;
+
+    let mint0 = Pubkey::from_str(&body.mint0).map_err(ErrorBadRequest)?;
+    let mint1 = Pubkey::from_str(&body.mint1).map_err(ErrorBadRequest)?;
+    let (input_mint, output_mint) = if body.zero_for_one {
+        (mint0, mint1)
+    } else {
+        (mint1, mint0)
+    };
+    let accounts = swap_accounts(
+        &state,
+        &payer,
+        &input_mint,
+        &output_mint,
+        &body.input_account,
+        &body.output_account,
+    )
+    .await?;
+
+    let mut instructions = accounts.setup;
+    instructions.extend(
+        split::swap_instructions(
+            &split.pools,
+            &split.quote,
+            &payer,
+            &state.raydium_program_id,
+            accounts.input_token,
+            accounts.output_token,
+            body.slippage,
+        )
+        .map_err(ErrorBadGateway)?,
+    );
+    let instructions =
+        compute_budget::with_compute_budget(&state.rpc, &payer, instructions, priority_fee)
+            .await
+            .map_err(ErrorBadGateway)?;
+    let res = utils::send_or_simulate(&state.rpc, &instructions, &payer, body.simulate)
+        .await
+        .map_err(quote_error)?;
+
+    Ok(Json(res))
+}
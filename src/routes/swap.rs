@@ -1,43 +1,73 @@
 use std::str::FromStr;
-use std::fs;
 
 use actix_web::{
     post,
     web::{self, Json},
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use solana_sdk::program_pack::Pack;
 use solana_sdk::pubkey::Pubkey;
-use spl_token::ui_amount_to_amount;
-use solana_sdk::signature::EncodableKey;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+use spl_token::{amount_to_ui_amount_string, native_mint, ui_amount_to_amount};
 
 use crate::{
-    constants::RAYDIUM_CLMM_PUBKEY,
+    error::AppError,
     extractors::account::AuthorizationGuard,
     models::account::Account,
+    routes::{lookup_error, priority_fee, quote_error, PriceLimit},
     solclient::{
-        pool::{LoadPoolAccounts, Pool},
+        compute_budget::PriorityFee,
+        market::{other_amount_threshold, AnyPool, PoolKind, SwapParams, SwapPool},
+        token_accounts::{NativeAccount, SwapAccounts, NATIVE_SOL},
         utils::TransactionResult,
     },
     state::AppState,
 };
 
-const DISCRIMINATOR_SIZE: usize = 8;
-const BUMP_SIZE: usize = 1;
-const PUBKEY_SIZE: usize = 32;
-
 #[derive(Deserialize)]
 struct SwapBody {
+    /// The pair in any order, `zero_for_one` sells `mint0` as given here. Native SOL is
+    /// `11111111111111111111111111111111` and swaps as WSOL the payer never holds.
     mint0: String,
     mint1: String,
-    input_account: String,
-    output_account: String,
+    /// Accounts of the payer to use instead of the derived ones, see `SwapAccounts::resolve`.
+    /// Not taken for native SOL.
+    input_account: Option<String>,
+    output_account: Option<String>,
     amount: f64,
     slippage: f64,
+    /// Only considers pools of that kind, all kinds by default
+    kind: Option<PoolKind>,
+    /// Picks the pool of that amm config instead of the deepest one of the pair
     fee_index: Option<u16>,
+    /// Picks this pool of the pair, of whatever kind, exclusive with `kind` and `fee_index`
     pool_state: Option<String>,
     simulate: bool,
     zero_for_one: bool,
+    /// `false` makes `amount` the exact output and `slippage` bound the input
+    #[serde(default = "crate::routes::default_is_base_input")]
+    is_base_input: bool,
+    /// Price of the pool's mint0 in its mint1 the swap may not move past, the swap is filled partially
+    /// when it is reached
+    price_limit: Option<f64>,
+    /// Same as `price_limit` but expressed as a tick index
+    tick_limit: Option<i32>,
     fee_bps: Option<u64>,
+    /// See `PriorityFee`
+    priority_fee: Option<PriorityFee>,
+}
+
+#[derive(Serialize)]
+struct SwapResponse {
+    /// What leaves the input account as quoted, less than `amount` when a price limit
+    /// stops the swap early
+    amount_in: String,
+    /// What lands in the output account as quoted, `slippage` bounds how far it may fall
+    amount_out: String,
+    /// `true` when the price limit stops the swap before `amount` is filled
+    limited: bool,
+    transaction: TransactionResult,
 }
 
 #[post("/swap")]
@@ -45,111 +75,228 @@ async fn swap_route(
     state: web::Data<AppState>,
     AuthorizationGuard(account_id): AuthorizationGuard,
     body: web::Json<SwapBody>,
-) -> actix_web::error::Result<Json<TransactionResult>> {
-    // let payer = Account::find_one(&state.pool, account_id, &state.masterkey)
-    // .await
-    // .map_err(actix_web::error::ErrorBadGateway)?
-    // .keypair;
-
-    let keypair_file = "/Users/kevin/.config/solana/id_localnet.json";
-    let payer = solana_sdk::signature::Keypair::read_from_file(keypair_file)
-        .map_err(|e| actix_web::error::ErrorBadRequest(format!("Failed to parse keypair: {}", e)))?;
-    println!("payer: {:?}", payer);
-
-    let mint0 = Pubkey::from_str(&body.mint0).unwrap();
-    let mint1 = Pubkey::from_str(&body.mint1).unwrap();
-
-    // 执行了raydium clmm合约地址
-    let raydium_amm_v3 = Pubkey::from_str(RAYDIUM_CLMM_PUBKEY).unwrap();
-
-    let (amm_config_key, pool_state) = match (body.fee_index, &body.pool_state) {
-        (Some(fee_index), None) => {
-            let (amm_config_key, _) = Pubkey::find_program_address(
-                &[
-                    raydium_amm_v3::states::AMM_CONFIG_SEED.as_bytes(),
-                    &fee_index.to_be_bytes(),
-                ],
-                &raydium_amm_v3,
-            );
-            
-            let (pool_state, _) = Pubkey::find_program_address(
-                &[
-                    raydium_amm_v3::states::POOL_SEED.as_bytes(),
-                    amm_config_key.as_ref(),
-                    mint0.as_ref(),
-                    mint1.as_ref(),
-                ],
-                &raydium_amm_v3,
-            );
-            
-            (amm_config_key, pool_state)
-        },
-        (None, Some(pool_state_str)) => {
-            let pool_state = Pubkey::from_str(pool_state_str)
-                .map_err(|e| actix_web::error::ErrorBadRequest(format!("Invalid pool state address: {}", e)))?;
-            println!("pool_state: {:?}", pool_state);
-            let pool_account = state.rpc.get_account(&pool_state).await
-        .map_err(|e| actix_web::error::ErrorBadGateway(format!("Failed to fetch pool account: {}", e)))?;
-    
-            println!("Data length: {}", pool_account.data.len());
-            
-            // 1. 计算 ammConfig 的偏移量和数据
-            let amm_config_offset = DISCRIMINATOR_SIZE + BUMP_SIZE;
-            let amm_config_data = &pool_account.data[amm_config_offset..amm_config_offset + PUBKEY_SIZE];
-            let amm_config_key = Pubkey::new(amm_config_data);
-            println!("amm_config_key: {:?}", amm_config_key);
-
-            (amm_config_key, pool_state)
-        },
-        _ => return Err(actix_web::error::ErrorBadRequest("Must provide either fee_index or pool_state")),
+) -> actix_web::error::Result<Json<SwapResponse>> {
+    let payer = payer_keypair(&state, account_id).await?;
+    let priority_fee =
+        priority_fee(body.priority_fee).map_err(actix_web::error::ErrorBadRequest)?;
+
+    let mint0 = Pubkey::from_str(&body.mint0).map_err(actix_web::error::ErrorBadRequest)?;
+    let mint1 = Pubkey::from_str(&body.mint1).map_err(actix_web::error::ErrorBadRequest)?;
+    let (native0, native1) = (mint0 == NATIVE_SOL, mint1 == NATIVE_SOL);
+    let wrapped = |mint: Pubkey| {
+        if mint == NATIVE_SOL {
+            native_mint::id()
+        } else {
+            mint
+        }
     };
+    let (mint0, mint1) = (wrapped(mint0), wrapped(mint1));
 
-    let (tick_array_bitmap_extension, _) = Pubkey::find_program_address(
-        &[
-            raydium_amm_v3::states::POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(),
-            pool_state.as_ref(),
-        ],
-        &raydium_amm_v3,
-    );
-    println!("tick_array_bitmap_extension: {:?}", tick_array_bitmap_extension);
-    let pool = Pool::load(
+    // mints may come in any order, the pool is the deepest of the pair unless pinned
+    let pair = match (body.kind, body.fee_index, &body.pool_state) {
+        (kind, fee_index, None) => state
+            .registry
+            .deepest_pool(&state.rpc, mint0, mint1, kind, fee_index)
+            .await
+            .map_err(lookup_error)?,
+        (None, None, Some(pool_state_str)) => {
+            let pool_state = Pubkey::from_str(pool_state_str).map_err(|e| {
+                actix_web::error::ErrorBadRequest(format!("Invalid pool state address: {}", e))
+            })?;
+            state
+                .registry
+                .pair_pool(&state.rpc, pool_state, mint0, mint1)
+                .await
+                .map_err(lookup_error)?
+        }
+        _ => {
+            return Err(actix_web::error::ErrorBadRequest(
+                "kind and fee_index are mutually exclusive with pool_state",
+            ))
+        }
+    };
+    let zero_for_one = pair.direction(body.zero_for_one);
+
+    let pool = AnyPool::load(
         state.rpc.clone(),
-        &state.raydium_program_id,
-        LoadPoolAccounts {
-            mint0,
-            mint1,
-            amm_config: amm_config_key,
-            pool_id: pool_state,
-            tickarray_bitmap_extension: tick_array_bitmap_extension,
-        },
-        body.zero_for_one,
+        &state.programs,
+        &pair,
+        state.max_tick_arrays,
     )
     .await
     .map_err(actix_web::error::ErrorBadGateway)?;
 
-    // let input_token = get_associated_token_address(&payer.pubkey(), &mint0);
-    // let output_token = get_associated_token_address(&payer.pubkey(), &mint1);
-    let input_token =
-        Pubkey::from_str(&body.input_account).map_err(actix_web::error::ErrorBadRequest)?;
-    let output_token =
-        Pubkey::from_str(&body.output_account).map_err(actix_web::error::ErrorBadRequest)?;
+    // amount is denominated in the input mint for exact input, in the output mint otherwise
+    let (decimals_0, decimals_1) = pool.mint_decimals();
+    let amount_decimals = if zero_for_one == body.is_base_input {
+        decimals_0
+    } else {
+        decimals_1
+    };
+    let amount = ui_amount_to_amount(body.amount, amount_decimals);
+
+    let sqrt_price_limit_x64 = PriceLimit::parse(body.price_limit, body.tick_limit)
+        .and_then(|limit| limit.map(|limit| limit.sqrt_price_x64(&pool)).transpose())
+        .map_err(actix_web::error::ErrorBadRequest)?;
+
+    let ((input_mint, native_input), (output_mint, native_output)) = if body.zero_for_one {
+        ((mint0, native0), (mint1, native1))
+    } else {
+        ((mint1, native1), (mint0, native0))
+    };
+    if (native_input && body.input_account.is_some())
+        || (native_output && body.output_account.is_some())
+    {
+        return Err(actix_web::error::ErrorBadRequest(
+            "native SOL is swapped through a temporary account, leave its account out",
+        ));
+    }
+
+    // the most the swap may spend is wrapped, whatever it leaves comes back with the close
+    let native_input = if native_input {
+        let lamports = if body.is_base_input {
+            amount
+        } else {
+            let epoch = state
+                .rpc
+                .get_epoch_info()
+                .await
+                .map_err(actix_web::error::ErrorBadGateway)?
+                .epoch;
+            let quote = pool
+                .quote(
+                    state.rpc.clone(),
+                    epoch,
+                    amount,
+                    sqrt_price_limit_x64,
+                    zero_for_one,
+                    false,
+                )
+                .await
+                .map_err(quote_error)?;
+            other_amount_threshold(&quote, body.slippage, false, body.fee_bps)
+        };
+        Some(native_account(&state, &payer, lamports).await?)
+    } else {
+        None
+    };
+    let native_output = if native_output {
+        Some(native_account(&state, &payer, 0).await?)
+    } else {
+        None
+    };
+
+    let address =
+        |native: &Option<NativeAccount>| native.as_ref().map(|native| native.address.to_string());
+    let mut accounts = swap_accounts(
+        &state,
+        &payer,
+        &input_mint,
+        &output_mint,
+        &address(&native_input).or(body.input_account.clone()),
+        &address(&native_output).or(body.output_account.clone()),
+    )
+    .await?;
+    for native in [native_input, native_output].into_iter().flatten() {
+        accounts.wrap(native);
+    }
 
-    let res = pool
+    let (quote, transaction) = pool
         .swap(
             state.rpc.clone(),
-            &state.raydium_program_id,
             payer,
-            input_token,
-            output_token,
-            ui_amount_to_amount(body.amount, pool.state.mint_decimals_0),
-            body.slippage,
-            true, // TODO: Would be nice to implement body.is_base_input,
+            SwapParams {
+                input_token: accounts.input_token,
+                output_token: accounts.output_token,
+                amount,
+                slippage: body.slippage,
+                is_base_input: body.is_base_input,
+                zero_for_one,
+                sqrt_price_limit_x64,
+                fee_bps: body.fee_bps,
+                priority_fee,
+            },
+            &accounts.setup,
+            &accounts.cleanup,
             body.simulate,
-            body.zero_for_one,
-            body.fee_bps,
         )
         .await
+        .map_err(quote_error)?;
+
+    let (decimals_in, decimals_out) = if zero_for_one {
+        (decimals_0, decimals_1)
+    } else {
+        (decimals_1, decimals_0)
+    };
+    Ok(Json(SwapResponse {
+        amount_in: amount_to_ui_amount_string(quote.amount_in, decimals_in),
+        amount_out: amount_to_ui_amount_string(quote.amount_out, decimals_out),
+        limited: quote.limited,
+        transaction,
+    }))
+}
+
+/// Keypair of the authenticated account, the swaps are signed with it
+pub(crate) async fn payer_keypair(
+    state: &AppState,
+    account_id: i32,
+) -> actix_web::error::Result<Keypair> {
+    let account = Account::find_one(&state.pool, account_id, &state.masterkeys)
+        .await
+        .map_err(|e| match e.downcast_ref::<AppError>() {
+            Some(AppError::AccountDoesntExist | AppError::AccountExpired(_)) => {
+                actix_web::error::ErrorUnauthorized(e)
+            }
+            _ => actix_web::error::ErrorInternalServerError(e),
+        })?;
+    Ok(account.keypair)
+}
+
+/// A temporary WSOL account of the payer funded with `lamports`
+async fn native_account(
+    state: &AppState,
+    payer: &Keypair,
+    lamports: u64,
+) -> actix_web::error::Result<NativeAccount> {
+    let rent = state
+        .rpc
+        .get_minimum_balance_for_rent_exemption(spl_token::state::Account::LEN)
+        .await
         .map_err(actix_web::error::ErrorBadGateway)?;
+    NativeAccount::new(
+        &payer.pubkey(),
+        &NativeAccount::unique_seed(),
+        rent,
+        lamports,
+    )
+    .map_err(actix_web::error::ErrorInternalServerError)
+}
 
-    Ok(Json(res))
+/// Token accounts of the payer for a swap of `input_mint` into `output_mint`, the given
+/// ones override the derived associated token accounts
+pub(crate) async fn swap_accounts(
+    state: &AppState,
+    payer: &Keypair,
+    input_mint: &Pubkey,
+    output_mint: &Pubkey,
+    input_account: &Option<String>,
+    output_account: &Option<String>,
+) -> actix_web::error::Result<SwapAccounts> {
+    let parse = |account: &Option<String>| {
+        account
+            .as_deref()
+            .map(Pubkey::from_str)
+            .transpose()
+            .map_err(actix_web::error::ErrorBadRequest)
+    };
+    SwapAccounts::resolve(
+        &state.rpc,
+        &payer.pubkey(),
+        input_mint,
+        output_mint,
+        parse(input_account)?,
+        parse(output_account)?,
+    )
+    .await
+    .map_err(actix_web::error::ErrorBadGateway)
 }
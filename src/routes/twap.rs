@@ -0,0 +1,161 @@
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    error::{ErrorBadGateway, ErrorBadRequest},
+    get,
+    web::{self, Json},
+    Result,
+};
+use raydium_amm_v3::states::PoolState;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    extractors::account::AuthorizationGuard,
+    routes::{lookup_error, quote_error},
+    solclient::{
+        market::{AnyPool, PoolKind},
+        oracle::{ObservationState, Twap},
+        pool::deserialize_anchor_account,
+        utils,
+    },
+    state::AppState,
+};
+
+#[derive(Deserialize)]
+struct TwapQuery {
+    /// The pair in any order
+    mint0: String,
+    mint1: String,
+    /// Picks the pool of that amm config instead of the deepest one of the pair
+    fee_index: Option<u16>,
+    /// Comma separated windows in seconds
+    #[serde(default = "default_windows")]
+    windows: String,
+}
+
+/// A time-weighted average price of the pool's mint0 in its mint1
+#[derive(Serialize)]
+pub(crate) struct TwapResponse {
+    window_secs: u32,
+    average_tick: i32,
+    price: f64,
+}
+
+impl TwapResponse {
+    fn new(twap: Twap, decimals_0: u8, decimals_1: u8) -> anyhow::Result<Self> {
+        Ok(Self {
+            window_secs: twap.window_secs,
+            average_tick: twap.average_tick,
+            price: twap.price(decimals_0, decimals_1)?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct OracleResponse {
+    pool: String,
+    tick_current: i32,
+    spot_price: f64,
+    /// Unix time of the oldest observation, no window may reach back further
+    oldest_observation: Option<u32>,
+    twaps: Vec<TwapResponse>,
+}
+
+/// Time-weighted average prices of a CLMM pool read from its observation account
+#[get("/twap")]
+async fn get_twap(
+    query: web::Query<TwapQuery>,
+    state: web::Data<AppState>,
+    _auth: AuthorizationGuard,
+) -> Result<Json<OracleResponse>> {
+    let mint0 = Pubkey::from_str(&query.mint0).map_err(ErrorBadRequest)?;
+    let mint1 = Pubkey::from_str(&query.mint1).map_err(ErrorBadRequest)?;
+    if mint0 == mint1 {
+        return Err(ErrorBadRequest("mint0 and mint1 have to differ"));
+    }
+    let windows = parse_windows(&query.windows).map_err(ErrorBadRequest)?;
+
+    // only CLMM pools keep observations of their tick
+    let pair = state
+        .registry
+        .deepest_pool(
+            &state.rpc,
+            mint0,
+            mint1,
+            Some(PoolKind::Clmm),
+            query.fee_index,
+        )
+        .await
+        .map_err(lookup_error)?;
+    let account = state
+        .rpc
+        .get_account(&pair.pool)
+        .await
+        .map_err(ErrorBadGateway)?;
+    let pool_state = deserialize_anchor_account::<PoolState>(&account).map_err(ErrorBadGateway)?;
+    let observation = ObservationState::load(&state.rpc, &pool_state.observation_key)
+        .await
+        .map_err(ErrorBadGateway)?;
+
+    let (decimals_0, decimals_1) = (pool_state.mint_decimals_0, pool_state.mint_decimals_1);
+    let now = unix_now();
+    let twaps = windows
+        .iter()
+        .map(|window| {
+            let twap = observation.twap(*window, now, pool_state.tick_current)?;
+            TwapResponse::new(twap, decimals_0, decimals_1)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(quote_error)?;
+
+    Ok(Json(OracleResponse {
+        pool: pair.pool.to_string(),
+        tick_current: pool_state.tick_current,
+        spot_price: utils::sqrt_price_x64_to_price(
+            pool_state.sqrt_price_x64,
+            decimals_0,
+            decimals_1,
+        ),
+        oldest_observation: observation.oldest_timestamp(),
+        twaps,
+    }))
+}
+
+/// TWAPs of a listened pool as of now, for the WebSocket updates
+pub(crate) fn twap_responses(pool: &AnyPool, windows: &[u32]) -> anyhow::Result<Vec<TwapResponse>> {
+    let AnyPool::Clmm(pool) = pool else {
+        anyhow::bail!("TWAPs are only available for CLMM pools");
+    };
+    let (decimals_0, decimals_1) = (pool.state().mint_decimals_0, pool.state().mint_decimals_1);
+    pool.twaps(windows, unix_now())?
+        .into_iter()
+        .map(|twap| TwapResponse::new(twap, decimals_0, decimals_1))
+        .collect()
+}
+
+/// Comma separated windows in seconds, e.g. `60,300,3600`
+pub(crate) fn parse_windows(windows: &str) -> anyhow::Result<Vec<u32>> {
+    windows
+        .split(',')
+        .map(|window| {
+            window
+                .trim()
+                .parse::<u32>()
+                .map_err(|e| anyhow::anyhow!("invalid window {}: {}", window, e))
+        })
+        .collect()
+}
+
+fn unix_now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as u32)
+}
+
+fn default_windows() -> String {
+    "60,300,1800".to_string()
+}
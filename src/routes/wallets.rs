@@ -0,0 +1,291 @@
+use std::str::FromStr;
+
+use actix_web::{
+    error::{
+        ErrorBadGateway, ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound,
+    },
+    get, post,
+    web::{self, Json},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{native_token::lamports_to_sol, pubkey::Pubkey};
+use spl_token::amount_to_ui_amount;
+
+use crate::{
+    constants::MAX_WALLET_DAYS,
+    error::AppError,
+    extractors::account::AuthorizationGuard,
+    models::account::Account,
+    routes::swap::payer_keypair,
+    solclient::wallet::{self, TokenBalance},
+    state::AppState,
+};
+
+#[derive(Deserialize)]
+struct DurationBody {
+    /// e.g. `30m` or `7d`
+    duration: String,
+}
+
+impl DurationBody {
+    /// The duration, positive and at most `MAX_WALLET_DAYS`
+    fn duration(&self) -> Result<chrono::Duration> {
+        let duration = humantime::parse_duration(&self.duration).map_err(ErrorBadRequest)?;
+        let duration = chrono::Duration::from_std(duration).map_err(ErrorBadRequest)?;
+        if duration <= chrono::Duration::zero() {
+            return Err(ErrorBadRequest("duration has to be positive"));
+        }
+        if duration > chrono::Duration::days(MAX_WALLET_DAYS) {
+            return Err(ErrorBadRequest(format!(
+                "duration is at most {} days",
+                MAX_WALLET_DAYS
+            )));
+        }
+        Ok(duration)
+    }
+}
+
+#[derive(Deserialize)]
+struct SweepBody {
+    /// Where every token and all SOL of the wallet go
+    destination: String,
+}
+
+#[derive(Serialize)]
+struct WalletResponse {
+    id: i32,
+    pubkey: String,
+    /// The account that created it, none for wallets of `new wallet`
+    owner_id: Option<i32>,
+    /// Unix seconds
+    die_at: i64,
+    expired: bool,
+}
+
+impl From<&Account> for WalletResponse {
+    fn from(account: &Account) -> Self {
+        Self {
+            id: account.id,
+            pubkey: account.pubkey().to_string(),
+            owner_id: account.owner_id,
+            die_at: account.die_at.timestamp(),
+            expired: account.is_expired(),
+        }
+    }
+}
+
+/// A wallet with a token valid until its `die_at`
+#[derive(Serialize)]
+struct WalletTokenResponse {
+    #[serde(flatten)]
+    wallet: WalletResponse,
+    access_token: String,
+}
+
+impl WalletTokenResponse {
+    fn new(account: &Account, state: &AppState) -> Result<Self> {
+        Ok(Self {
+            wallet: account.into(),
+            access_token: account
+                .access_token(state.jwt_secret.as_bytes())
+                .map_err(ErrorInternalServerError)?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct TokenBalanceResponse {
+    account: String,
+    mint: String,
+    program_id: String,
+    amount: String,
+    decimals: u8,
+    ui_amount: f64,
+}
+
+impl From<&TokenBalance> for TokenBalanceResponse {
+    fn from(balance: &TokenBalance) -> Self {
+        Self {
+            account: balance.account.to_string(),
+            mint: balance.mint.to_string(),
+            program_id: balance.program_id.to_string(),
+            amount: balance.amount.to_string(),
+            decimals: balance.decimals,
+            ui_amount: amount_to_ui_amount(balance.amount, balance.decimals),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BalancesResponse {
+    #[serde(flatten)]
+    wallet: WalletResponse,
+    lamports: u64,
+    sol: f64,
+    tokens: Vec<TokenBalanceResponse>,
+}
+
+#[derive(Serialize)]
+struct SweepResponse {
+    destination: String,
+    /// The token transactions in order, the SOL transfer last
+    signatures: Vec<String>,
+}
+
+/// Creates a wallet owned by the caller, who may manage it from then on
+#[post("/wallets")]
+async fn create_wallet(
+    state: web::Data<AppState>,
+    AuthorizationGuard(account_id): AuthorizationGuard,
+    body: web::Json<DurationBody>,
+) -> Result<Json<WalletTokenResponse>> {
+    // only a live account may create wallets
+    payer_keypair(&state, account_id).await?;
+    let duration = body.duration()?;
+
+    let mut account = Account::new_unique(state.masterkeys.current(), duration)
+        .map_err(ErrorInternalServerError)?;
+    account.owner_id = Some(account_id);
+    account
+        .save(&state.pool)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(Json(WalletTokenResponse::new(&account, &state)?))
+}
+
+/// The caller's wallet and the wallets it created, expired ones included
+#[get("/wallets")]
+async fn list_wallets(
+    state: web::Data<AppState>,
+    AuthorizationGuard(account_id): AuthorizationGuard,
+) -> Result<Json<Vec<WalletResponse>>> {
+    let accounts = Account::find_managed_by(&state.pool, account_id, &state.masterkeys)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(Json(accounts.iter().map(WalletResponse::from).collect()))
+}
+
+/// A wallet with its SOL and token balances
+#[get("/wallets/{id}")]
+async fn get_wallet(
+    state: web::Data<AppState>,
+    AuthorizationGuard(account_id): AuthorizationGuard,
+    id: web::Path<i32>,
+) -> Result<Json<BalancesResponse>> {
+    let account = managed_account(&state, account_id, *id).await?;
+    let pubkey = account.pubkey();
+
+    let lamports = state
+        .rpc
+        .get_balance(&pubkey)
+        .await
+        .map_err(ErrorBadGateway)?;
+    let tokens = wallet::token_balances(&state.rpc, &pubkey)
+        .await
+        .map_err(ErrorBadGateway)?;
+
+    Ok(Json(BalancesResponse {
+        wallet: (&account).into(),
+        lamports,
+        sol: lamports_to_sol(lamports),
+        tokens: tokens.iter().map(TokenBalanceResponse::from).collect(),
+    }))
+}
+
+/// Ends the wallet now, it can no longer swap. A wallet may also end itself.
+#[post("/wallets/{id}/expire")]
+async fn expire_wallet(
+    state: web::Data<AppState>,
+    AuthorizationGuard(account_id): AuthorizationGuard,
+    id: web::Path<i32>,
+) -> Result<Json<WalletResponse>> {
+    let mut account = managed_account(&state, account_id, *id).await?;
+    if !account.is_expired() {
+        account
+            .set_die_at(&state.pool, chrono::Utc::now())
+            .await
+            .map_err(ErrorInternalServerError)?;
+    }
+    Ok(Json((&account).into()))
+}
+
+/// Moves the end of the wallet by `duration`, counted from now for an expired one, to at
+/// most `MAX_WALLET_DAYS` from now. The old token still expires at the old `die_at`, a new
+/// one comes with the response.
+#[post("/wallets/{id}/extend")]
+async fn extend_wallet(
+    state: web::Data<AppState>,
+    AuthorizationGuard(account_id): AuthorizationGuard,
+    id: web::Path<i32>,
+    body: web::Json<DurationBody>,
+) -> Result<Json<WalletTokenResponse>> {
+    let duration = body.duration()?;
+    let mut account = owned_account(&state, account_id, *id).await?;
+
+    let die_at = account.extended_die_at(duration).ok_or_else(|| {
+        ErrorBadRequest(format!(
+            "a wallet lives at most {} days from now",
+            MAX_WALLET_DAYS
+        ))
+    })?;
+    account
+        .set_die_at(&state.pool, die_at)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(Json(WalletTokenResponse::new(&account, &state)?))
+}
+
+/// Moves every token and all SOL of the wallet to `destination`, expired wallets included.
+/// A wallet may also sweep itself.
+#[post("/wallets/{id}/sweep")]
+async fn sweep_wallet(
+    state: web::Data<AppState>,
+    AuthorizationGuard(account_id): AuthorizationGuard,
+    id: web::Path<i32>,
+    body: web::Json<SweepBody>,
+) -> Result<Json<SweepResponse>> {
+    let destination = Pubkey::from_str(&body.destination).map_err(ErrorBadRequest)?;
+    let account = managed_account(&state, account_id, *id).await?;
+    if destination == account.pubkey() {
+        return Err(ErrorBadRequest("destination is the wallet itself"));
+    }
+
+    let signatures = wallet::sweep(&state.rpc, &account.keypair, &destination)
+        .await
+        .map_err(ErrorBadGateway)?;
+
+    Ok(Json(SweepResponse {
+        destination: destination.to_string(),
+        signatures,
+    }))
+}
+
+/// Wallet `id`, as long as the live account `account_id` is allowed to manage it. Other
+/// accounts' wallets are reported as missing.
+async fn managed_account(state: &AppState, account_id: i32, id: i32) -> Result<Account> {
+    // the caller has to be alive, the token alone outlives an expired account
+    payer_keypair(state, account_id).await?;
+    let account = Account::find_any(&state.pool, id, &state.masterkeys)
+        .await
+        .map_err(|e| match e.downcast_ref::<AppError>() {
+            Some(AppError::AccountDoesntExist) => ErrorNotFound("wallet not found"),
+            _ => ErrorInternalServerError(e),
+        })?;
+    if !account.is_managed_by(account_id) {
+        return Err(ErrorNotFound("wallet not found"));
+    }
+    Ok(account)
+}
+
+/// Wallet `id` as long as the live account `account_id` created it, only the owner may
+/// extend a wallet
+async fn owned_account(state: &AppState, account_id: i32, id: i32) -> Result<Account> {
+    let account = managed_account(state, account_id, id).await?;
+    if !account.is_owned_by(account_id) {
+        return Err(ErrorForbidden("only the owner of a wallet may extend it"));
+    }
+    Ok(account)
+}
@@ -1 +1,2 @@
 pub mod price_feed;
+mod protocol;
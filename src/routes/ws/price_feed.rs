@@ -1,62 +1,53 @@
-use std::str::FromStr;
-
 use actix_web::{get, web, Error, HttpRequest, HttpResponse};
-use solana_sdk::pubkey::Pubkey;
-use spl_token::{amount_to_ui_amount_string, ui_amount_to_amount};
 
 use crate::{
     extractors::account::AuthorizationGuard,
-    routes::{PriceFeedQuery, PriceFeedResponse},
+    routes::{PriceFeedQuery, PriceLimit},
+    solclient::pubsub::PoolBroadcast,
     state::AppState,
 };
 
+use super::protocol::{self, Feed, Framing};
+
+/// Streams quotes of the pool in the query string, or of any number of pools managed
+/// through subscribe/unsubscribe messages when the query string is empty
 #[get("/ws/price-feed")]
 pub async fn stream_price_feed(
     req: HttpRequest,
-    query: web::Query<PriceFeedQuery>,
     state: web::Data<AppState>,
     _auth: AuthorizationGuard,
     stream: web::Payload,
 ) -> Result<HttpResponse, Error> {
-    let (res, mut session, _) = actix_ws::handle(&req, stream)?;
+    if req.query_string().is_empty() {
+        let (res, session, messages) = actix_ws::handle(&req, stream)?;
+        actix_web::rt::spawn(protocol::multiplex(session, messages, state));
+        return Ok(res);
+    }
 
-    let mint0 = Pubkey::from_str(&query.mint0).unwrap();
-    let mint1 = Pubkey::from_str(&query.mint1).unwrap();
+    let query = web::Query::<PriceFeedQuery>::from_query(req.query_string())?;
+    let amounts = query.amounts().map_err(actix_web::error::ErrorBadRequest)?;
+    query
+        .twap_windows()
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let price_limit = PriceLimit::parse(query.price_limit, query.tick_limit)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let pair = query.resolve(&state).await?;
+    let zero_for_one = pair.direction(query.zero_for_one);
 
-    let mut broadcast = state.broadcast.lock().await;
-    let mut subscription = broadcast
-        .subscribe(&state.raydium_program_id, mint0, mint1, query.fee_index)
-        .await
-        .unwrap();
-    drop(broadcast);
+    let (res, session, _) = actix_ws::handle(&req, stream)?;
 
-    tokio::spawn(async move {
-        while let Ok(pool) = subscription.recv().await {
-            let amount_out = pool
-                .quote(
-                    state.rpc.clone(),
-                    ui_amount_to_amount(1f64, pool.state.mint_decimals_0),
-                    0,
-                    true,
-                    true,
-                )
-                .await
-                .unwrap();
+    let subscription = PoolBroadcast::subscribe(&state.broadcast, &pair).await;
+    let subscription = subscription.map_err(actix_web::error::ErrorBadGateway)?;
 
-            session
-                .text(
-                    serde_json::to_string(&PriceFeedResponse {
-                        amount_out: amount_to_ui_amount_string(
-                            amount_out,
-                            pool.state.mint_decimals_1,
-                        ),
-                    })
-                    .unwrap(),
-                )
-                .await
-                .unwrap();
-        }
-    });
+    let feed = Feed {
+        pool_id: pair.pool,
+        zero_for_one,
+        query: query.into_inner(),
+        amounts,
+        price_limit,
+        framing: Framing::Untagged,
+    };
+    actix_web::rt::spawn(protocol::stream_quotes(session, state, subscription, feed));
     // respond immediately with response connected to WS session
     Ok(res)
 }
@@ -0,0 +1,360 @@
+use std::{collections::HashMap, str::FromStr};
+
+use actix_web::web;
+use actix_ws::{Closed, Message, MessageStream, Session};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use tokio::{
+    sync::broadcast,
+    task::{JoinHandle, JoinSet},
+};
+
+use crate::{
+    constants::MAX_SOCKET_SUBSCRIPTIONS,
+    routes::{
+        twap::{twap_responses, TwapResponse},
+        ErrorResponse, PriceFeed, PriceFeedQuery, PriceLimit, StatusResponse,
+    },
+    solclient::pubsub::{PoolBroadcast, PoolEvent},
+    state::AppState,
+};
+
+/// Frames a client sends
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Starts streaming quotes of a pool, takes the same fields as the `/price-feed` query.
+    /// Subscribing to a pool again replaces its quote parameters.
+    Subscribe(PriceFeedQuery),
+    /// Stops streaming quotes of a pool, `pool` is the address from the ack
+    Unsubscribe { pool: String },
+}
+
+/// Frames the server sends, every one of them names the pool it is about
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Ack {
+        action: Action,
+        pool: String,
+    },
+    Update {
+        pool: String,
+        quote: PriceFeed,
+        /// Only when the subscription asked for `twap_windows`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        twap: Option<Vec<TwapResponse>>,
+    },
+    /// The pool feed dropped, no updates until it is resynced
+    Disconnected {
+        pool: String,
+    },
+    /// Updates of the last `gap_ms` were missed, the next update is based on a fresh state
+    Resynced {
+        pool: String,
+        gap_ms: u64,
+    },
+    Error {
+        /// Missing when the frame could not be attributed to a pool, e.g. invalid JSON
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pool: Option<String>,
+        error: String,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    Subscribe,
+    Unsubscribe,
+}
+
+impl ServerMessage {
+    fn error(pool: Option<Pubkey>, error: impl ToString) -> Self {
+        Self::Error {
+            pool: pool.map(|pool| pool.to_string()),
+            error: error.to_string(),
+        }
+    }
+}
+
+/// How a feed writes its frames
+#[derive(Clone, Copy)]
+pub(super) enum Framing {
+    /// [`ServerMessage`]s, as on the multiplexed socket
+    Tagged,
+    /// What the single pool socket always sent: the bare quote (next to the TWAPs when
+    /// asked for), a [`StatusResponse`] or an [`ErrorResponse`]
+    Untagged,
+}
+
+/// An untagged update of a feed that asked for `twap_windows`
+#[derive(Serialize)]
+struct QuoteWithTwap<'a> {
+    quote: &'a PriceFeed,
+    twap: &'a [TwapResponse],
+}
+
+impl Framing {
+    fn frame(self, message: &ServerMessage) -> String {
+        let frame = match (self, message) {
+            (Self::Tagged, _) | (Self::Untagged, ServerMessage::Ack { .. }) => {
+                serde_json::to_string(message)
+            }
+            (Self::Untagged, ServerMessage::Update { quote, twap, .. }) => match twap {
+                Some(twap) => serde_json::to_string(&QuoteWithTwap { quote, twap }),
+                None => serde_json::to_string(quote),
+            },
+            (Self::Untagged, ServerMessage::Disconnected { .. }) => {
+                serde_json::to_string(&StatusResponse::Disconnected)
+            }
+            (Self::Untagged, ServerMessage::Resynced { gap_ms, .. }) => {
+                serde_json::to_string(&StatusResponse::Resynced { gap_ms: *gap_ms })
+            }
+            (Self::Untagged, ServerMessage::Error { error, .. }) => {
+                serde_json::to_string(&ErrorResponse {
+                    error: error.clone(),
+                })
+            }
+        };
+        frame.unwrap()
+    }
+}
+
+/// What a feed quotes on every update of its pool, validated when subscribing
+pub(super) struct Feed {
+    pub pool_id: Pubkey,
+    pub zero_for_one: bool,
+    pub query: PriceFeedQuery,
+    pub amounts: Vec<f64>,
+    pub price_limit: Option<PriceLimit>,
+    pub framing: Framing,
+}
+
+/// A subscription ready to stream, or the pool it failed for (if known) and why
+type Prepared = Result<(Feed, broadcast::Receiver<PoolEvent>), (Option<Pubkey>, String)>;
+
+/// Serves any number of pool feeds over one socket until the client goes away. Pools are
+/// resolved and loaded on tasks of their own, so the socket keeps being served meanwhile.
+pub async fn multiplex(
+    mut session: Session,
+    mut messages: MessageStream,
+    state: web::Data<AppState>,
+) {
+    let mut feeds: HashMap<Pubkey, JoinHandle<()>> = HashMap::new();
+    let mut pending: JoinSet<Prepared> = JoinSet::new();
+
+    loop {
+        let reply = tokio::select! {
+            message = messages.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    match serde_json::from_str::<ClientMessage>(&text) {
+                        Ok(ClientMessage::Subscribe(_))
+                            if pending.len() >= MAX_SOCKET_SUBSCRIPTIONS =>
+                        {
+                            ServerMessage::error(None, too_many_subscriptions())
+                        }
+                        Ok(ClientMessage::Subscribe(query)) => {
+                            pending.spawn(prepare(state.clone(), query));
+                            continue;
+                        }
+                        Ok(ClientMessage::Unsubscribe { pool }) => unsubscribe(&mut feeds, &pool),
+                        Err(e) => ServerMessage::error(None, e),
+                    }
+                }
+                Some(Ok(Message::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            Some(prepared) = pending.join_next() => match prepared {
+                Ok(Ok((feed, subscription))) => {
+                    start(&session, &state, &mut feeds, feed, subscription)
+                }
+                Ok(Err((pool, error))) => ServerMessage::error(pool, error),
+                // the task panicked
+                Err(e) => ServerMessage::error(None, e),
+            },
+        };
+
+        if send(&mut session, &reply).await.is_err() {
+            break;
+        }
+    }
+
+    pending.abort_all();
+    for (_, feed) in feeds {
+        feed.abort();
+    }
+    let _ = session.close(None).await;
+}
+
+fn too_many_subscriptions() -> String {
+    format!("at most {} pools per socket", MAX_SOCKET_SUBSCRIPTIONS)
+}
+
+/// Resolves the pool of `query`, validates the rest of it and subscribes to the pool
+async fn prepare(state: web::Data<AppState>, query: PriceFeedQuery) -> Prepared {
+    let pair = query
+        .resolve(&state)
+        .await
+        .map_err(|e| (None, e.to_string()))?;
+    let pool_id = pair.pool;
+    let failed = |e: anyhow::Error| (Some(pool_id), e.to_string());
+    let amounts = query.amounts().map_err(failed)?;
+    query.twap_windows().map_err(failed)?;
+    let price_limit = PriceLimit::parse(query.price_limit, query.tick_limit).map_err(failed)?;
+
+    let subscription = PoolBroadcast::subscribe(&state.broadcast, &pair)
+        .await
+        .map_err(failed)?;
+
+    let feed = Feed {
+        pool_id,
+        zero_for_one: pair.direction(query.zero_for_one),
+        query,
+        amounts,
+        price_limit,
+        framing: Framing::Tagged,
+    };
+    Ok((feed, subscription))
+}
+
+/// Starts streaming a prepared feed, replacing an earlier one of the same pool
+fn start(
+    session: &Session,
+    state: &web::Data<AppState>,
+    feeds: &mut HashMap<Pubkey, JoinHandle<()>>,
+    feed: Feed,
+    subscription: broadcast::Receiver<PoolEvent>,
+) -> ServerMessage {
+    let pool_id = feed.pool_id;
+    if !feeds.contains_key(&pool_id) && feeds.len() >= MAX_SOCKET_SUBSCRIPTIONS {
+        return ServerMessage::error(Some(pool_id), too_many_subscriptions());
+    }
+
+    let feed = tokio::spawn(stream_quotes(
+        session.clone(),
+        state.clone(),
+        subscription,
+        feed,
+    ));
+    if let Some(previous) = feeds.insert(pool_id, feed) {
+        previous.abort();
+    }
+
+    ServerMessage::Ack {
+        action: Action::Subscribe,
+        pool: pool_id.to_string(),
+    }
+}
+
+fn unsubscribe(feeds: &mut HashMap<Pubkey, JoinHandle<()>>, pool: &str) -> ServerMessage {
+    let pool_id = match Pubkey::from_str(pool) {
+        Ok(pool_id) => pool_id,
+        Err(e) => return ServerMessage::error(None, e),
+    };
+
+    match feeds.remove(&pool_id) {
+        Some(feed) => {
+            feed.abort();
+            ServerMessage::Ack {
+                action: Action::Unsubscribe,
+                pool: pool_id.to_string(),
+            }
+        }
+        None => ServerMessage::error(Some(pool_id), "not subscribed"),
+    }
+}
+
+/// Quotes every pool update until the client goes away, failed quotes are reported and
+/// the feed carries on
+pub(super) async fn stream_quotes(
+    mut session: Session,
+    state: web::Data<AppState>,
+    mut subscription: broadcast::Receiver<PoolEvent>,
+    feed: Feed,
+) {
+    let Feed {
+        pool_id,
+        zero_for_one,
+        query,
+        amounts,
+        price_limit,
+        framing,
+    } = feed;
+    // validated when subscribing
+    let twap_windows = query.twap_windows().unwrap_or_default();
+    loop {
+        let event = match subscription.recv().await {
+            Ok(event) => event,
+            // quoting fell behind, the next update is newer anyway
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let pool = match event {
+            PoolEvent::Update(pool) => pool,
+            PoolEvent::Disconnected => {
+                let status = ServerMessage::Disconnected {
+                    pool: pool_id.to_string(),
+                };
+                if session.text(framing.frame(&status)).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            PoolEvent::Resynced(pool, gap) => {
+                let status = ServerMessage::Resynced {
+                    pool: pool_id.to_string(),
+                    gap_ms: gap.as_millis() as u64,
+                };
+                if session.text(framing.frame(&status)).await.is_err() {
+                    break;
+                }
+                pool
+            }
+        };
+
+        let sqrt_price_limit_x64 = price_limit
+            .map(|limit| limit.sqrt_price_x64(&pool))
+            .transpose();
+        let quote = match sqrt_price_limit_x64 {
+            Ok(sqrt_price_limit_x64) => {
+                PriceFeed::quote(
+                    state.rpc.clone(),
+                    &pool,
+                    &query,
+                    &amounts,
+                    zero_for_one,
+                    sqrt_price_limit_x64,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        let twap = match twap_windows.as_slice() {
+            [] => Ok(None),
+            windows => twap_responses(&pool, windows).map(Some),
+        };
+        let reply = match (quote, twap) {
+            (Ok(quote), Ok(twap)) => ServerMessage::Update {
+                pool: pool_id.to_string(),
+                quote,
+                twap,
+            },
+            (Err(e), _) | (_, Err(e)) => ServerMessage::error(Some(pool_id), e),
+        };
+
+        if session.text(framing.frame(&reply)).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn send(session: &mut Session, message: &ServerMessage) -> Result<(), Closed> {
+    session.text(serde_json::to_string(message).unwrap()).await
+}
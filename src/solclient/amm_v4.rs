@@ -0,0 +1,418 @@
+use std::sync::Arc;
+
+use arrayref::array_ref;
+use futures::future::join_all;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::Keypair,
+    signer::Signer,
+};
+
+use crate::error::SwapError;
+
+use super::{
+    constant_product::{Fee, Reserves},
+    market::{filled_amount, other_amount_threshold, PoolKind, SwapParams, SwapPool},
+    pool::Quote,
+    registry::{program_accounts_config, PairPool},
+    utils::token_amount,
+};
+
+const AUTHORITY_SEED: &[u8] = b"amm authority";
+const SWAP_BASE_IN: u8 = 9;
+const SWAP_BASE_OUT: u8 = 11;
+
+/// The fields of a legacy `AmmInfo` the crate uses. Coin and pc are the pool's mint0 and
+/// mint1, they come in no particular order.
+#[derive(Debug, Clone, Copy)]
+pub struct AmmInfo {
+    pub status: u64,
+    pub coin_decimals: u64,
+    pub pc_decimals: u64,
+    pub swap_fee_numerator: u64,
+    pub swap_fee_denominator: u64,
+    pub need_take_pnl_coin: u64,
+    pub need_take_pnl_pc: u64,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub coin_vault_mint: Pubkey,
+    pub pc_vault_mint: Pubkey,
+    pub open_orders: Pubkey,
+    pub market: Pubkey,
+    pub market_program: Pubkey,
+    pub target_orders: Pubkey,
+}
+
+impl AmmInfo {
+    pub const LEN: usize = 752;
+    const COIN_MINT_OFFSET: usize = 400;
+    const PC_MINT_OFFSET: usize = 432;
+
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() != Self::LEN {
+            anyhow::bail!("not an AMM v4 pool");
+        }
+        let pubkey = |offset| Pubkey::new_from_array(*array_ref![data, offset, 32]);
+        let number = |offset| u64::from_le_bytes(*array_ref![data, offset, 8]);
+
+        Ok(Self {
+            status: number(0),
+            coin_decimals: number(32),
+            pc_decimals: number(40),
+            swap_fee_numerator: number(176),
+            swap_fee_denominator: number(184),
+            need_take_pnl_coin: number(192),
+            need_take_pnl_pc: number(200),
+            coin_vault: pubkey(336),
+            pc_vault: pubkey(368),
+            coin_vault_mint: pubkey(Self::COIN_MINT_OFFSET),
+            pc_vault_mint: pubkey(Self::PC_MINT_OFFSET),
+            open_orders: pubkey(496),
+            market: pubkey(528),
+            market_program: pubkey(560),
+            target_orders: pubkey(592),
+        })
+    }
+
+    /// Initialized, swap only and waiting for the open time are the states that swap
+    pub fn swappable(&self) -> bool {
+        matches!(self.status, 1 | 6 | 7)
+    }
+}
+
+/// OpenBook market accounts every AMM v4 swap passes along, whether or not the pool
+/// still places orders
+#[derive(Debug, Clone, Copy)]
+pub struct MarketAccounts {
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub event_queue: Pubkey,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub vault_signer: Pubkey,
+}
+
+impl MarketAccounts {
+    pub fn decode(market: &Pubkey, market_program: &Pubkey, data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < 349 {
+            anyhow::bail!("not an OpenBook market");
+        }
+        let pubkey = |offset| Pubkey::new_from_array(*array_ref![data, offset, 32]);
+        let nonce = u64::from_le_bytes(*array_ref![data, 45, 8]);
+        let vault_signer = Pubkey::create_program_address(
+            &[market.as_ref(), &nonce.to_le_bytes()],
+            market_program,
+        )?;
+
+        Ok(Self {
+            coin_vault: pubkey(117),
+            pc_vault: pubkey(165),
+            event_queue: pubkey(253),
+            bids: pubkey(285),
+            asks: pubkey(317),
+            vault_signer,
+        })
+    }
+}
+
+/// A Raydium AMM v4 pool with the balances of its vaults
+#[derive(Debug, Clone)]
+pub struct AmmV4Pool {
+    id: Pubkey,
+    program_id: Pubkey,
+    info: AmmInfo,
+    market: MarketAccounts,
+    vault0: u64,
+    vault1: u64,
+    mint0: Vec<u8>,
+    mint1: Vec<u8>,
+}
+
+impl AmmV4Pool {
+    pub async fn load(rpc: &RpcClient, program_id: &Pubkey, pool: Pubkey) -> anyhow::Result<Self> {
+        let account = rpc.get_account(&pool).await?;
+        Self::load_with_info(rpc, program_id, pool, AmmInfo::decode(&account.data)?).await
+    }
+
+    /// Loads what the pool prices and swaps with next to its already decoded state
+    async fn load_with_info(
+        rpc: &RpcClient,
+        program_id: &Pubkey,
+        pool: Pubkey,
+        info: AmmInfo,
+    ) -> anyhow::Result<Self> {
+        let keys = [
+            info.coin_vault,
+            info.pc_vault,
+            info.coin_vault_mint,
+            info.pc_vault_mint,
+            info.market,
+        ];
+        let accounts = rpc.get_multiple_accounts(&keys).await?;
+        let existing = |index: usize| {
+            accounts[index]
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("account {} does not exist", keys[index]))
+        };
+
+        let market =
+            MarketAccounts::decode(&info.market, &info.market_program, &existing(4)?.data)?;
+
+        Ok(Self {
+            id: pool,
+            program_id: *program_id,
+            info,
+            market,
+            vault0: token_amount(existing(0)?)?,
+            vault1: token_amount(existing(1)?)?,
+            mint0: existing(2)?.data.clone(),
+            mint1: existing(3)?.data.clone(),
+        })
+    }
+
+    /// AMM v4 pools of the pair, with either mint as the coin
+    pub async fn find_pairs(
+        rpc: &RpcClient,
+        program_id: &Pubkey,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+    ) -> anyhow::Result<Vec<PairPool>> {
+        let mut accounts = Vec::new();
+        for (coin, pc) in [(mint_a, mint_b), (mint_b, mint_a)] {
+            accounts.extend(
+                rpc.get_program_accounts_with_config(
+                    program_id,
+                    program_accounts_config(vec![
+                        RpcFilterType::DataSize(AmmInfo::LEN as u64),
+                        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                            AmmInfo::COIN_MINT_OFFSET,
+                            coin.as_ref(),
+                        )),
+                        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                            AmmInfo::PC_MINT_OFFSET,
+                            pc.as_ref(),
+                        )),
+                    ]),
+                )
+                .await?,
+            );
+        }
+
+        let pools = join_all(accounts.into_iter().map(|(pool, account)| async move {
+            let info = AmmInfo::decode(&account.data)?;
+            Self::load_with_info(rpc, program_id, pool, info).await
+        }))
+        .await;
+        let mut pairs = Vec::new();
+        for pool in pools {
+            let pool = pool?;
+            // disabled pools keep their reserves but cannot be swapped against
+            if pool.info.swappable() {
+                pairs.push(pool.pair(mint_a));
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// The pool as a pool of the pair requested as `mint_a` -> the other mint
+    pub fn pair(&self, mint_a: Pubkey) -> PairPool {
+        let fee = self.reserves().fee;
+        PairPool {
+            pool: self.id,
+            kind: PoolKind::AmmV4,
+            mint0: self.info.coin_vault_mint,
+            mint1: self.info.pc_vault_mint,
+            amm_config: Pubkey::default(),
+            fee_index: 0,
+            trade_fee_rate: (fee.numerator * 1_000_000)
+                .checked_div(fee.denominator)
+                .unwrap_or_default() as u32,
+            liquidity: self.reserves().liquidity(),
+            zero_for_one: mint_a == self.info.coin_vault_mint,
+        }
+    }
+
+    pub fn info(&self) -> &AmmInfo {
+        &self.info
+    }
+
+    /// Vault balances without the pnl the pool owes to the protocol. Orders on the
+    /// OpenBook market are not accounted for, pools stopped placing them.
+    pub fn reserves(&self) -> Reserves {
+        Reserves {
+            reserve0: self.vault0.saturating_sub(self.info.need_take_pnl_coin),
+            reserve1: self.vault1.saturating_sub(self.info.need_take_pnl_pc),
+            fee: Fee {
+                numerator: self.info.swap_fee_numerator,
+                denominator: self.info.swap_fee_denominator,
+            },
+        }
+    }
+
+    pub(super) fn watched_accounts(&self) -> Vec<Pubkey> {
+        vec![
+            self.id,
+            self.info.coin_vault,
+            self.info.pc_vault,
+            self.info.coin_vault_mint,
+            self.info.pc_vault_mint,
+        ]
+    }
+
+    pub(super) fn update(&mut self, key: &Pubkey, account: &Account) -> anyhow::Result<()> {
+        if *key == self.id {
+            self.info = AmmInfo::decode(&account.data)?;
+        } else if *key == self.info.coin_vault {
+            self.vault0 = token_amount(account)?;
+        } else if *key == self.info.pc_vault {
+            self.vault1 = token_amount(account)?;
+        } else if *key == self.info.coin_vault_mint {
+            self.mint0 = account.data.clone();
+        } else if *key == self.info.pc_vault_mint {
+            self.mint1 = account.data.clone();
+        }
+        Ok(())
+    }
+
+    /// `swap_base_in`, or `swap_base_out` without `is_base_input`. The direction follows
+    /// from the mints of the user's token accounts.
+    pub fn swap_instruction(
+        &self,
+        owner: Pubkey,
+        input_token: Pubkey,
+        output_token: Pubkey,
+        amount: u64,
+        other_amount_threshold: u64,
+        is_base_input: bool,
+    ) -> Instruction {
+        let (authority, _) = Pubkey::find_program_address(&[AUTHORITY_SEED], &self.program_id);
+        // base in takes the input and the minimum output, base out the maximum input and
+        // the output
+        let (tag, first, second) = if is_base_input {
+            (SWAP_BASE_IN, amount, other_amount_threshold)
+        } else {
+            (SWAP_BASE_OUT, other_amount_threshold, amount)
+        };
+        let mut data = vec![tag];
+        data.extend_from_slice(&first.to_le_bytes());
+        data.extend_from_slice(&second.to_le_bytes());
+
+        let info = &self.info;
+        let market = &self.market;
+        Instruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new(self.id, false),
+                AccountMeta::new_readonly(authority, false),
+                AccountMeta::new(info.open_orders, false),
+                AccountMeta::new(info.target_orders, false),
+                AccountMeta::new(info.coin_vault, false),
+                AccountMeta::new(info.pc_vault, false),
+                AccountMeta::new_readonly(info.market_program, false),
+                AccountMeta::new(info.market, false),
+                AccountMeta::new(market.bids, false),
+                AccountMeta::new(market.asks, false),
+                AccountMeta::new(market.event_queue, false),
+                AccountMeta::new(market.coin_vault, false),
+                AccountMeta::new(market.pc_vault, false),
+                AccountMeta::new_readonly(market.vault_signer, false),
+                AccountMeta::new(input_token, false),
+                AccountMeta::new(output_token, false),
+                AccountMeta::new_readonly(owner, true),
+            ],
+            data,
+        }
+    }
+}
+
+impl SwapPool for AmmV4Pool {
+    fn id(&self) -> Pubkey {
+        self.id
+    }
+
+    fn program_id(&self) -> Pubkey {
+        self.program_id
+    }
+
+    fn kind(&self) -> PoolKind {
+        PoolKind::AmmV4
+    }
+
+    fn mints(&self) -> (Pubkey, Pubkey) {
+        (self.info.coin_vault_mint, self.info.pc_vault_mint)
+    }
+
+    fn mint_decimals(&self) -> (u8, u8) {
+        (self.info.coin_decimals as u8, self.info.pc_decimals as u8)
+    }
+
+    fn sqrt_price_x64(&self) -> u128 {
+        self.reserves().sqrt_price_x64()
+    }
+
+    async fn quote(
+        &self,
+        _rpc: Arc<RpcClient>,
+        epoch: u64,
+        amount: u64,
+        sqrt_price_limit_x64: Option<u128>,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> anyhow::Result<Quote> {
+        self.reserves().quote(
+            &self.mint0,
+            &self.mint1,
+            epoch,
+            amount,
+            sqrt_price_limit_x64,
+            zero_for_one,
+            is_base_input,
+        )
+    }
+
+    async fn swap_instructions(
+        &self,
+        rpc: &RpcClient,
+        payer: &Keypair,
+        params: &SwapParams,
+    ) -> anyhow::Result<(Quote, Vec<Instruction>)> {
+        if !self.info.swappable() {
+            return Err(SwapError::InvalidInput("pool does not accept swaps").into());
+        }
+        let epoch = rpc.get_epoch_info().await?.epoch;
+        let quote = self.reserves().quote(
+            &self.mint0,
+            &self.mint1,
+            epoch,
+            params.amount,
+            params.sqrt_price_limit_x64,
+            params.zero_for_one,
+            params.is_base_input,
+        )?;
+
+        let amount = filled_amount(&quote, params);
+        let threshold = other_amount_threshold(
+            &quote,
+            params.slippage,
+            params.is_base_input,
+            params.fee_bps,
+        );
+        let instruction = self.swap_instruction(
+            payer.pubkey(),
+            params.input_token,
+            params.output_token,
+            amount,
+            threshold,
+            params.is_base_input,
+        );
+
+        Ok((quote, vec![instruction]))
+    }
+}
@@ -0,0 +1,124 @@
+use serde::Deserialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig, compute_budget::ComputeBudgetInstruction,
+    instruction::Instruction, pubkey::Pubkey, signature::Keypair, signer::Signer,
+    transaction::Transaction,
+};
+
+use super::utils;
+
+/// The most compute units a transaction may ask for
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+/// Accounts `getRecentPrioritizationFees` takes at most
+const MAX_FEE_ACCOUNTS: usize = 128;
+
+/// The compute unit price of a transaction, in micro-lamports, fixed or a percentile of
+/// recent fees. Transactions without one pay no priority fee.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriorityFee {
+    /// e.g. `{"fixed": 10000}`
+    Fixed(u64),
+    /// That percentile of the fees recently paid to write the accounts the transaction
+    /// writes, e.g. `{"recent": 75}`
+    Recent(u8),
+}
+
+/// `instructions` behind a compute unit limit sized by simulating them and, with a
+/// `priority_fee`, a compute unit price. The limit stays at the maximum when the
+/// simulation fails, so sending or simulating for real reports the failure.
+pub async fn with_compute_budget(
+    rpc: &RpcClient,
+    payer: &Keypair,
+    instructions: Vec<Instruction>,
+    priority_fee: Option<PriorityFee>,
+) -> anyhow::Result<Vec<Instruction>> {
+    let units_consumed = units_consumed(rpc, payer, &instructions).await?;
+    let mut budget = vec![ComputeBudgetInstruction::set_compute_unit_limit(
+        compute_unit_limit(units_consumed),
+    )];
+    let price = match priority_fee {
+        None => None,
+        Some(PriorityFee::Fixed(price)) => Some(price),
+        Some(PriorityFee::Recent(percentile)) => {
+            Some(recent_fee(rpc, &instructions, percentile).await?)
+        }
+    };
+    if let Some(price) = price {
+        budget.push(ComputeBudgetInstruction::set_compute_unit_price(price));
+    }
+
+    budget.extend(instructions);
+    Ok(budget)
+}
+
+/// Limit for a transaction that consumed `units_consumed` when simulated, with a fifth on
+/// top since the state may move until it lands
+pub fn compute_unit_limit(units_consumed: Option<u64>) -> u32 {
+    units_consumed.map_or(MAX_COMPUTE_UNIT_LIMIT, |units| {
+        (units + units / 5).min(MAX_COMPUTE_UNIT_LIMIT as u64) as u32
+    })
+}
+
+/// `percentile` of `fees`, nearest rank below, none paid for no fees
+pub fn fee_percentile(fees: &[u64], percentile: u8) -> anyhow::Result<u64> {
+    if percentile > 100 {
+        anyhow::bail!("fee percentile {} is above 100", percentile);
+    }
+    let mut fees = fees.to_vec();
+    fees.sort_unstable();
+    Ok(match fees.len() {
+        0 => 0,
+        len => fees[(len - 1) * percentile as usize / 100],
+    })
+}
+
+/// Compute units `instructions` consume under the maximum limit, none if they fail
+async fn units_consumed(
+    rpc: &RpcClient,
+    payer: &Keypair,
+    instructions: &[Instruction],
+) -> anyhow::Result<Option<u64>> {
+    let mut simulated = vec![ComputeBudgetInstruction::set_compute_unit_limit(
+        MAX_COMPUTE_UNIT_LIMIT,
+    )];
+    simulated.extend_from_slice(instructions);
+    let recent_hash = rpc.get_latest_blockhash().await?;
+    let txn = Transaction::new_signed_with_payer(
+        &simulated,
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_hash,
+    );
+    let ret = utils::simulate_transaction(rpc, &txn, false, CommitmentConfig::confirmed()).await?;
+    Ok(match ret.value.err {
+        Some(_) => None,
+        None => ret.value.units_consumed,
+    })
+}
+
+/// The `percentile` of the fees recent slots paid for the writable accounts of
+/// `instructions`
+async fn recent_fee(
+    rpc: &RpcClient,
+    instructions: &[Instruction],
+    percentile: u8,
+) -> anyhow::Result<u64> {
+    let mut writable: Vec<Pubkey> = vec![];
+    for meta in instructions.iter().flat_map(|ix| &ix.accounts) {
+        if meta.is_writable && !writable.contains(&meta.pubkey) {
+            writable.push(meta.pubkey);
+        }
+    }
+    writable.truncate(MAX_FEE_ACCOUNTS);
+
+    let fees: Vec<u64> = rpc
+        .get_recent_prioritization_fees(&writable)
+        .await?
+        .into_iter()
+        .map(|fee| fee.prioritization_fee)
+        .collect();
+    fee_percentile(&fees, percentile)
+}
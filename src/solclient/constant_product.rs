@@ -0,0 +1,310 @@
+use raydium_amm_v3::libraries::big_num::U256;
+use spl_token_2022::{extension::StateWithExtensionsMut, state::Mint};
+
+use crate::error::SwapError;
+
+use super::{
+    pool::{get_transfer_fee, Quote},
+    utils::get_transfer_inverse_fee,
+};
+
+/// Trade fee taken from the input, `numerator / denominator` of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fee {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl Fee {
+    /// Fee on `amount` of input, rounded up like the programs do
+    pub fn of(&self, amount: u64) -> u64 {
+        if self.denominator == 0 {
+            return 0;
+        }
+        ceil_div(
+            amount as u128 * self.numerator as u128,
+            self.denominator as u128,
+        ) as u64
+    }
+
+    /// Smallest input that still leaves `amount` once the fee is taken
+    pub fn gross_up(&self, amount: u64) -> Option<u64> {
+        if self.denominator == 0 {
+            return Some(amount);
+        }
+        let net_share = self
+            .denominator
+            .checked_sub(self.numerator)
+            .filter(|d| *d > 0)?;
+        u64::try_from(ceil_div(
+            amount as u128 * self.denominator as u128,
+            net_share as u128,
+        ))
+        .ok()
+    }
+}
+
+/// A swap against the curve, token-2022 transfer fees not applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurveSwap {
+    /// What the pool receives, trade fee included
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub fee_amount: u64,
+}
+
+/// Balances a constant product pool prices with, excluding whatever its vaults hold for
+/// somebody else, e.g. accrued protocol fees
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reserves {
+    pub reserve0: u64,
+    pub reserve1: u64,
+    pub fee: Fee,
+}
+
+impl Reserves {
+    /// Price of mint0 in mint1 as a Q64.64 sqrt price, the way CLMM pools report it
+    pub fn sqrt_price_x64(&self) -> u128 {
+        if self.reserve0 == 0 {
+            return 0;
+        }
+        ((U256::from(self.reserve1) << 128) / U256::from(self.reserve0))
+            .integer_sqrt()
+            .as_u128()
+    }
+
+    /// `sqrt(reserve0 * reserve1)`, which is what a CLMM pool calls liquidity, so depths
+    /// of both pool types compare
+    pub fn liquidity(&self) -> u128 {
+        U256::from(self.reserve0 as u128 * self.reserve1 as u128)
+            .integer_sqrt()
+            .as_u128()
+    }
+
+    /// Swaps `amount` of input for exact input, or of output otherwise. A price limit caps
+    /// the swap at the input that moves the price there, the returned flag tells whether
+    /// it did.
+    pub fn swap(
+        &self,
+        amount: u64,
+        sqrt_price_limit_x64: Option<u128>,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> Result<(CurveSwap, bool), SwapError> {
+        if amount == 0 {
+            return Err(SwapError::InvalidInput("amountSpecified must not be 0"));
+        }
+        let (reserve_in, reserve_out) = if zero_for_one {
+            (self.reserve0, self.reserve1)
+        } else {
+            (self.reserve1, self.reserve0)
+        };
+        let max_amount_in = sqrt_price_limit_x64
+            .map(|limit| self.input_to_price(limit, zero_for_one))
+            .transpose()?;
+
+        if is_base_input {
+            return match max_amount_in {
+                Some(max_amount_in) if amount > max_amount_in => Ok((
+                    swap_base_input(reserve_in, reserve_out, self.fee, max_amount_in)?,
+                    true,
+                )),
+                _ => Ok((
+                    swap_base_input(reserve_in, reserve_out, self.fee, amount)?,
+                    false,
+                )),
+            };
+        }
+
+        if let Some(max_amount_in) = max_amount_in {
+            let capped = swap_base_input(reserve_in, reserve_out, self.fee, max_amount_in)?;
+            if amount > capped.amount_out {
+                return Ok((capped, true));
+            }
+        }
+        Ok((
+            swap_base_output(reserve_in, reserve_out, self.fee, amount)?,
+            false,
+        ))
+    }
+
+    /// Reserves once `swap` went through, the trade fee stays in the pool
+    pub fn after(&self, swap: &CurveSwap, zero_for_one: bool) -> Self {
+        let (reserve0, reserve1) = if zero_for_one {
+            (
+                self.reserve0.saturating_add(swap.amount_in),
+                self.reserve1.saturating_sub(swap.amount_out),
+            )
+        } else {
+            (
+                self.reserve0.saturating_sub(swap.amount_out),
+                self.reserve1.saturating_add(swap.amount_in),
+            )
+        };
+        Self {
+            reserve0,
+            reserve1,
+            fee: self.fee,
+        }
+    }
+
+    /// Input, trade fee included, that moves the price to `sqrt_price_limit_x64`
+    fn input_to_price(
+        &self,
+        sqrt_price_limit_x64: u128,
+        zero_for_one: bool,
+    ) -> Result<u64, SwapError> {
+        let sqrt_price_x64 = self.sqrt_price_x64();
+        if zero_for_one && sqrt_price_limit_x64 >= sqrt_price_x64 {
+            return Err(SwapError::InvalidInput(
+                "sqrt_price_limit_x64 must smaller than current",
+            ));
+        }
+        if !zero_for_one && sqrt_price_limit_x64 <= sqrt_price_x64 {
+            return Err(SwapError::InvalidInput(
+                "sqrt_price_limit_x64 must greater than current",
+            ));
+        }
+
+        // the product stays put, so the input side ends up at sqrt(k) / sqrt(price) for
+        // mint0 and at sqrt(k) * sqrt(price) for mint1
+        let sqrt_k = U256::from(self.liquidity());
+        let (reserve_in, reserve_in_after) = if zero_for_one {
+            (
+                self.reserve0,
+                (sqrt_k << 64) / U256::from(sqrt_price_limit_x64),
+            )
+        } else {
+            (
+                self.reserve1,
+                (sqrt_k * U256::from(sqrt_price_limit_x64)) >> 64,
+            )
+        };
+        let amount_in = reserve_in_after
+            .saturating_sub(U256::from(reserve_in))
+            .min(U256::from(u64::MAX))
+            .as_u64();
+
+        Ok(self.fee.gross_up(amount_in).unwrap_or(u64::MAX))
+    }
+
+    /// [`Reserves::swap`] as seen from the user's token accounts, i.e. with the token-2022
+    /// transfer fees of `mint0`/`mint1` at `epoch` applied on both sides
+    #[allow(clippy::too_many_arguments)]
+    pub fn quote(
+        &self,
+        mint0: &[u8],
+        mint1: &[u8],
+        epoch: u64,
+        amount: u64,
+        sqrt_price_limit_x64: Option<u128>,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> anyhow::Result<Quote> {
+        let mut mint0 = mint0.to_vec();
+        let mut mint1 = mint1.to_vec();
+        let mint0_state = StateWithExtensionsMut::<Mint>::unpack(&mut mint0)?;
+        let mint1_state = StateWithExtensionsMut::<Mint>::unpack(&mut mint1)?;
+        let (input_mint_state, output_mint_state) = if zero_for_one {
+            (mint0_state, mint1_state)
+        } else {
+            (mint1_state, mint0_state)
+        };
+
+        let amount_specified = if is_base_input {
+            amount.checked_sub(get_transfer_fee(&input_mint_state, epoch, amount))
+        } else {
+            amount.checked_add(get_transfer_inverse_fee(&output_mint_state, epoch, amount))
+        }
+        .ok_or_else(|| anyhow::anyhow!("transfer fee overflows amount {}", amount))?;
+
+        let (swap, limited) = self.swap(
+            amount_specified,
+            sqrt_price_limit_x64,
+            zero_for_one,
+            is_base_input,
+        )?;
+
+        let amount_in = if is_base_input && !limited {
+            amount
+        } else {
+            let transfer_fee = get_transfer_inverse_fee(&input_mint_state, epoch, swap.amount_in);
+            let amount_in = swap
+                .amount_in
+                .checked_add(transfer_fee)
+                .ok_or_else(|| anyhow::anyhow!("transfer fee overflows amount in"))?;
+            if is_base_input {
+                amount_in.min(amount)
+            } else {
+                amount_in
+            }
+        };
+        let amount_out = if !is_base_input && !limited {
+            amount
+        } else {
+            swap.amount_out - get_transfer_fee(&output_mint_state, epoch, swap.amount_out)
+        };
+
+        Ok(Quote {
+            amount_in,
+            amount_out,
+            limited,
+            sqrt_price_before_x64: self.sqrt_price_x64(),
+            sqrt_price_after_x64: self.after(&swap, zero_for_one).sqrt_price_x64(),
+            fee_amount: swap.fee_amount,
+            ticks_crossed: 0,
+        })
+    }
+}
+
+/// Output of `amount_in` against `reserve_in`/`reserve_out`, the fee is taken first
+pub fn swap_base_input(
+    reserve_in: u64,
+    reserve_out: u64,
+    fee: Fee,
+    amount_in: u64,
+) -> Result<CurveSwap, SwapError> {
+    if reserve_in == 0 || reserve_out == 0 {
+        return Err(SwapError::InsufficientLiquidity);
+    }
+    let fee_amount = fee.of(amount_in);
+    let amount_in_less_fee = amount_in.saturating_sub(fee_amount) as u128;
+    let amount_out =
+        amount_in_less_fee * reserve_out as u128 / (reserve_in as u128 + amount_in_less_fee);
+
+    Ok(CurveSwap {
+        amount_in,
+        amount_out: amount_out as u64,
+        fee_amount,
+    })
+}
+
+/// Input, fee included, that buys exactly `amount_out` of `reserve_out`
+pub fn swap_base_output(
+    reserve_in: u64,
+    reserve_out: u64,
+    fee: Fee,
+    amount_out: u64,
+) -> Result<CurveSwap, SwapError> {
+    if reserve_in == 0 || amount_out >= reserve_out {
+        return Err(SwapError::InsufficientLiquidity);
+    }
+    let amount_in_less_fee = u64::try_from(ceil_div(
+        amount_out as u128 * reserve_in as u128,
+        (reserve_out - amount_out) as u128,
+    ))
+    .map_err(|_| SwapError::InsufficientLiquidity)?;
+    let amount_in = fee
+        .gross_up(amount_in_less_fee)
+        .ok_or(SwapError::InsufficientLiquidity)?;
+
+    Ok(CurveSwap {
+        amount_in,
+        amount_out,
+        fee_amount: amount_in - amount_in_less_fee,
+    })
+}
+
+fn ceil_div(numerator: u128, denominator: u128) -> u128 {
+    (numerator + denominator - 1) / denominator
+}
@@ -0,0 +1,414 @@
+use std::sync::Arc;
+
+use arrayref::array_ref;
+use futures::future::join_all;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::Keypair,
+    signer::Signer,
+};
+
+use crate::error::SwapError;
+
+use super::{
+    constant_product::{Fee, Reserves},
+    market::{filled_amount, other_amount_threshold, PoolKind, SwapParams, SwapPool},
+    pool::Quote,
+    registry::{program_accounts_config, PairPool},
+    utils::{anchor_discriminator, token_amount},
+};
+
+/// Trade fee rates of CPMM amm configs are parts per million
+const FEE_RATE_DENOMINATOR: u64 = 1_000_000;
+/// Bit of `PoolState::status` the program sets to stop swaps
+const SWAP_DISABLED: u8 = 1 << 2;
+const AUTH_SEED: &[u8] = b"vault_and_lp_mint_auth_seed";
+
+/// The fields of a CPMM `PoolState` the crate uses, decoded by offset as the program
+/// crate does not build next to the CLMM one
+#[derive(Debug, Clone, Copy)]
+pub struct CpmmPoolState {
+    pub amm_config: Pubkey,
+    pub token_0_vault: Pubkey,
+    pub token_1_vault: Pubkey,
+    pub token_0_mint: Pubkey,
+    pub token_1_mint: Pubkey,
+    pub token_0_program: Pubkey,
+    pub token_1_program: Pubkey,
+    pub observation_key: Pubkey,
+    pub status: u8,
+    pub mint_0_decimals: u8,
+    pub mint_1_decimals: u8,
+    pub protocol_fees_token_0: u64,
+    pub protocol_fees_token_1: u64,
+    pub fund_fees_token_0: u64,
+    pub fund_fees_token_1: u64,
+}
+
+impl CpmmPoolState {
+    pub const LEN: usize = 637;
+    const TOKEN_0_MINT_OFFSET: usize = 168;
+    const TOKEN_1_MINT_OFFSET: usize = 200;
+
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < Self::LEN || data[..8] != anchor_discriminator("account:PoolState") {
+            anyhow::bail!("not a CPMM pool state");
+        }
+        let pubkey = |offset| Pubkey::new_from_array(*array_ref![data, offset, 32]);
+        let amount = |offset| u64::from_le_bytes(*array_ref![data, offset, 8]);
+
+        Ok(Self {
+            amm_config: pubkey(8),
+            token_0_vault: pubkey(72),
+            token_1_vault: pubkey(104),
+            token_0_mint: pubkey(Self::TOKEN_0_MINT_OFFSET),
+            token_1_mint: pubkey(Self::TOKEN_1_MINT_OFFSET),
+            token_0_program: pubkey(232),
+            token_1_program: pubkey(264),
+            observation_key: pubkey(296),
+            status: data[329],
+            mint_0_decimals: data[331],
+            mint_1_decimals: data[332],
+            protocol_fees_token_0: amount(341),
+            protocol_fees_token_1: amount(349),
+            fund_fees_token_0: amount(357),
+            fund_fees_token_1: amount(365),
+        })
+    }
+}
+
+/// The fields of a CPMM `AmmConfig` the crate uses
+#[derive(Debug, Clone, Copy)]
+pub struct CpmmAmmConfig {
+    pub index: u16,
+    /// Parts per million of the input
+    pub trade_fee_rate: u64,
+}
+
+impl CpmmAmmConfig {
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < 20 || data[..8] != anchor_discriminator("account:AmmConfig") {
+            anyhow::bail!("not a CPMM amm config");
+        }
+        Ok(Self {
+            index: u16::from_le_bytes(*array_ref![data, 10, 2]),
+            trade_fee_rate: u64::from_le_bytes(*array_ref![data, 12, 8]),
+        })
+    }
+}
+
+/// A Raydium CPMM pool with the balances of its vaults
+#[derive(Debug, Clone)]
+pub struct CpmmPool {
+    id: Pubkey,
+    program_id: Pubkey,
+    state: CpmmPoolState,
+    amm_config: CpmmAmmConfig,
+    vault0: u64,
+    vault1: u64,
+    mint0: Vec<u8>,
+    mint1: Vec<u8>,
+}
+
+impl CpmmPool {
+    pub async fn load(rpc: &RpcClient, program_id: &Pubkey, pool: Pubkey) -> anyhow::Result<Self> {
+        let account = rpc.get_account(&pool).await?;
+        Self::load_with_state(rpc, program_id, pool, CpmmPoolState::decode(&account.data)?).await
+    }
+
+    /// Loads what the pool prices with next to its already decoded state
+    async fn load_with_state(
+        rpc: &RpcClient,
+        program_id: &Pubkey,
+        pool: Pubkey,
+        state: CpmmPoolState,
+    ) -> anyhow::Result<Self> {
+        let keys = [
+            state.amm_config,
+            state.token_0_vault,
+            state.token_1_vault,
+            state.token_0_mint,
+            state.token_1_mint,
+        ];
+        let accounts = rpc.get_multiple_accounts(&keys).await?;
+        let existing = |index: usize| {
+            accounts[index]
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("account {} does not exist", keys[index]))
+        };
+
+        Ok(Self {
+            id: pool,
+            program_id: *program_id,
+            state,
+            amm_config: CpmmAmmConfig::decode(&existing(0)?.data)?,
+            vault0: token_amount(existing(1)?)?,
+            vault1: token_amount(existing(2)?)?,
+            mint0: existing(3)?.data.clone(),
+            mint1: existing(4)?.data.clone(),
+        })
+    }
+
+    /// CPMM pools of the pair, the program creates them with token 0 < token 1 only
+    pub async fn find_pairs(
+        rpc: &RpcClient,
+        program_id: &Pubkey,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+    ) -> anyhow::Result<Vec<PairPool>> {
+        let (mint0, mint1) = if mint_a < mint_b {
+            (mint_a, mint_b)
+        } else {
+            (mint_b, mint_a)
+        };
+        let accounts = rpc
+            .get_program_accounts_with_config(
+                program_id,
+                program_accounts_config(vec![
+                    RpcFilterType::DataSize(CpmmPoolState::LEN as u64),
+                    RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                        CpmmPoolState::TOKEN_0_MINT_OFFSET,
+                        mint0.as_ref(),
+                    )),
+                    RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                        CpmmPoolState::TOKEN_1_MINT_OFFSET,
+                        mint1.as_ref(),
+                    )),
+                ]),
+            )
+            .await?;
+
+        let pools = join_all(accounts.into_iter().map(|(pool, account)| async move {
+            let state = CpmmPoolState::decode(&account.data)?;
+            Self::load_with_state(rpc, program_id, pool, state).await
+        }))
+        .await;
+        pools
+            .into_iter()
+            .map(|pool| Ok(pool?.pair(mint_a)))
+            .collect()
+    }
+
+    /// The pool as a pool of the pair requested as `mint_a` -> the other mint
+    pub fn pair(&self, mint_a: Pubkey) -> PairPool {
+        PairPool {
+            pool: self.id,
+            kind: PoolKind::Cpmm,
+            mint0: self.state.token_0_mint,
+            mint1: self.state.token_1_mint,
+            amm_config: self.state.amm_config,
+            fee_index: self.amm_config.index,
+            trade_fee_rate: self.amm_config.trade_fee_rate as u32,
+            liquidity: self.reserves().liquidity(),
+            zero_for_one: mint_a == self.state.token_0_mint,
+        }
+    }
+
+    pub fn state(&self) -> &CpmmPoolState {
+        &self.state
+    }
+
+    /// Vault balances without the protocol and fund fees accrued in them
+    pub fn reserves(&self) -> Reserves {
+        Reserves {
+            reserve0: self
+                .vault0
+                .saturating_sub(self.state.protocol_fees_token_0)
+                .saturating_sub(self.state.fund_fees_token_0),
+            reserve1: self
+                .vault1
+                .saturating_sub(self.state.protocol_fees_token_1)
+                .saturating_sub(self.state.fund_fees_token_1),
+            fee: Fee {
+                numerator: self.amm_config.trade_fee_rate,
+                denominator: FEE_RATE_DENOMINATOR,
+            },
+        }
+    }
+
+    pub(super) fn watched_accounts(&self) -> Vec<Pubkey> {
+        vec![
+            self.id,
+            self.state.amm_config,
+            self.state.token_0_vault,
+            self.state.token_1_vault,
+            self.state.token_0_mint,
+            self.state.token_1_mint,
+        ]
+    }
+
+    pub(super) fn update(&mut self, key: &Pubkey, account: &Account) -> anyhow::Result<()> {
+        if *key == self.id {
+            self.state = CpmmPoolState::decode(&account.data)?;
+        } else if *key == self.state.amm_config {
+            self.amm_config = CpmmAmmConfig::decode(&account.data)?;
+        } else if *key == self.state.token_0_vault {
+            self.vault0 = token_amount(account)?;
+        } else if *key == self.state.token_1_vault {
+            self.vault1 = token_amount(account)?;
+        } else if *key == self.state.token_0_mint {
+            self.mint0 = account.data.clone();
+        } else if *key == self.state.token_1_mint {
+            self.mint1 = account.data.clone();
+        }
+        Ok(())
+    }
+
+    /// `swap_base_input`, or `swap_base_output` without `is_base_input`, in the given
+    /// direction
+    #[allow(clippy::too_many_arguments)]
+    pub fn swap_instruction(
+        &self,
+        payer: Pubkey,
+        input_token: Pubkey,
+        output_token: Pubkey,
+        zero_for_one: bool,
+        amount: u64,
+        other_amount_threshold: u64,
+        is_base_input: bool,
+    ) -> Instruction {
+        let state = &self.state;
+        let (input_vault, output_vault, input_program, output_program, input_mint, output_mint) =
+            if zero_for_one {
+                (
+                    state.token_0_vault,
+                    state.token_1_vault,
+                    state.token_0_program,
+                    state.token_1_program,
+                    state.token_0_mint,
+                    state.token_1_mint,
+                )
+            } else {
+                (
+                    state.token_1_vault,
+                    state.token_0_vault,
+                    state.token_1_program,
+                    state.token_0_program,
+                    state.token_1_mint,
+                    state.token_0_mint,
+                )
+            };
+        let (authority, _) = Pubkey::find_program_address(&[AUTH_SEED], &self.program_id);
+
+        let name = if is_base_input {
+            "global:swap_base_input"
+        } else {
+            "global:swap_base_output"
+        };
+        let mut data = anchor_discriminator(name).to_vec();
+        data.extend_from_slice(&amount.to_le_bytes());
+        data.extend_from_slice(&other_amount_threshold.to_le_bytes());
+
+        Instruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new_readonly(payer, true),
+                AccountMeta::new_readonly(authority, false),
+                AccountMeta::new_readonly(state.amm_config, false),
+                AccountMeta::new(self.id, false),
+                AccountMeta::new(input_token, false),
+                AccountMeta::new(output_token, false),
+                AccountMeta::new(input_vault, false),
+                AccountMeta::new(output_vault, false),
+                AccountMeta::new_readonly(input_program, false),
+                AccountMeta::new_readonly(output_program, false),
+                AccountMeta::new_readonly(input_mint, false),
+                AccountMeta::new_readonly(output_mint, false),
+                AccountMeta::new(state.observation_key, false),
+            ],
+            data,
+        }
+    }
+}
+
+impl SwapPool for CpmmPool {
+    fn id(&self) -> Pubkey {
+        self.id
+    }
+
+    fn program_id(&self) -> Pubkey {
+        self.program_id
+    }
+
+    fn kind(&self) -> PoolKind {
+        PoolKind::Cpmm
+    }
+
+    fn mints(&self) -> (Pubkey, Pubkey) {
+        (self.state.token_0_mint, self.state.token_1_mint)
+    }
+
+    fn mint_decimals(&self) -> (u8, u8) {
+        (self.state.mint_0_decimals, self.state.mint_1_decimals)
+    }
+
+    fn sqrt_price_x64(&self) -> u128 {
+        self.reserves().sqrt_price_x64()
+    }
+
+    async fn quote(
+        &self,
+        _rpc: Arc<RpcClient>,
+        epoch: u64,
+        amount: u64,
+        sqrt_price_limit_x64: Option<u128>,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> anyhow::Result<Quote> {
+        self.reserves().quote(
+            &self.mint0,
+            &self.mint1,
+            epoch,
+            amount,
+            sqrt_price_limit_x64,
+            zero_for_one,
+            is_base_input,
+        )
+    }
+
+    async fn swap_instructions(
+        &self,
+        rpc: &RpcClient,
+        payer: &Keypair,
+        params: &SwapParams,
+    ) -> anyhow::Result<(Quote, Vec<Instruction>)> {
+        if self.state.status & SWAP_DISABLED != 0 {
+            return Err(SwapError::InvalidInput("pool does not accept swaps").into());
+        }
+        let epoch = rpc.get_epoch_info().await?.epoch;
+        let quote = self.reserves().quote(
+            &self.mint0,
+            &self.mint1,
+            epoch,
+            params.amount,
+            params.sqrt_price_limit_x64,
+            params.zero_for_one,
+            params.is_base_input,
+        )?;
+
+        let amount = filled_amount(&quote, params);
+        let threshold = other_amount_threshold(
+            &quote,
+            params.slippage,
+            params.is_base_input,
+            params.fee_bps,
+        );
+        let instruction = self.swap_instruction(
+            payer.pubkey(),
+            params.input_token,
+            params.output_token,
+            params.zero_for_one,
+            amount,
+            threshold,
+            params.is_base_input,
+        );
+
+        Ok((quote, vec![instruction]))
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use raydium_amm_v3::states::{AmmConfig, PoolState, TickArrayBitmapExtension, TickArrayState};
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::UiAccount;
+use solana_sdk::account::Account;
+
+use crate::error::SwapError;
+
+use super::{
+    pool::deserialize_anchor_account,
+    utils::{self, SwapState},
+};
+
+/// Raw accounts of a CLMM pool at one point in time, each one exactly as `getAccountInfo`
+/// returns it with `base64` encoding. This is the format of the quote fixtures.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PoolSnapshot {
+    pub amm_config: UiAccount,
+    pub pool_state: UiAccount,
+    pub tick_array_bitmap_extension: UiAccount,
+    pub tick_arrays: Vec<UiAccount>,
+}
+
+/// Quotes swaps against decoded pool accounts without any RPC access
+#[derive(Clone)]
+pub struct QuoteEngine {
+    amm_config: AmmConfig,
+    pool_state: PoolState,
+    tick_array_bitmap_extension: TickArrayBitmapExtension,
+    tick_arrays: BTreeMap<i32, TickArrayState>,
+}
+
+impl QuoteEngine {
+    pub fn new(
+        amm_config: AmmConfig,
+        pool_state: PoolState,
+        tick_array_bitmap_extension: TickArrayBitmapExtension,
+        tick_arrays: impl IntoIterator<Item = TickArrayState>,
+    ) -> Self {
+        Self {
+            amm_config,
+            pool_state,
+            tick_array_bitmap_extension,
+            tick_arrays: tick_arrays
+                .into_iter()
+                .map(|tick_array| (tick_array.start_tick_index, tick_array))
+                .collect(),
+        }
+    }
+
+    pub fn from_snapshot(snapshot: &PoolSnapshot) -> anyhow::Result<Self> {
+        let tick_arrays = snapshot
+            .tick_arrays
+            .iter()
+            .map(|account| deserialize_anchor_account::<TickArrayState>(&decode(account)?))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self::new(
+            deserialize_anchor_account(&decode(&snapshot.amm_config)?)?,
+            deserialize_anchor_account(&decode(&snapshot.pool_state)?)?,
+            deserialize_anchor_account(&decode(&snapshot.tick_array_bitmap_extension)?)?,
+            tick_arrays,
+        ))
+    }
+
+    pub fn pool_state(&self) -> &PoolState {
+        &self.pool_state
+    }
+
+    pub fn amm_config(&self) -> &AmmConfig {
+        &self.amm_config
+    }
+
+    pub fn tick_array_bitmap_extension(&self) -> &TickArrayBitmapExtension {
+        &self.tick_array_bitmap_extension
+    }
+
+    pub fn set_pool_state(&mut self, pool_state: PoolState) {
+        self.pool_state = pool_state;
+    }
+
+    pub fn set_amm_config(&mut self, amm_config: AmmConfig) {
+        self.amm_config = amm_config;
+    }
+
+    pub fn set_tick_array_bitmap_extension(
+        &mut self,
+        tick_array_bitmap_extension: TickArrayBitmapExtension,
+    ) {
+        self.tick_array_bitmap_extension = tick_array_bitmap_extension;
+    }
+
+    pub fn has_tick_array(&self, start_index: i32) -> bool {
+        self.tick_arrays.contains_key(&start_index)
+    }
+
+    /// Drops the tick arrays whose start index does not pass `keep`
+    pub fn retain_tick_arrays(&mut self, mut keep: impl FnMut(i32) -> bool) {
+        self.tick_arrays.retain(|start_index, _| keep(*start_index));
+    }
+
+    /// Number of tick arrays loaded so far
+    pub fn tick_array_count(&self) -> usize {
+        self.tick_arrays.len()
+    }
+
+    /// Adds (or refreshes) tick arrays, e.g. after fetching the ones a quote walked into
+    pub fn insert_tick_arrays(&mut self, tick_arrays: impl IntoIterator<Item = TickArrayState>) {
+        self.tick_arrays.extend(
+            tick_arrays
+                .into_iter()
+                .map(|tick_array| (tick_array.start_tick_index, tick_array)),
+        );
+    }
+
+    /// Initialized tick arrays in swap direction, starting with the one the current tick
+    /// is in. Stops at the first array that is not loaded, the swap math reports it with
+    /// [`SwapError::TickArrayNotLoaded`] if it actually needs it.
+    pub fn tick_arrays(&self, zero_for_one: bool) -> VecDeque<TickArrayState> {
+        self.tick_arrays_with(zero_for_one, |_| None)
+    }
+
+    /// [`QuoteEngine::tick_arrays`] that falls back to `more` for arrays not loaded here
+    fn tick_arrays_with(
+        &self,
+        zero_for_one: bool,
+        more: impl Fn(i32) -> Option<TickArrayState>,
+    ) -> VecDeque<TickArrayState> {
+        let extension = Some(self.tick_array_bitmap_extension);
+        let mut tick_arrays = VecDeque::new();
+        let Ok((_, mut start_index)) = self
+            .pool_state
+            .get_first_initialized_tick_array(&extension, zero_for_one)
+        else {
+            return tick_arrays;
+        };
+
+        while let Some(tick_array) = self
+            .tick_arrays
+            .get(&start_index)
+            .copied()
+            .or_else(|| more(start_index))
+        {
+            tick_arrays.push_back(tick_array);
+            match self.pool_state.next_initialized_tick_array_start_index(
+                &extension,
+                start_index,
+                zero_for_one,
+            ) {
+                Ok(Some(next_start_index)) => start_index = next_start_index,
+                _ => break,
+            }
+        }
+
+        tick_arrays
+    }
+
+    /// Runs the swap math for `amount` of the input (or output, without `is_base_input`)
+    /// mint. Token-2022 transfer fees are not applied here.
+    pub fn quote(
+        &self,
+        amount: u64,
+        sqrt_price_limit_x64: Option<u128>,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> Result<(SwapState, VecDeque<i32>), SwapError> {
+        self.quote_with(
+            |_| None,
+            amount,
+            sqrt_price_limit_x64,
+            zero_for_one,
+            is_base_input,
+        )
+    }
+
+    /// [`QuoteEngine::quote`] that also walks the tick arrays `more` has, e.g. ones
+    /// fetched for an earlier quote
+    pub fn quote_with(
+        &self,
+        more: impl Fn(i32) -> Option<TickArrayState>,
+        amount: u64,
+        sqrt_price_limit_x64: Option<u128>,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> Result<(SwapState, VecDeque<i32>), SwapError> {
+        utils::get_out_put_amount_and_remaining_accounts(
+            amount,
+            sqrt_price_limit_x64,
+            zero_for_one,
+            is_base_input,
+            &self.amm_config,
+            &self.pool_state,
+            &self.tick_array_bitmap_extension,
+            &mut self.tick_arrays_with(zero_for_one, more),
+        )
+    }
+}
+
+fn decode(account: &UiAccount) -> anyhow::Result<Account> {
+    account
+        .decode()
+        .ok_or_else(|| anyhow::anyhow!("unable to decode account data, expected base64"))
+}
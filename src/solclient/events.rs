@@ -0,0 +1,95 @@
+use arrayref::array_ref;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+use super::{
+    pool::{PriceTick, TickSource},
+    utils,
+};
+
+/// Prefix of the log line `emit!` writes an event into
+const PROGRAM_DATA: &str = "Program data: ";
+
+/// The `SwapEvent` the CLMM program emits for every swap, decoded by offset the same
+/// way as the observation state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapEvent {
+    pub pool_state: Pubkey,
+    pub sender: Pubkey,
+    pub token_account_0: Pubkey,
+    pub token_account_1: Pubkey,
+    pub amount_0: u64,
+    pub transfer_fee_0: u64,
+    pub amount_1: u64,
+    pub transfer_fee_1: u64,
+    pub zero_for_one: bool,
+    /// Pool price after the swap
+    pub sqrt_price_x64: u128,
+    pub liquidity: u128,
+    pub tick: i32,
+}
+
+impl SwapEvent {
+    pub const LEN: usize = 8 + 32 * 4 + 8 * 4 + 1 + 16 + 16 + 4;
+
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < Self::LEN || data[..8] != utils::anchor_discriminator("event:SwapEvent") {
+            anyhow::bail!("not a CLMM swap event");
+        }
+        Ok(Self {
+            pool_state: Pubkey::new_from_array(*array_ref![data, 8, 32]),
+            sender: Pubkey::new_from_array(*array_ref![data, 40, 32]),
+            token_account_0: Pubkey::new_from_array(*array_ref![data, 72, 32]),
+            token_account_1: Pubkey::new_from_array(*array_ref![data, 104, 32]),
+            amount_0: u64::from_le_bytes(*array_ref![data, 136, 8]),
+            transfer_fee_0: u64::from_le_bytes(*array_ref![data, 144, 8]),
+            amount_1: u64::from_le_bytes(*array_ref![data, 152, 8]),
+            transfer_fee_1: u64::from_le_bytes(*array_ref![data, 160, 8]),
+            zero_for_one: data[168] != 0,
+            sqrt_price_x64: u128::from_le_bytes(*array_ref![data, 169, 16]),
+            liquidity: u128::from_le_bytes(*array_ref![data, 185, 16]),
+            tick: i32::from_le_bytes(*array_ref![data, 201, 4]),
+        })
+    }
+
+    /// The swap as a price tick of its pool, `block_time` in unix seconds. `signature` and
+    /// `event_index`, the position among the swap events of the pool in the transaction,
+    /// identify the tick so it is only written once.
+    pub fn price_tick(
+        &self,
+        signature: Signature,
+        event_index: u32,
+        slot: u64,
+        block_time: i64,
+        decimals_0: u8,
+        decimals_1: u8,
+    ) -> PriceTick {
+        PriceTick {
+            pool: self.pool_state,
+            slot,
+            observed_at: block_time * 1000,
+            sqrt_price_x64: self.sqrt_price_x64,
+            tick: self.tick,
+            liquidity: self.liquidity,
+            price: utils::sqrt_price_x64_to_price(self.sqrt_price_x64, decimals_0, decimals_1),
+            volume0: self.amount_0 as f64 / 10f64.powi(decimals_0 as i32),
+            source: TickSource::Backfill {
+                signature,
+                event_index,
+            },
+        }
+    }
+}
+
+/// Swap events of `pool` in the log messages of a transaction, in the order they were
+/// emitted. Events of other pools and anything else logged as program data are skipped.
+pub fn swap_events<'a>(
+    logs: &'a [String],
+    pool: &'a Pubkey,
+) -> impl Iterator<Item = SwapEvent> + 'a {
+    logs.iter()
+        .filter_map(|log| log.strip_prefix(PROGRAM_DATA))
+        .filter_map(|data| STANDARD.decode(data).ok())
+        .filter_map(|data| SwapEvent::decode(&data).ok())
+        .filter(move |event| event.pool_state == *pool)
+}
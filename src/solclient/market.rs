@@ -0,0 +1,416 @@
+use std::{future::Future, str::FromStr, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::UiAccount;
+use solana_client::{
+    nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient},
+    rpc_response::Response as RpcResponse,
+};
+use solana_sdk::{account::Account, instruction::Instruction, pubkey::Pubkey, signature::Keypair};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::constants::{RAYDIUM_AMM_V4_PUBKEY, RAYDIUM_CLMM_PUBKEY, RAYDIUM_CPMM_PUBKEY};
+
+use super::{
+    amm_v4::AmmV4Pool,
+    compute_budget::{self, PriorityFee},
+    cpmm::CpmmPool,
+    pool::{LoadPoolAccounts, Pool, PriceTick, Quote, UnsubscribeHandle},
+    pubsub::{self, AccountListener, AccountStreams, PoolAddresses, PoolEvent},
+    registry::PairPool,
+    utils::{self, amount_with_slippage, TransactionResult},
+};
+
+/// Raydium programs a pair may have pools in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolKind {
+    Clmm,
+    Cpmm,
+    AmmV4,
+}
+
+/// Program ids of the pool kinds, overridable for devnet
+#[derive(Debug, Clone, Copy)]
+pub struct RaydiumPrograms {
+    pub clmm: Pubkey,
+    pub cpmm: Pubkey,
+    pub amm_v4: Pubkey,
+}
+
+impl Default for RaydiumPrograms {
+    fn default() -> Self {
+        Self {
+            clmm: Pubkey::from_str(RAYDIUM_CLMM_PUBKEY).unwrap(),
+            cpmm: Pubkey::from_str(RAYDIUM_CPMM_PUBKEY).unwrap(),
+            amm_v4: Pubkey::from_str(RAYDIUM_AMM_V4_PUBKEY).unwrap(),
+        }
+    }
+}
+
+impl RaydiumPrograms {
+    /// Kind of the pools owned by `program_id`, if any
+    pub fn kind_of(&self, program_id: &Pubkey) -> Option<PoolKind> {
+        if *program_id == self.clmm {
+            Some(PoolKind::Clmm)
+        } else if *program_id == self.cpmm {
+            Some(PoolKind::Cpmm)
+        } else if *program_id == self.amm_v4 {
+            Some(PoolKind::AmmV4)
+        } else {
+            None
+        }
+    }
+}
+
+/// What a swap against a pool asks for, amounts in the smallest unit
+#[derive(Debug, Clone, Copy)]
+pub struct SwapParams {
+    pub input_token: Pubkey,
+    pub output_token: Pubkey,
+    /// Of the input mint for exact input, of the output mint otherwise
+    pub amount: u64,
+    pub slippage: f64,
+    pub is_base_input: bool,
+    /// In pool order
+    pub zero_for_one: bool,
+    /// Pools without an on-chain price limit swap whatever the quote filled up to it
+    pub sqrt_price_limit_x64: Option<u128>,
+    /// Extra fee in basis points the threshold leaves room for
+    pub fee_bps: Option<u64>,
+    /// Compute unit price of the swap transaction, none by default
+    pub priority_fee: Option<PriorityFee>,
+}
+
+/// A pool the price feed can quote and the swap endpoint can trade against, whatever
+/// program it belongs to
+pub trait SwapPool {
+    fn id(&self) -> Pubkey;
+
+    fn program_id(&self) -> Pubkey;
+
+    fn kind(&self) -> PoolKind;
+
+    /// Mints in pool order, prices are the ones of the first in the second
+    fn mints(&self) -> (Pubkey, Pubkey);
+
+    fn mint_decimals(&self) -> (u8, u8);
+
+    /// Current price as a Q64.64 sqrt price
+    fn sqrt_price_x64(&self) -> u128;
+
+    /// Quotes `amount` of input for exact input, or of output otherwise, with the token-2022
+    /// transfer fees of `epoch`
+    fn quote(
+        &self,
+        rpc: Arc<RpcClient>,
+        epoch: u64,
+        amount: u64,
+        sqrt_price_limit_x64: Option<u128>,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> impl Future<Output = anyhow::Result<Quote>> + Send;
+
+    /// The pool's swap instructions for `params`, compute budget left to the caller, along
+    /// with the quote their threshold is derived from
+    fn swap_instructions(
+        &self,
+        rpc: &RpcClient,
+        payer: &Keypair,
+        params: &SwapParams,
+    ) -> impl Future<Output = anyhow::Result<(Quote, Vec<Instruction>)>> + Send;
+}
+
+/// Minimum output for exact input, or maximum input otherwise, of a swap quoted as
+/// `quote` with `slippage` and an extra `fee_bps` on top
+pub fn other_amount_threshold(
+    quote: &Quote,
+    slippage: f64,
+    is_base_input: bool,
+    fee_bps: Option<u64>,
+) -> u64 {
+    let custom_fee = |amount: u64| {
+        fee_bps.map_or(0, |fee_bps| {
+            (amount as f64 * fee_bps as f64 / 10000.0) as u64
+        })
+    };
+    if is_base_input {
+        let amount_out = quote
+            .amount_out
+            .saturating_sub(custom_fee(quote.amount_out));
+        amount_with_slippage(amount_out, slippage, false)
+    } else {
+        let amount_in = amount_with_slippage(quote.amount_in, slippage, true);
+        amount_in.saturating_add(custom_fee(amount_in))
+    }
+}
+
+/// Amount to swap for `params` on programs without a price limit, which is whatever the
+/// quote filled up to the limit
+pub fn filled_amount(quote: &Quote, params: &SwapParams) -> u64 {
+    match (quote.limited, params.is_base_input) {
+        (false, _) => params.amount,
+        (true, true) => quote.amount_in,
+        (true, false) => quote.amount_out,
+    }
+}
+
+/// Any pool a pair may resolve to
+#[derive(Clone)]
+pub enum AnyPool {
+    Clmm(Pool),
+    Cpmm(CpmmPool),
+    AmmV4(AmmV4Pool),
+}
+
+impl AnyPool {
+    /// Loads the pool `pair` was resolved to, through RPC
+    pub async fn load(
+        rpc: Arc<RpcClient>,
+        programs: &RaydiumPrograms,
+        pair: &PairPool,
+        max_tick_arrays: usize,
+    ) -> anyhow::Result<Self> {
+        match pair.kind {
+            PoolKind::Clmm => {
+                let addresses = PoolAddresses::with_amm_config(
+                    &programs.clmm,
+                    pair.amm_config,
+                    pair.mint0,
+                    pair.mint1,
+                );
+                let pool = Pool::load(
+                    rpc,
+                    &programs.clmm,
+                    LoadPoolAccounts {
+                        amm_config: addresses.amm_config,
+                        pool_id: addresses.pool_state,
+                        tickarray_bitmap_extension: addresses.tick_array_bitmap_extension,
+                        mint0: pair.mint0,
+                        mint1: pair.mint1,
+                    },
+                    max_tick_arrays,
+                )
+                .await?;
+                Ok(Self::Clmm(pool))
+            }
+            PoolKind::Cpmm => Ok(Self::Cpmm(
+                CpmmPool::load(&rpc, &programs.cpmm, pair.pool).await?,
+            )),
+            PoolKind::AmmV4 => Ok(Self::AmmV4(
+                AmmV4Pool::load(&rpc, &programs.amm_v4, pair.pool).await?,
+            )),
+        }
+    }
+
+    /// Streams the pool with every change of the accounts its price depends on. Only CLMM
+    /// pools report their price changes to `ticks`.
+    pub async fn listen(
+        &self,
+        rpc: Arc<RpcClient>,
+        pubsub: Arc<PubsubClient>,
+        tx: broadcast::Sender<PoolEvent>,
+        dropped: mpsc::UnboundedSender<Pubkey>,
+        ticks: mpsc::UnboundedSender<PriceTick>,
+    ) -> anyhow::Result<UnsubscribeHandle> {
+        match self {
+            Self::Clmm(pool) => pool.listen(rpc, pubsub, tx, dropped, ticks).await,
+            _ => {
+                let listener = ConstantProductListener {
+                    pool: self.clone(),
+                    watched: self.watched_accounts(),
+                };
+                pubsub::listen(self.id(), listener, rpc, pubsub, tx, dropped).await
+            }
+        }
+    }
+
+    /// Swaps against the pool in one transaction, `setup` runs ahead of the swap and
+    /// `cleanup` after it. The compute unit limit is sized by simulating the transaction.
+    /// Returns the quote the swap was sent with next to the transaction.
+    pub async fn swap(
+        &self,
+        rpc: Arc<RpcClient>,
+        payer: Keypair,
+        params: SwapParams,
+        setup: &[Instruction],
+        cleanup: &[Instruction],
+        simulate: bool,
+    ) -> anyhow::Result<(Quote, TransactionResult)> {
+        let mut instructions = setup.to_vec();
+        let (quote, swap_instructions) = self.swap_instructions(&rpc, &payer, &params).await?;
+        instructions.extend(swap_instructions);
+        instructions.extend_from_slice(cleanup);
+        let instructions =
+            compute_budget::with_compute_budget(&rpc, &payer, instructions, params.priority_fee)
+                .await?;
+        let result = utils::send_or_simulate(&rpc, &instructions, &payer, simulate).await?;
+        Ok((quote, result))
+    }
+
+    /// Accounts a listener of a constant product pool follows
+    fn watched_accounts(&self) -> Vec<Pubkey> {
+        match self {
+            Self::Clmm(_) => Vec::new(),
+            Self::Cpmm(pool) => pool.watched_accounts(),
+            Self::AmmV4(pool) => pool.watched_accounts(),
+        }
+    }
+
+    /// Applies a change of one of the [`AnyPool::watched_accounts`]
+    fn update(&mut self, key: &Pubkey, account: &Account) -> anyhow::Result<()> {
+        match self {
+            Self::Clmm(_) => Ok(()),
+            Self::Cpmm(pool) => pool.update(key, account),
+            Self::AmmV4(pool) => pool.update(key, account),
+        }
+    }
+}
+
+impl SwapPool for AnyPool {
+    fn id(&self) -> Pubkey {
+        match self {
+            Self::Clmm(pool) => pool.id(),
+            Self::Cpmm(pool) => pool.id(),
+            Self::AmmV4(pool) => pool.id(),
+        }
+    }
+
+    fn program_id(&self) -> Pubkey {
+        match self {
+            Self::Clmm(pool) => pool.program_id(),
+            Self::Cpmm(pool) => pool.program_id(),
+            Self::AmmV4(pool) => pool.program_id(),
+        }
+    }
+
+    fn kind(&self) -> PoolKind {
+        match self {
+            Self::Clmm(pool) => pool.kind(),
+            Self::Cpmm(pool) => pool.kind(),
+            Self::AmmV4(pool) => pool.kind(),
+        }
+    }
+
+    fn mints(&self) -> (Pubkey, Pubkey) {
+        match self {
+            Self::Clmm(pool) => pool.mints(),
+            Self::Cpmm(pool) => pool.mints(),
+            Self::AmmV4(pool) => pool.mints(),
+        }
+    }
+
+    fn mint_decimals(&self) -> (u8, u8) {
+        match self {
+            Self::Clmm(pool) => pool.mint_decimals(),
+            Self::Cpmm(pool) => pool.mint_decimals(),
+            Self::AmmV4(pool) => pool.mint_decimals(),
+        }
+    }
+
+    fn sqrt_price_x64(&self) -> u128 {
+        match self {
+            Self::Clmm(pool) => pool.sqrt_price_x64(),
+            Self::Cpmm(pool) => pool.sqrt_price_x64(),
+            Self::AmmV4(pool) => pool.sqrt_price_x64(),
+        }
+    }
+
+    async fn quote(
+        &self,
+        rpc: Arc<RpcClient>,
+        epoch: u64,
+        amount: u64,
+        sqrt_price_limit_x64: Option<u128>,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> anyhow::Result<Quote> {
+        match self {
+            Self::Clmm(pool) => {
+                pool.quote(
+                    rpc,
+                    epoch,
+                    amount,
+                    sqrt_price_limit_x64,
+                    zero_for_one,
+                    is_base_input,
+                )
+                .await
+            }
+            Self::Cpmm(pool) => {
+                pool.quote(
+                    rpc,
+                    epoch,
+                    amount,
+                    sqrt_price_limit_x64,
+                    zero_for_one,
+                    is_base_input,
+                )
+                .await
+            }
+            Self::AmmV4(pool) => {
+                pool.quote(
+                    rpc,
+                    epoch,
+                    amount,
+                    sqrt_price_limit_x64,
+                    zero_for_one,
+                    is_base_input,
+                )
+                .await
+            }
+        }
+    }
+
+    async fn swap_instructions(
+        &self,
+        rpc: &RpcClient,
+        payer: &Keypair,
+        params: &SwapParams,
+    ) -> anyhow::Result<(Quote, Vec<Instruction>)> {
+        match self {
+            Self::Clmm(pool) => pool.swap_instructions(rpc, payer, params).await,
+            Self::Cpmm(pool) => pool.swap_instructions(rpc, payer, params).await,
+            Self::AmmV4(pool) => pool.swap_instructions(rpc, payer, params).await,
+        }
+    }
+}
+
+/// Listens to the [`AnyPool::watched_accounts`] of a constant product pool, every change
+/// of one of them is an update
+struct ConstantProductListener {
+    pool: AnyPool,
+    watched: Vec<Pubkey>,
+}
+
+impl AccountListener for ConstantProductListener {
+    /// Index into `watched`
+    type Account = usize;
+
+    async fn start(
+        &mut self,
+        _rpc: &RpcClient,
+        streams: &mut AccountStreams<'_, usize>,
+    ) -> anyhow::Result<()> {
+        for (index, pubkey) in self.watched.iter().enumerate() {
+            streams.subscribe(index, pubkey).await?;
+        }
+        Ok(())
+    }
+
+    async fn update(
+        &mut self,
+        _rpc: &RpcClient,
+        _streams: &mut AccountStreams<'_, usize>,
+        index: usize,
+        response: RpcResponse<UiAccount>,
+    ) -> anyhow::Result<Option<AnyPool>> {
+        let key = &self.watched[index];
+        let account = response
+            .value
+            .decode::<Account>()
+            .ok_or_else(|| anyhow::anyhow!("unable to decode {}", key))?;
+        self.pool.update(key, &account)?;
+        Ok(Some(self.pool.clone()))
+    }
+}
@@ -1,3 +1,16 @@
+pub mod amm_v4;
+pub mod compute_budget;
+pub mod constant_product;
+pub mod cpmm;
+pub mod engine;
+pub mod events;
+pub mod market;
+pub mod oracle;
 pub mod pool;
 pub mod pubsub;
+pub mod registry;
+pub mod router;
+pub mod split;
+pub mod token_accounts;
 pub mod utils;
+pub mod wallet;
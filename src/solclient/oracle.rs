@@ -0,0 +1,157 @@
+use arrayref::array_ref;
+use raydium_amm_v3::libraries::tick_math;
+use serde::Serialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::error::OracleError;
+
+use super::utils::{self, anchor_discriminator};
+
+/// Slots of the observation ring buffer
+pub const OBSERVATION_NUM: usize = 100;
+/// `block_timestamp`, `tick_cumulative` and 32 bytes of padding
+const OBSERVATION_LEN: usize = 4 + 8 + 32;
+const OBSERVATIONS_OFFSET: usize = 8 + 1 + 8 + 2 + 32;
+
+/// One slot of the ring buffer, written at most every 15 seconds by the first swap after
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Observation {
+    pub block_timestamp: u32,
+    /// Sum of `tick * seconds` since the pool was created
+    pub tick_cumulative: i64,
+}
+
+/// A CLMM `ObservationState`, decoded by offset since releases of the program crate
+/// disagree on its layout. This is the one with tick cumulatives the program runs today.
+#[derive(Debug, Clone)]
+pub struct ObservationState {
+    pub initialized: bool,
+    pub recent_epoch: u64,
+    /// Slot of the most recent observation
+    pub observation_index: u16,
+    pub pool_id: Pubkey,
+    pub observations: Vec<Observation>,
+}
+
+/// Time-weighted average over the last `window_secs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Twap {
+    pub window_secs: u32,
+    /// Arithmetic mean of the tick, i.e. the geometric mean of the price
+    pub average_tick: i32,
+}
+
+impl Twap {
+    /// Price of mint0 in mint1 at the average tick
+    pub fn price(&self, decimals_0: u8, decimals_1: u8) -> anyhow::Result<f64> {
+        let sqrt_price_x64 = tick_math::get_sqrt_price_at_tick(self.average_tick)
+            .map_err(|e| anyhow::anyhow!("invalid average tick {}: {}", self.average_tick, e))?;
+        Ok(utils::sqrt_price_x64_to_price(
+            sqrt_price_x64,
+            decimals_0,
+            decimals_1,
+        ))
+    }
+}
+
+impl ObservationState {
+    pub const LEN: usize = OBSERVATIONS_OFFSET + OBSERVATION_LEN * OBSERVATION_NUM + 8 * 4;
+
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < Self::LEN || data[..8] != anchor_discriminator("account:ObservationState") {
+            anyhow::bail!("not a CLMM observation state");
+        }
+        let observations = (0..OBSERVATION_NUM)
+            .map(|index| {
+                let offset = OBSERVATIONS_OFFSET + index * OBSERVATION_LEN;
+                Observation {
+                    block_timestamp: u32::from_le_bytes(*array_ref![data, offset, 4]),
+                    tick_cumulative: i64::from_le_bytes(*array_ref![data, offset + 4, 8]),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            initialized: data[8] != 0,
+            recent_epoch: u64::from_le_bytes(*array_ref![data, 9, 8]),
+            observation_index: u16::from_le_bytes(*array_ref![data, 17, 2]),
+            pool_id: Pubkey::new_from_array(*array_ref![data, 19, 32]),
+            observations,
+        })
+    }
+
+    /// Fetches and decodes the observation account of a pool
+    pub async fn load(rpc: &RpcClient, observation_key: &Pubkey) -> anyhow::Result<Self> {
+        Self::decode(&rpc.get_account(observation_key).await?.data)
+    }
+
+    /// Written observations, oldest first
+    pub fn chronological(&self) -> impl Iterator<Item = &Observation> {
+        let split = (self.observation_index as usize + 1).min(self.observations.len());
+        self.observations[split..]
+            .iter()
+            .chain(&self.observations[..split])
+            .filter(|observation| observation.block_timestamp != 0)
+    }
+
+    /// Timestamp of the oldest observation, the furthest a window may reach back
+    pub fn oldest_timestamp(&self) -> Option<u32> {
+        self.chronological()
+            .next()
+            .map(|observation| observation.block_timestamp)
+    }
+
+    /// Tick cumulative at `timestamp`. Past the latest observation the pool has been at
+    /// `tick_current` since, between two observations the cumulative is interpolated.
+    pub fn tick_cumulative_at(
+        &self,
+        timestamp: u32,
+        tick_current: i32,
+    ) -> Result<i64, OracleError> {
+        let latest = self
+            .observations
+            .get(self.observation_index as usize)
+            .filter(|latest| self.initialized && latest.block_timestamp != 0)
+            .ok_or(OracleError::NotInitialized)?;
+        if timestamp >= latest.block_timestamp {
+            let elapsed = (timestamp - latest.block_timestamp) as i64;
+            return Ok(latest.tick_cumulative + tick_current as i64 * elapsed);
+        }
+
+        let mut before: Option<&Observation> = None;
+        for observation in self.chronological() {
+            if observation.block_timestamp > timestamp {
+                let Some(before) = before else {
+                    break;
+                };
+                let span = (observation.block_timestamp - before.block_timestamp) as i128;
+                let elapsed = (timestamp - before.block_timestamp) as i128;
+                let delta = (observation.tick_cumulative - before.tick_cumulative) as i128;
+                return Ok(before.tick_cumulative + (delta * elapsed / span) as i64);
+            }
+            before = Some(observation);
+        }
+
+        let oldest = self.oldest_timestamp().unwrap_or(latest.block_timestamp);
+        Err(OracleError::HistoryTooShort(oldest))
+    }
+
+    /// Time-weighted average tick over the `window_secs` before `now`, rounded towards
+    /// negative infinity like the Uniswap oracle does
+    pub fn twap(&self, window_secs: u32, now: u32, tick_current: i32) -> Result<Twap, OracleError> {
+        if window_secs == 0 {
+            return Err(OracleError::EmptyWindow);
+        }
+        let start = now
+            .checked_sub(window_secs)
+            .ok_or(OracleError::HistoryTooShort(0))?;
+        let delta = self.tick_cumulative_at(now, tick_current)?
+            - self.tick_cumulative_at(start, tick_current)?;
+
+        Ok(Twap {
+            window_secs,
+            average_tick: delta.div_euclid(window_secs as i64) as i32,
+        })
+    }
+}
@@ -1,47 +1,158 @@
-use std::{collections::VecDeque, future::Future, pin::Pin, str::FromStr, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
-use anchor_client::{Client, Cluster};
+use anchor_client::{Client, Cluster, Program};
 use anchor_lang::{prelude::AccountMeta, AccountDeserialize};
-use arrayref::array_ref;
-use futures::{future::Join, StreamExt};
 use raydium_amm_v3::states::{
-    AmmConfig, PoolState, TickArrayBitmapExtension, TickArrayState, POOL_TICK_ARRAY_BITMAP_SEED,
+    PoolState, TickArrayBitmapExtension, TickArrayState, POOL_TICK_ARRAY_BITMAP_SEED,
+};
+use solana_account_decoder::UiAccount;
+use solana_client::{
+    nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient},
+    rpc_response::Response as RpcResponse,
 };
-use solana_client::nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient};
 use solana_sdk::{
     account::Account,
-    commitment_config::{CommitmentConfig, CommitmentLevel},
-    compute_budget::ComputeBudgetInstruction,
+    instruction::Instruction,
     pubkey::Pubkey,
-    signature::Keypair,
-    signer::Signer,
-    transaction::Transaction,
+    signature::{Keypair, Signature},
 };
 use spl_token_2022::{
     extension::{
         transfer_fee::TransferFeeConfig, BaseState, BaseStateWithExtensions, StateWithExtensionsMut,
     },
-    state::{Account as TokenAccount, Mint},
+    state::Mint,
 };
 use tokio::{
-    sync::{broadcast, mpsc},
+    sync::{broadcast, mpsc, oneshot},
     task::JoinHandle,
 };
 
-use crate::solclient::utils::{amount_with_slippage, swap_v2_instr};
+use crate::{
+    constants::{FETCHED_TICK_ARRAY_TTL, MAX_SWAP_TICK_ARRAYS, TICK_ARRAY_BATCH},
+    error::SwapError,
+    solclient::utils::swap_v2_instr,
+};
 
-use super::utils::{self, TransactionResult, SimulateDebugResult, DebugSwapParams};
+use super::engine::QuoteEngine;
+use super::market::{other_amount_threshold, AnyPool, PoolKind, SwapParams, SwapPool};
+use super::oracle::{ObservationState, Twap};
+use super::pubsub::{self, AccountListener, AccountStreams, PoolEvent};
+use super::utils;
 use serde::Serialize;
 
 #[derive(Clone)]
 pub struct Pool {
     id: Pubkey,
+    raydium_program_id: Pubkey,
     mint0: Vec<u8>,
     mint1: Vec<u8>,
-    amm_config: AmmConfig,
-    pub state: PoolState,
-    tick_array: VecDeque<TickArrayState>,
-    tick_array_bitmap_extension: TickArrayBitmapExtension,
+    engine: QuoteEngine,
+    /// Upper bound of tick arrays a single quote may load
+    max_tick_arrays: usize,
+    /// Price history of the pool, loaded once the pool is listened to
+    observation: Option<Arc<ObservationState>>,
+    /// Tick arrays quotes fetched beyond the ones of `engine`, shared by every copy of
+    /// the pool, so that the next quote does not fetch them again
+    fetched: Arc<Mutex<HashMap<i32, (Instant, TickArrayState)>>>,
+}
+
+/// Amounts as seen from the user's token accounts, token-2022 transfer fees included
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Quote {
+    /// What leaves the input account
+    pub amount_in: u64,
+    /// What lands in the output account
+    pub amount_out: u64,
+    /// Whether the price limit stopped the swap before the specified amount was filled
+    pub limited: bool,
+    /// Pool price before the swap
+    pub sqrt_price_before_x64: u128,
+    /// Pool price the swap leaves behind
+    pub sqrt_price_after_x64: u128,
+    /// Trade fee paid to the pool, in the input mint
+    pub fee_amount: u64,
+    /// Initialized ticks crossed, i.e. liquidity changes along the way
+    pub ticks_crossed: u32,
+}
+
+/// Where a price tick was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickSource {
+    /// A pool state change a listener saw, its volume is an estimate
+    Live,
+    /// The `event_index`th swap event of the pool in transaction `signature`, its volume
+    /// is the swapped amount
+    Backfill {
+        signature: Signature,
+        event_index: u32,
+    },
+}
+
+impl TickSource {
+    /// Name stored in the `source` column
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Live => "live",
+            Self::Backfill { .. } => "backfill",
+        }
+    }
+}
+
+/// A pool state a listener saw, what candles are built from
+#[derive(Debug, Clone, Copy)]
+pub struct PriceTick {
+    pub pool: Pubkey,
+    pub slot: u64,
+    /// Unix time in milliseconds the update arrived at
+    pub observed_at: i64,
+    pub sqrt_price_x64: u128,
+    pub tick: i32,
+    pub liquidity: u128,
+    /// Of mint0 in mint1, decimals applied
+    pub price: f64,
+    /// Mint0 that moved through the pool since `previous`, decimals applied. Estimated
+    /// from the price change at the current liquidity, so swaps that cancel out between
+    /// two updates are missed.
+    pub volume0: f64,
+    pub source: TickSource,
+}
+
+impl PriceTick {
+    pub fn new(pool: Pubkey, slot: u64, previous: &PoolState, current: &PoolState) -> Self {
+        let observed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_millis() as i64);
+        // virtual mint0 reserve is L / sqrt(P), its change is what was swapped
+        let q64 = (1u128 << 64) as f64;
+        let volume0 = match (previous.sqrt_price_x64, current.sqrt_price_x64) {
+            (0, _) | (_, 0) => 0f64,
+            (from, to) => {
+                current.liquidity as f64 * (q64 / to as f64 - q64 / from as f64).abs()
+                    / 10f64.powi(current.mint_decimals_0 as i32)
+            }
+        };
+
+        Self {
+            pool,
+            slot,
+            observed_at,
+            sqrt_price_x64: current.sqrt_price_x64,
+            tick: current.tick_current,
+            liquidity: current.liquidity,
+            price: utils::sqrt_price_x64_to_price(
+                current.sqrt_price_x64,
+                current.mint_decimals_0,
+                current.mint_decimals_1,
+            ),
+            volume0,
+            source: TickSource::Live,
+        }
+    }
 }
 
 pub struct LoadPoolAccounts {
@@ -52,36 +163,154 @@ pub struct LoadPoolAccounts {
     pub mint1: Pubkey,
 }
 
-type UnsubscribeFn =
-    Join<Pin<Box<dyn Future<Output = ()> + Send>>, Pin<Box<dyn Future<Output = ()> + Send>>>;
-
 pub struct UnsubscribeHandle {
     handle: JoinHandle<std::result::Result<(), anyhow::Error>>,
-    rx: mpsc::Receiver<UnsubscribeFn>,
+    shutdown: oneshot::Sender<()>,
 }
 
 impl UnsubscribeHandle {
-    pub async fn unsubscribe(mut self) {
-        if let Some(unsubscribe) = self.rx.recv().await {
-            unsubscribe.await;
-        }
+    pub(super) fn new(
+        handle: JoinHandle<std::result::Result<(), anyhow::Error>>,
+        shutdown: oneshot::Sender<()>,
+    ) -> Self {
+        Self { handle, shutdown }
+    }
+
+    /// Whether the listener stopped on its own, e.g. because the stream ended
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
 
+    /// Stops the listener, which unsubscribes all of its accounts
+    pub async fn unsubscribe(self) {
+        let _ = self.shutdown.send(());
         let _ = self.handle.await;
     }
 }
 
+/// Accounts a pool listener is subscribed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum ListenedAccount {
+    PoolState,
+    TickArrayBitmapExtension,
+    AmmConfig,
+    Mint0,
+    Mint1,
+    Observation,
+    TickArray(i32),
+}
+
+/// Keeps a CLMM pool up to date, see [`Pool::listen`]
+struct ClmmListener {
+    pool: Pool,
+    ticks: mpsc::UnboundedSender<PriceTick>,
+}
+
+impl AccountListener for ClmmListener {
+    type Account = ListenedAccount;
+
+    async fn start(
+        &mut self,
+        rpc: &RpcClient,
+        streams: &mut AccountStreams<'_, ListenedAccount>,
+    ) -> anyhow::Result<()> {
+        let pool = &mut self.pool;
+        let (tick_array_bitmap_extension_pubkey, _) = Pubkey::find_program_address(
+            &[POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(), pool.id.as_ref()],
+            &pool.raydium_program_id,
+        );
+        let state = *pool.state();
+        // the feed works without a price history, TWAPs just are not available
+        match ObservationState::load(rpc, &state.observation_key).await {
+            Ok(observation) => pool.observation = Some(Arc::new(observation)),
+            Err(e) => log::warn!("No observations for pool {}: {}", pool.id, e),
+        }
+
+        for (account, pubkey) in [
+            (ListenedAccount::PoolState, pool.id),
+            (
+                ListenedAccount::TickArrayBitmapExtension,
+                tick_array_bitmap_extension_pubkey,
+            ),
+            (ListenedAccount::AmmConfig, state.amm_config),
+            (ListenedAccount::Mint0, state.token_mint_0),
+            (ListenedAccount::Mint1, state.token_mint_1),
+            (ListenedAccount::Observation, state.observation_key),
+        ] {
+            streams.subscribe(account, &pubkey).await?;
+        }
+        sync_tick_array_subscriptions(rpc, pool, streams).await
+    }
+
+    async fn update(
+        &mut self,
+        rpc: &RpcClient,
+        streams: &mut AccountStreams<'_, ListenedAccount>,
+        account: ListenedAccount,
+        response: RpcResponse<UiAccount>,
+    ) -> anyhow::Result<Option<AnyPool>> {
+        let pool = &mut self.pool;
+        let data = response
+            .value
+            .decode::<Account>()
+            .ok_or_else(|| anyhow::anyhow!("unable to decode {:?}", account))?;
+
+        match account {
+            ListenedAccount::PoolState => {
+                let previous = *pool.state();
+                pool.engine
+                    .set_pool_state(deserialize_anchor_account(&data)?);
+                let slot = response.context.slot;
+                let tick = PriceTick::new(pool.id, slot, &previous, pool.state());
+                let _ = self.ticks.send(tick);
+            }
+            ListenedAccount::TickArrayBitmapExtension => {
+                let extension = deserialize_anchor_account(&data)?;
+                pool.engine.set_tick_array_bitmap_extension(extension);
+            }
+            ListenedAccount::AmmConfig => {
+                pool.engine
+                    .set_amm_config(deserialize_anchor_account(&data)?);
+            }
+            ListenedAccount::Mint0 => pool.mint0 = data.data,
+            ListenedAccount::Mint1 => pool.mint1 = data.data,
+            ListenedAccount::Observation => match ObservationState::decode(&data.data) {
+                Ok(observation) => pool.observation = Some(Arc::new(observation)),
+                Err(e) => log::warn!("Observations of {}: {}", pool.id, e),
+            },
+            ListenedAccount::TickArray(_) => {
+                // a late notification of an array that already left the window
+                if !streams.is_subscribed(&account) {
+                    return Ok(None);
+                }
+                let tick_array = deserialize_anchor_account::<TickArrayState>(&data)?;
+                pool.engine.insert_tick_arrays([tick_array]);
+            }
+        }
+        // the current tick or the initialized tick arrays may have moved
+        if matches!(
+            account,
+            ListenedAccount::PoolState | ListenedAccount::TickArrayBitmapExtension
+        ) {
+            sync_tick_array_subscriptions(rpc, pool, streams).await?;
+        }
+        Ok(Some(AnyPool::Clmm(pool.clone())))
+    }
+}
+
 impl std::fmt::Debug for Pool {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Pool")
             .field("id", &self.id)
             .field("mint0", &self.mint0)
             .field("mint1", &self.mint1)
-            .field("amm_config", &self.amm_config)
-            .field("state", &self.state)
+            .field("amm_config", self.engine.amm_config())
+            .field("state", self.engine.pool_state())
             .field(
                 "tick_array_bitmap_extension",
-                &self.tick_array_bitmap_extension,
+                self.engine.tick_array_bitmap_extension(),
             )
+            .field("tick_arrays", &self.engine.tick_array_count())
             .finish()
     }
 }
@@ -91,7 +320,7 @@ impl Pool {
         rpc: Arc<RpcClient>,
         raydium_program_id: &Pubkey,
         accounts: LoadPoolAccounts,
-        zero_for_one: bool,
+        max_tick_arrays: usize,
     ) -> anyhow::Result<Self> {
         // load mult account
         let load_accounts = vec![
@@ -102,272 +331,328 @@ impl Pool {
             accounts.mint1,
         ];
         let rsps = rpc.get_multiple_accounts(&load_accounts).await?;
-        dbg!(&rsps);
-        let [amm_config_account, pool_account, tickarray_bitmap_extension_account, mint0_account, mint1_account] =
-            array_ref![rsps, 0, 5];
-
-        let mint0_data = mint0_account.clone().unwrap().data;
-        let mint1_data = mint1_account.clone().unwrap().data;
-        let amm_config_state = deserialize_anchor_account::<raydium_amm_v3::states::AmmConfig>(
-            amm_config_account.as_ref().unwrap(),
-        )?;
-        let pool_state = deserialize_anchor_account::<raydium_amm_v3::states::PoolState>(
-            pool_account.as_ref().unwrap(),
-        )?;
-        let tickarray_bitmap_extension =
-            deserialize_anchor_account::<raydium_amm_v3::states::TickArrayBitmapExtension>(
-                tickarray_bitmap_extension_account.as_ref().unwrap(),
-            )?;
-
-        // let zero_for_one = true;
-        println!("zero_for_one: {}", zero_for_one);
+        // a wrong mint order or fee index derives an address nobody created
+        let existing = |index: usize| {
+            rsps[index]
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("account {} does not exist", load_accounts[index]))
+        };
 
-        // load tick_arrays
-        let tick_arrays = load_cur_and_next_five_tick_array(
+        let amm_config_state =
+            deserialize_anchor_account::<raydium_amm_v3::states::AmmConfig>(existing(0)?)?;
+        let pool_state =
+            deserialize_anchor_account::<raydium_amm_v3::states::PoolState>(existing(1)?)?;
+        let tickarray_bitmap_extension = deserialize_anchor_account::<
+            raydium_amm_v3::states::TickArrayBitmapExtension,
+        >(existing(2)?)?;
+        let mint0_data = existing(3)?.data.clone();
+        let mint1_data = existing(4)?.data.clone();
+
+        // load tick_arrays, further ones are fetched when a quote walks into them
+        let tick_arrays = load_tick_arrays_around(
             &rpc,
             raydium_program_id,
             &accounts.pool_id,
             &pool_state,
             &tickarray_bitmap_extension,
-            zero_for_one,
+            max_tick_arrays,
         )
         .await?;
 
         Ok(Self {
             id: accounts.pool_id,
+            raydium_program_id: *raydium_program_id,
             mint0: mint0_data,
             mint1: mint1_data,
-            amm_config: amm_config_state,
-            state: pool_state,
-            tick_array: tick_arrays,
-            tick_array_bitmap_extension: tickarray_bitmap_extension,
+            engine: QuoteEngine::new(
+                amm_config_state,
+                pool_state,
+                tickarray_bitmap_extension,
+                tick_arrays,
+            ),
+            max_tick_arrays,
+            observation: None,
+            fetched: Default::default(),
         })
     }
 
+    /// A pool over already decoded accounts, e.g. a fixture, `mint0`/`mint1` are the raw
+    /// mint account data. Quotes at an epoch work without any RPC access.
+    pub fn from_engine(
+        id: Pubkey,
+        raydium_program_id: Pubkey,
+        mint0: Vec<u8>,
+        mint1: Vec<u8>,
+        engine: QuoteEngine,
+        max_tick_arrays: usize,
+    ) -> Self {
+        Self {
+            id,
+            raydium_program_id,
+            mint0,
+            mint1,
+            engine,
+            max_tick_arrays,
+            observation: None,
+            fetched: Default::default(),
+        }
+    }
+
+    pub fn id(&self) -> Pubkey {
+        self.id
+    }
+
+    pub fn state(&self) -> &PoolState {
+        self.engine.pool_state()
+    }
+
+    /// The observation account as last seen by the listener
+    pub fn observation(&self) -> Option<&ObservationState> {
+        self.observation.as_deref()
+    }
+
+    /// Time-weighted averages over each of `windows` up to `now`, see
+    /// [`ObservationState::twap`]
+    pub fn twaps(&self, windows: &[u32], now: u32) -> anyhow::Result<Vec<Twap>> {
+        let observation = self
+            .observation()
+            .ok_or_else(|| anyhow::anyhow!("observations of pool {} not loaded", self.id))?;
+        windows
+            .iter()
+            .map(|window| Ok(observation.twap(*window, now, self.state().tick_current)?))
+            .collect()
+    }
+
+    /// Streams the pool with every change of its accounts, the pool state, bitmap
+    /// extension, amm config, mints and the tick arrays around the current tick. Each
+    /// account keeps its latest value, so any single change is enough for an update.
+    /// Changes of the pool state are reported to `ticks` as well.
     pub async fn listen(
         &self,
         rpc: Arc<RpcClient>,
-        raydium_program_id: Pubkey,
         pubsub: Arc<PubsubClient>,
-        tx: broadcast::Sender<Pool>,
+        tx: broadcast::Sender<PoolEvent>,
+        dropped: mpsc::UnboundedSender<Pubkey>,
+        ticks: mpsc::UnboundedSender<PriceTick>,
     ) -> anyhow::Result<UnsubscribeHandle> {
-        let pool_id = self.id;
-        let mint0 = self.mint0.clone();
-        let mint1 = self.mint1.clone();
-        let amm_config = self.amm_config.clone();
-
-        let (shutdown_tx, rx) = mpsc::channel::<_>(2);
-
-        let handle = tokio::spawn(async move {
-            let config = solana_client::rpc_config::RpcAccountInfoConfig {
-                encoding: Some(solana_account_decoder::UiAccountEncoding::Base64Zstd),
-                data_slice: None,
-                commitment: Some(solana_sdk::commitment_config::CommitmentConfig {
-                    commitment: CommitmentLevel::Processed,
-                }),
-                min_context_slot: None,
-            };
-            let (state, state_unsub) = pubsub
-                .account_subscribe(&pool_id, Some(config.clone()))
-                .await?;
-            let (tick_array_bitmap_extension_pubkey, _) = Pubkey::find_program_address(
-                &[POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(), pool_id.as_ref()],
-                &raydium_program_id,
-            );
-            let (tick_array_bitmap, tick_array_bitmap_unsub) = pubsub
-                .account_subscribe(&tick_array_bitmap_extension_pubkey, Some(config))
-                .await?;
-            let unsub = futures::future::join(state_unsub(), tick_array_bitmap_unsub());
-            shutdown_tx
-                .send(unsub)
-                .await
-                .expect("Cannot send unsub handler to shutdown_tx, this should never happen");
-            let mut hose = state.zip(tick_array_bitmap);
-
-            while let Some((state, bitmap)) = hose.next().await {
-                let pool_state = deserialize_anchor_account::<raydium_amm_v3::states::PoolState>(
-                    state.value.decode().as_ref().unwrap(),
-                )?;
-                let tickarray_bitmap_extension =
-                    deserialize_anchor_account::<raydium_amm_v3::states::TickArrayBitmapExtension>(
-                        bitmap.value.decode().as_ref().unwrap(),
-                    )?;
-                let tick_arrays = load_cur_and_next_five_tick_array(
-                    &rpc,
-                    &raydium_program_id,
-                    &tickarray_bitmap_extension.pool_id,
-                    &pool_state,
-                    &tickarray_bitmap_extension,
-                    true,
-                )
-                .await?;
-
-                tx.send(Pool {
-                    id: tickarray_bitmap_extension.pool_id,
-                    mint0: mint0.clone(),
-                    mint1: mint1.clone(),
-                    amm_config: amm_config.clone(),
-                    state: pool_state,
-                    tick_array: tick_arrays,
-                    tick_array_bitmap_extension: tickarray_bitmap_extension,
-                })?;
-            }
-
-            Ok::<_, anyhow::Error>(())
-        });
-
-        Ok(UnsubscribeHandle { handle, rx })
+        let listener = ClmmListener {
+            pool: self.clone(),
+            ticks,
+        };
+        pubsub::listen(self.id, listener, rpc, pubsub, tx, dropped).await
     }
 
-    pub async fn quote(
+    /// Same as [`SwapPool::quote`] but without touching the RPC, `epoch` is used to pick
+    /// the active token-2022 transfer fee. Only the tick arrays already loaded are used.
+    pub fn quote_at_epoch(
         &self,
-        rpc: Arc<RpcClient>,
+        epoch: u64,
         amount: u64,
-        _sqrt_price_limit_x64: u128, // TODO: Would be nice to implement
+        sqrt_price_limit_x64: Option<u128>,
         zero_for_one: bool,
         is_base_input: bool,
-    ) -> anyhow::Result<u64> {
-        let epoch = rpc.get_epoch_info().await?.epoch;
-        let mut mint0 = self.mint0.clone();
-        let mut mint1 = self.mint1.clone();
-        let mint0_state = StateWithExtensionsMut::<Mint>::unpack(&mut mint0)?;
-        let mint1_state = StateWithExtensionsMut::<Mint>::unpack(&mut mint1)?;
-        let transfer_fee = if is_base_input {
-            if zero_for_one {
-                get_transfer_fee(&mint0_state, epoch, amount)
-            } else {
-                get_transfer_fee(&mint1_state, epoch, amount)
-            }
-        } else {
-            0
-        };
-        let amount_specified = amount.checked_sub(transfer_fee).unwrap();
-
-        let sqrt_price_limit_x64 = None;
-
-        let (other_amount_threshold, _) = utils::get_out_put_amount_and_remaining_accounts(
-            amount_specified,
+    ) -> anyhow::Result<Quote> {
+        let (quote, _) = self.simulate_swap(
+            epoch,
+            amount,
             sqrt_price_limit_x64,
             zero_for_one,
             is_base_input,
-            &self.amm_config,
-            &self.state,
-            &self.tick_array_bitmap_extension,
-            &mut self.tick_array.clone(),
-        )
-        .unwrap();
+        )?;
 
-        Ok(other_amount_threshold)
+        Ok(quote)
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub async fn swap(
+    /// Fetches and keeps the tick arrays a swap of `amount` walks into, so that any
+    /// smaller swap in the same direction can be quoted with [`Pool::quote_at_epoch`].
+    /// Stops early without an error when the pool cannot fill the whole amount.
+    pub async fn preload_tick_arrays(
         &self,
-        rpc: Arc<RpcClient>,
-        raydium_program_id: &Pubkey,
-        payer: Keypair,
-        input_token: Pubkey,
-        output_token: Pubkey,
+        rpc: &RpcClient,
+        epoch: u64,
         amount: u64,
-        slippage: f64,
+        zero_for_one: bool,
         is_base_input: bool,
-        simulate: bool,
+    ) -> anyhow::Result<()> {
+        let simulated = self
+            .simulate_swap_loading(rpc, epoch, amount, None, zero_for_one, is_base_input)
+            .await;
+
+        match simulated {
+            Err(err) if err.downcast_ref::<SwapError>().is_none() => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// [`Pool::simulate_swap`] that fetches the tick arrays the simulated price walks
+    /// into, [`TICK_ARRAY_BATCH`] per round trip and at most `max_tick_arrays` in total.
+    /// The fetched arrays are kept for later quotes for [`FETCHED_TICK_ARRAY_TTL`].
+    /// Returns the start indexes of the tick arrays the swap needs next to the quote.
+    pub async fn simulate_swap_loading(
+        &self,
+        rpc: &RpcClient,
+        epoch: u64,
+        amount: u64,
+        sqrt_price_limit_x64: Option<u128>,
         zero_for_one: bool,
-        fee_bps: Option<u64>,
-    ) -> anyhow::Result<TransactionResult> {
-        let load_accounts = vec![input_token, output_token];
-        let rsps = rpc.get_multiple_accounts(&load_accounts).await?;
-        let epoch = rpc.get_epoch_info().await?.epoch;
-        let [user_input_account, user_output_account] = array_ref![rsps, 0, 2];
-
-        println!("=== user_input_account ===");
-        println!("user_input_account: {:?}", user_input_account);
-        println!("=== user_output_account ===");
-        println!("user_output_account: {:?}", user_output_account);
-        
-        // let mut user_input_token_data = user_input_account.clone().unwrap().data;
-        // let user_input_state =
-        //     StateWithExtensionsMut::<TokenAccount>::unpack(&mut user_input_token_data)?;
-        // let mut user_output_token_data = user_output_account.clone().unwrap().data;
-        // let user_output_state =
-        //     StateWithExtensionsMut::<TokenAccount>::unpack(&mut user_output_token_data)?;
+        is_base_input: bool,
+    ) -> anyhow::Result<(Quote, VecDeque<i32>)> {
+        let mut fetched = 0;
+        loop {
+            let err = match self.simulate_swap(
+                epoch,
+                amount,
+                sqrt_price_limit_x64,
+                zero_for_one,
+                is_base_input,
+            ) {
+                Ok(simulated) => return Ok(simulated),
+                Err(err) => err,
+            };
+            let Some(SwapError::TickArrayNotLoaded(start_index)) = err.downcast_ref() else {
+                return Err(err);
+            };
 
+            let loaded = self.engine.tick_array_count() + fetched;
+            if loaded >= self.max_tick_arrays {
+                return Err(SwapError::TickArrayLimit(self.max_tick_arrays).into());
+            }
+            let start_indexes = tick_array_start_indexes(
+                self.engine.pool_state(),
+                self.engine.tick_array_bitmap_extension(),
+                *start_index,
+                zero_for_one,
+                TICK_ARRAY_BATCH.min(self.max_tick_arrays - loaded),
+            );
+            let tick_arrays =
+                fetch_tick_arrays(rpc, &self.raydium_program_id, &self.id, &start_indexes).await?;
+            fetched += tick_arrays.len();
+
+            let now = Instant::now();
+            let mut cached = self.fetched.lock().unwrap_or_else(|e| e.into_inner());
+            cached.retain(|_, (fetched_at, _)| now - *fetched_at < FETCHED_TICK_ARRAY_TTL);
+            for tick_array in tick_arrays {
+                cached.insert(tick_array.start_tick_index, (now, tick_array));
+            }
+        }
+    }
+
+    /// Runs the swap math against the tick arrays of `engine` and the ones fetched before,
+    /// then applies the transfer fees of both mints, so that the quoted amounts are what
+    /// the user sends/receives. Returns the start indexes of the tick arrays the swap
+    /// needs along with the quote.
+    pub fn simulate_swap(
+        &self,
+        epoch: u64,
+        amount: u64,
+        sqrt_price_limit_x64: Option<u128>,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> anyhow::Result<(Quote, VecDeque<i32>)> {
         let mut mint0 = self.mint0.clone();
         let mut mint1 = self.mint1.clone();
         let mint0_state = StateWithExtensionsMut::<Mint>::unpack(&mut mint0)?;
         let mint1_state = StateWithExtensionsMut::<Mint>::unpack(&mut mint1)?;
-
-        // let zero_for_one = user_input_state.base.mint == self.state.token_mint_0
-        //     && user_output_state.base.mint == self.state.token_mint_1;
-
-        // 前端传入zero_for_one，默认true
-        // let zero_for_one = false;
-        println!("Using zero_for_one = {}", zero_for_one);
-
-        let transfer_fee = if is_base_input {
-            if zero_for_one {
-                get_transfer_fee(&mint0_state, epoch, amount)
-            } else {
-                get_transfer_fee(&mint1_state, epoch, amount)
-            }
+        let (input_mint_state, output_mint_state) = if zero_for_one {
+            (mint0_state, mint1_state)
         } else {
-            0
+            (mint1_state, mint0_state)
         };
-        // 计算 amount_specified，这个值就是用户输入的金额，减去手续费，只有spltoekn 2022代币才有手续费
-        let amount_specified = amount.checked_sub(transfer_fee).unwrap();
-        // load tick_arrays
-        let mut tick_arrays = load_cur_and_next_five_tick_array(
-            &rpc,
-            raydium_program_id,
-            &self.id,
-            &self.state,
-            &self.tick_array_bitmap_extension,
-            zero_for_one,
-        )
-        .await?;
-
-        let mut sqrt_price_limit_x64 = None;
 
-        // 计算基础输出值other_amount_threshold，根据当前池子信息，计算swap最终收到的多少目标代币，还没有考虑滑点
-        let (mut other_amount_threshold, tick_array_indexs) =
-            utils::get_out_put_amount_and_remaining_accounts(
+        // exact input: the pool receives what is left after the input transfer fee
+        // exact output: the pool has to send enough to cover the output transfer fee
+        let amount_specified = if is_base_input {
+            amount.checked_sub(get_transfer_fee(&input_mint_state, epoch, amount))
+        } else {
+            amount.checked_add(utils::get_transfer_inverse_fee(
+                &output_mint_state,
+                epoch,
+                amount,
+            ))
+        }
+        .ok_or_else(|| anyhow::anyhow!("transfer fee overflows amount {}", amount))?;
+
+        let (swap_state, tick_array_indexs) = {
+            let now = Instant::now();
+            let cached = self.fetched.lock().unwrap_or_else(|e| e.into_inner());
+            self.engine.quote_with(
+                |start_index| {
+                    cached
+                        .get(&start_index)
+                        .filter(|(fetched_at, _)| now - *fetched_at < FETCHED_TICK_ARRAY_TTL)
+                        .map(|(_, tick_array)| *tick_array)
+                },
                 amount_specified,
                 sqrt_price_limit_x64,
                 zero_for_one,
                 is_base_input,
-                &self.amm_config,
-                &self.state,
-                &self.tick_array_bitmap_extension,
-                &mut tick_arrays,
-            )
-            .unwrap();
-
-        // 考虑滑点、自定义手续费和spltoken 2022代币的手续费之后，计算最终的输出值other_amount_threshold
-        if is_base_input {
-            // 先计算自定义手续费
-            if let Some(fee_bps) = fee_bps {
-                let custom_fee = (other_amount_threshold as f64 * fee_bps as f64 / 10000.0) as u64;
-                other_amount_threshold = other_amount_threshold.saturating_sub(custom_fee);
-            }
-            // calc mint out amount with slippage
-            other_amount_threshold = amount_with_slippage(other_amount_threshold, slippage, false);
+            )?
+        };
+
+        // the price limit may stop the swap before the specified side is used up, in
+        // which case the user facing amount has to be derived from what the pool used
+        let limited = swap_state.amount_specified_remaining != 0;
+        let amount_specified_used = amount_specified - swap_state.amount_specified_remaining;
+        let amount_calculated = swap_state.amount_calculated;
+
+        let (amount_in, amount_out) = if is_base_input {
+            let amount_in = if limited {
+                let transfer_fee = utils::get_transfer_inverse_fee(
+                    &input_mint_state,
+                    epoch,
+                    amount_specified_used,
+                );
+                (amount_specified_used + transfer_fee).min(amount)
+            } else {
+                amount
+            };
+            let amount_out = amount_calculated
+                - get_transfer_fee(&output_mint_state, epoch, amount_calculated);
+            (amount_in, amount_out)
         } else {
-            // calc max in with slippage
-            other_amount_threshold = amount_with_slippage(other_amount_threshold, slippage, true);
-            // 添加自定义手续费
-            if let Some(fee_bps) = fee_bps {
-                let custom_fee = (other_amount_threshold as f64 * fee_bps as f64 / 10000.0) as u64;
-                other_amount_threshold = other_amount_threshold.saturating_add(custom_fee);
-            }
-            // calc max in with transfer_fee
-            let transfer_fee = if zero_for_one {
-                utils::get_transfer_inverse_fee(&mint0_state, epoch, other_amount_threshold)
+            let amount_in = amount_calculated
+                .checked_add(utils::get_transfer_inverse_fee(
+                    &input_mint_state,
+                    epoch,
+                    amount_calculated,
+                ))
+                .ok_or_else(|| anyhow::anyhow!("transfer fee overflows amount in"))?;
+            let amount_out = if limited {
+                amount_specified_used
+                    - get_transfer_fee(&output_mint_state, epoch, amount_specified_used)
             } else {
-                utils::get_transfer_inverse_fee(&mint1_state, epoch, other_amount_threshold)
+                amount
             };
-            other_amount_threshold += transfer_fee;
-        }
+            (amount_in, amount_out)
+        };
+
+        Ok((
+            Quote {
+                amount_in,
+                amount_out,
+                limited,
+                sqrt_price_before_x64: self.state().sqrt_price_x64,
+                sqrt_price_after_x64: swap_state.sqrt_price_x64,
+                fee_amount: swap_state.fee_amount,
+                ticks_crossed: swap_state.ticks_crossed,
+            },
+            tick_array_indexs,
+        ))
+    }
 
+    /// Tick array bitmap extension and tick arrays a swap passes to the program, fails
+    /// for swaps crossing more than [`MAX_SWAP_TICK_ARRAYS`]
+    pub fn remaining_accounts(
+        &self,
+        raydium_program_id: &Pubkey,
+        tick_array_indexs: VecDeque<i32>,
+    ) -> Result<Vec<AccountMeta>, SwapError> {
+        if tick_array_indexs.len() > MAX_SWAP_TICK_ARRAYS {
+            return Err(SwapError::TooManyTickArrays(
+                tick_array_indexs.len(),
+                MAX_SWAP_TICK_ARRAYS,
+            ));
+        }
         let mut remaining_accounts = Vec::new();
         remaining_accounts.push(AccountMeta::new_readonly(
             Pubkey::find_program_address(
@@ -383,127 +668,151 @@ impl Pool {
         let mut accounts = tick_array_indexs
             .into_iter()
             .map(|index| {
-                AccountMeta::new(
-                    Pubkey::find_program_address(
-                        &[
-                            raydium_amm_v3::states::TICK_ARRAY_SEED.as_bytes(),
-                            self.id.to_bytes().as_ref(),
-                            &index.to_be_bytes(),
-                        ],
-                        raydium_program_id,
-                    )
-                    .0,
-                    false,
-                )
+                AccountMeta::new(tick_array_key(raydium_program_id, &self.id, index), false)
             })
             .collect();
         remaining_accounts.append(&mut accounts);
-        let mut instructions = Vec::new();
-        // 设置计算单元限制
-        let request_inits_instr = ComputeBudgetInstruction::set_compute_unit_limit(1_400_000_u32);
-        instructions.push(request_inits_instr);
-        let cluster = Cluster::Custom("".to_string(), "".to_string());
-        let client = Client::new(cluster, &payer);
-        // 指定了调用的raydium clmm合约地址
-        let clmm = client.program(*raydium_program_id)?;
-
-        let remaining_accounts_str = remaining_accounts.iter().map(|a| a.pubkey.to_string()).collect::<Vec<_>>();
-
-        println!("=== swap_v2_instr 参数打印 ===");
-        println!("clmm: {:?}", clmm.payer());
-        println!("amm_config: {:?}", self.state.amm_config);
-        println!("pool_id: {:?}", self.id);
-        println!("input_vault: {:?}", if zero_for_one { self.state.token_vault_0 } else { self.state.token_vault_1 });
-        println!("output_vault: {:?}", if zero_for_one { self.state.token_vault_1 } else { self.state.token_vault_0 });
-        println!("observation_state: {:?}", self.state.observation_key);
-        println!("user_input_token: {:?}", input_token);
-        println!("user_output_token: {:?}", output_token);
-        println!("input_vault_mint: {:?}", if zero_for_one { self.state.token_mint_0 } else { self.state.token_mint_1 });
-        println!("output_vault_mint: {:?}", if zero_for_one { self.state.token_mint_1 } else { self.state.token_mint_0 });
-        println!("remaining_accounts: {:?}", remaining_accounts);
-        println!("amount: {:?}", amount);
-        println!("other_amount_threshold: {:?}", other_amount_threshold);
-        println!("sqrt_price_limit_x64: {:?}", sqrt_price_limit_x64);
-        println!("is_base_input: {:?}", is_base_input);
-        println!("==============================");
-
-        let swap_instr = swap_v2_instr(
+        Ok(remaining_accounts)
+    }
+
+    /// `swap_v2` against this pool in the given direction
+    #[allow(clippy::too_many_arguments)]
+    pub fn swap_instruction(
+        &self,
+        clmm: Program<&Keypair>,
+        input_token: Pubkey,
+        output_token: Pubkey,
+        zero_for_one: bool,
+        remaining_accounts: Vec<AccountMeta>,
+        amount: u64,
+        other_amount_threshold: u64,
+        sqrt_price_limit_x64: Option<u128>,
+        is_base_input: bool,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        let state = self.state();
+        let (input_vault, output_vault, input_mint, output_mint) = if zero_for_one {
+            (
+                state.token_vault_0,
+                state.token_vault_1,
+                state.token_mint_0,
+                state.token_mint_1,
+            )
+        } else {
+            (
+                state.token_vault_1,
+                state.token_vault_0,
+                state.token_mint_1,
+                state.token_mint_0,
+            )
+        };
+
+        swap_v2_instr(
             clmm,
-            self.state.amm_config,
+            state.amm_config,
             self.id,
-            if zero_for_one {
-                self.state.token_vault_0
-            } else {
-                self.state.token_vault_1
-            },
-            if zero_for_one {
-                self.state.token_vault_1
-            } else {
-                self.state.token_vault_0
-            },
-            self.state.observation_key,
+            input_vault,
+            output_vault,
+            state.observation_key,
             input_token,
             output_token,
-            if zero_for_one {
-                self.state.token_mint_0
-            } else {
-                self.state.token_mint_1
-            },
-            if zero_for_one {
-                self.state.token_mint_1
-            } else {
-                self.state.token_mint_0
-            },
+            input_mint,
+            output_mint,
             remaining_accounts,
             amount,
             other_amount_threshold,
             sqrt_price_limit_x64,
             is_base_input,
         )
-        .unwrap();
-        instructions.extend(swap_instr);
-        // send
-        let signers = vec![&payer];
-        let recent_hash = rpc.get_latest_blockhash().await?;
-        let txn = Transaction::new_signed_with_payer(
-            &instructions,
-            Some(&payer.pubkey()),
-            &signers,
-            recent_hash,
-        );
-        if simulate {
-            let ret = utils::simulate_transaction(&rpc, &txn, true, CommitmentConfig::confirmed())
-                .await?;
-
-            // let remaining_accounts_str = remaining_accounts.iter().map(|a| a.pubkey.to_string()).collect::<Vec<_>>();
-            let debug_info = DebugSwapParams {
-                amm_config: self.state.amm_config.to_string(),
-                pool_id: self.id.to_string(),
-                input_vault: (if zero_for_one { self.state.token_vault_0 } else { self.state.token_vault_1 }).to_string(),
-                output_vault: (if zero_for_one { self.state.token_vault_1 } else { self.state.token_vault_0 }).to_string(),
-                observation_state: self.state.observation_key.to_string(),
-                user_input_token: input_token.to_string(),
-                user_output_token: output_token.to_string(),
-                input_vault_mint: (if zero_for_one { self.state.token_mint_0 } else { self.state.token_mint_1 }).to_string(),
-                output_vault_mint: (if zero_for_one { self.state.token_mint_1 } else { self.state.token_mint_0 }).to_string(),
-                remaining_accounts: remaining_accounts_str,
+    }
+}
+
+impl SwapPool for Pool {
+    fn id(&self) -> Pubkey {
+        self.id
+    }
+
+    fn program_id(&self) -> Pubkey {
+        self.raydium_program_id
+    }
+
+    fn kind(&self) -> PoolKind {
+        PoolKind::Clmm
+    }
+
+    fn mints(&self) -> (Pubkey, Pubkey) {
+        (self.state().token_mint_0, self.state().token_mint_1)
+    }
+
+    fn mint_decimals(&self) -> (u8, u8) {
+        (self.state().mint_decimals_0, self.state().mint_decimals_1)
+    }
+
+    fn sqrt_price_x64(&self) -> u128 {
+        self.state().sqrt_price_x64
+    }
+
+    async fn quote(
+        &self,
+        rpc: Arc<RpcClient>,
+        epoch: u64,
+        amount: u64,
+        sqrt_price_limit_x64: Option<u128>,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> anyhow::Result<Quote> {
+        let (quote, _) = self
+            .simulate_swap_loading(
+                &rpc,
+                epoch,
                 amount,
-                other_amount_threshold,
                 sqrt_price_limit_x64,
+                zero_for_one,
                 is_base_input,
-            };
+            )
+            .await?;
 
-            let result = SimulateDebugResult {
-                simulation: serde_json::to_value(ret.value).unwrap(),
-                debug: debug_info,
-            };
+        Ok(quote)
+    }
 
-            return Ok(TransactionResult::SimulateDebug(result));
-            // return Ok(TransactionResult::Simulate(ret.value));
-        }
+    async fn swap_instructions(
+        &self,
+        rpc: &RpcClient,
+        payer: &Keypair,
+        params: &SwapParams,
+    ) -> anyhow::Result<(Quote, Vec<Instruction>)> {
+        let epoch = rpc.get_epoch_info().await?.epoch;
+        let (quote, tick_array_indexs) = self
+            .simulate_swap_loading(
+                rpc,
+                epoch,
+                params.amount,
+                params.sqrt_price_limit_x64,
+                params.zero_for_one,
+                params.is_base_input,
+            )
+            .await?;
+        let threshold = other_amount_threshold(
+            &quote,
+            params.slippage,
+            params.is_base_input,
+            params.fee_bps,
+        );
 
-        let signature = utils::send_txn(&rpc, &txn, true).await?;
-        Ok(TransactionResult::Send(signature.to_string()))
+        let cluster = Cluster::Custom("".to_string(), "".to_string());
+        let client = Client::new(cluster, payer);
+        let instructions = self.swap_instruction(
+            client.program(self.raydium_program_id)?,
+            params.input_token,
+            params.output_token,
+            params.zero_for_one,
+            self.remaining_accounts(&self.raydium_program_id, tick_array_indexs)?,
+            params.amount,
+            threshold,
+            params.sqrt_price_limit_x64,
+            params.is_base_input,
+        )?;
+
+        Ok((quote, instructions))
     }
 }
 
@@ -512,66 +821,173 @@ pub fn deserialize_anchor_account<T: AccountDeserialize>(account: &Account) -> a
     T::try_deserialize(&mut data).map_err(Into::into)
 }
 
-async fn load_cur_and_next_five_tick_array(
+fn tick_array_key(raydium_program_id: &Pubkey, pool_id: &Pubkey, start_index: i32) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            raydium_amm_v3::states::TICK_ARRAY_SEED.as_bytes(),
+            pool_id.to_bytes().as_ref(),
+            &start_index.to_be_bytes(),
+        ],
+        raydium_program_id,
+    )
+    .0
+}
+
+/// `start_index` followed by the next initialized tick arrays in swap direction, `count`
+/// start indexes at most
+fn tick_array_start_indexes(
+    pool_state: &PoolState,
+    tickarray_bitmap_extension: &TickArrayBitmapExtension,
+    mut start_index: i32,
+    zero_for_one: bool,
+    count: usize,
+) -> Vec<i32> {
+    let mut start_indexes = Vec::with_capacity(count);
+    while start_indexes.len() < count {
+        start_indexes.push(start_index);
+        match pool_state.next_initialized_tick_array_start_index(
+            &Some(*tickarray_bitmap_extension),
+            start_index,
+            zero_for_one,
+        ) {
+            Ok(Some(next_start_index)) => start_index = next_start_index,
+            _ => break,
+        }
+    }
+    start_indexes
+}
+
+async fn fetch_tick_arrays(
     rpc_client: &RpcClient,
     raydium_program_id: &Pubkey,
     pool_id: &Pubkey,
+    start_indexes: &[i32],
+) -> anyhow::Result<Vec<TickArrayState>> {
+    let tick_array_keys = start_indexes
+        .iter()
+        .map(|start_index| tick_array_key(raydium_program_id, pool_id, *start_index))
+        .collect::<Vec<_>>();
+    let tick_array_rsps = rpc_client.get_multiple_accounts(&tick_array_keys).await?;
+
+    tick_array_rsps
+        .into_iter()
+        .zip(start_indexes)
+        .map(|(tick_array, start_index)| {
+            let tick_array = tick_array
+                .ok_or_else(|| anyhow::anyhow!("tick array {} does not exist", start_index))?;
+            deserialize_anchor_account::<TickArrayState>(&tick_array)
+        })
+        .collect()
+}
+
+/// Start indexes of the tick array the current price is in and of the next initialized
+/// ones on both sides of it
+fn tick_array_window(
     pool_state: &PoolState,
     tickarray_bitmap_extension: &TickArrayBitmapExtension,
-    zero_for_one: bool,
-) -> anyhow::Result<VecDeque<TickArrayState>> {
-    let (_, mut current_vaild_tick_array_start_index) = pool_state
-        .get_first_initialized_tick_array(&Some(*tickarray_bitmap_extension), zero_for_one)
-        .unwrap();
-    let mut tick_array_keys = Vec::new();
-    tick_array_keys.push(
-        Pubkey::find_program_address(
-            &[
-                raydium_amm_v3::states::TICK_ARRAY_SEED.as_bytes(),
-                pool_id.to_bytes().as_ref(),
-                &current_vaild_tick_array_start_index.to_be_bytes(),
-            ],
-            raydium_program_id,
-        )
-        .0,
+    max_tick_arrays: usize,
+) -> Vec<i32> {
+    let count = TICK_ARRAY_BATCH.min(max_tick_arrays / 2).max(1);
+    let mut start_indexes = Vec::new();
+    for zero_for_one in [true, false] {
+        // no initialized tick array in this direction at all
+        let Ok((_, first_start_index)) = pool_state
+            .get_first_initialized_tick_array(&Some(*tickarray_bitmap_extension), zero_for_one)
+        else {
+            continue;
+        };
+        for start_index in tick_array_start_indexes(
+            pool_state,
+            tickarray_bitmap_extension,
+            first_start_index,
+            zero_for_one,
+            count,
+        ) {
+            if !start_indexes.contains(&start_index) {
+                start_indexes.push(start_index);
+            }
+        }
+    }
+    start_indexes
+}
+
+/// Addresses of the tick arrays of [`tick_array_window`]
+pub fn tick_array_keys_around(
+    raydium_program_id: &Pubkey,
+    pool_id: &Pubkey,
+    pool_state: &PoolState,
+    tickarray_bitmap_extension: &TickArrayBitmapExtension,
+    max_tick_arrays: usize,
+) -> Vec<Pubkey> {
+    tick_array_window(pool_state, tickarray_bitmap_extension, max_tick_arrays)
+        .into_iter()
+        .map(|start_index| tick_array_key(raydium_program_id, pool_id, start_index))
+        .collect()
+}
+
+/// Loads the tick arrays of [`tick_array_window`], which covers most quotes without
+/// another round trip
+async fn load_tick_arrays_around(
+    rpc_client: &RpcClient,
+    raydium_program_id: &Pubkey,
+    pool_id: &Pubkey,
+    pool_state: &PoolState,
+    tickarray_bitmap_extension: &TickArrayBitmapExtension,
+    max_tick_arrays: usize,
+) -> anyhow::Result<Vec<TickArrayState>> {
+    let start_indexes = tick_array_window(pool_state, tickarray_bitmap_extension, max_tick_arrays);
+    fetch_tick_arrays(rpc_client, raydium_program_id, pool_id, &start_indexes).await
+}
+
+/// Moves the tick array subscriptions of a listener along with the current tick: arrays
+/// that left the window are unsubscribed and dropped, new ones are subscribed and, as
+/// PubSub only reports changes, fetched once.
+async fn sync_tick_array_subscriptions(
+    rpc_client: &RpcClient,
+    pool: &mut Pool,
+    streams: &mut AccountStreams<'_, ListenedAccount>,
+) -> anyhow::Result<()> {
+    let window = tick_array_window(
+        pool.state(),
+        pool.engine.tick_array_bitmap_extension(),
+        pool.max_tick_arrays,
     );
-    let mut max_array_size = 5;
-    while max_array_size != 0 {
-        let next_tick_array_index = pool_state
-            .next_initialized_tick_array_start_index(
-                &Some(*tickarray_bitmap_extension),
-                current_vaild_tick_array_start_index,
-                zero_for_one,
-            )
-            .unwrap();
-        if next_tick_array_index.is_none() {
-            break;
+
+    let left = streams
+        .accounts()
+        .filter_map(|account| match account {
+            ListenedAccount::TickArray(start_index) if !window.contains(start_index) => {
+                Some(*account)
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for account in left {
+        streams.unsubscribe(&account).await;
+    }
+    pool.engine
+        .retain_tick_arrays(|start_index| window.contains(&start_index));
+
+    let mut unknown = Vec::new();
+    for start_index in window {
+        let account = ListenedAccount::TickArray(start_index);
+        if streams.is_subscribed(&account) {
+            continue;
+        }
+        let key = tick_array_key(&pool.raydium_program_id, &pool.id, start_index);
+        streams.subscribe(account, &key).await?;
+        if !pool.engine.has_tick_array(start_index) {
+            unknown.push(start_index);
         }
-        current_vaild_tick_array_start_index = next_tick_array_index.unwrap();
-        tick_array_keys.push(
-            Pubkey::find_program_address(
-                &[
-                    raydium_amm_v3::states::TICK_ARRAY_SEED.as_bytes(),
-                    pool_id.to_bytes().as_ref(),
-                    &current_vaild_tick_array_start_index.to_be_bytes(),
-                ],
-                raydium_program_id,
-            )
-            .0,
-        );
-        max_array_size -= 1;
     }
-    let tick_array_rsps = rpc_client.get_multiple_accounts(&tick_array_keys).await?;
-    let mut tick_arrays = VecDeque::new();
-    for tick_array in tick_array_rsps {
-        let tick_array_state =
-            deserialize_anchor_account::<raydium_amm_v3::states::TickArrayState>(
-                &tick_array.unwrap(),
-            )
-            .unwrap();
-        tick_arrays.push_back(tick_array_state);
+    // fetched after subscribing so that no change falls in between
+    if !unknown.is_empty() {
+        let tick_arrays =
+            fetch_tick_arrays(rpc_client, &pool.raydium_program_id, &pool.id, &unknown).await?;
+        pool.engine.insert_tick_arrays(tick_arrays);
     }
-    Ok(tick_arrays)
+
+    Ok(())
 }
 
 pub fn get_transfer_fee<S: BaseState>(
@@ -588,17 +1004,3 @@ pub fn get_transfer_fee<S: BaseState>(
     };
     fee
 }
-
-// {
-//     "jsonrpc": "2.0",
-//     "id": 1,
-//     "method": "programSubscribe",
-//     "params": [
-//       "CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK",
-//       {
-//         "encoding": "base64",
-//         "commitment": "finalized"
-//       }
-//     ]
-//   }
-//   {"jsonrpc":"2.0","id":1,"method":"programSubscribe","params":["CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK",{"encoding":"jsonParsed","commitment":"finalized"}]}
@@ -1,39 +1,74 @@
-use std::{collections::HashMap, str::FromStr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    hash::Hash,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use solana_client::nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient};
-use solana_sdk::pubkey::Pubkey;
-use tokio::sync::broadcast;
+use futures::{
+    stream::{BoxStream, SelectAll},
+    StreamExt,
+};
+use serde::Serialize;
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_client::{
+    nonblocking::{
+        pubsub_client::{PubsubClient, UnsubscribeFn},
+        rpc_client::RpcClient,
+    },
+    rpc_config::RpcAccountInfoConfig,
+    rpc_response::Response as RpcResponse,
+};
+use solana_sdk::{
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    pubkey::Pubkey,
+};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
 
 use crate::constants::RAYDIUM_CLMM_PUBKEY;
 
-use super::pool::{LoadPoolAccounts, Pool, UnsubscribeHandle};
+/// Upper bound of how often idle subscriptions are looked for
+const REAP_INTERVAL: Duration = Duration::from_secs(10);
+/// Listeners usually drop together with the socket, wait that long to resubscribe them at once
+const RECONNECT_DEBOUNCE: Duration = Duration::from_millis(200);
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// A pool dropping again that soon after it was resubscribed points at a dead connection
+pub const FLAP_WINDOW: Duration = Duration::from_secs(5);
 
-#[derive(Clone)]
-pub struct ClmmBroadcast {
+use super::{
+    market::{AnyPool, PoolKind, RaydiumPrograms, SwapPool},
+    pool::{PriceTick, UnsubscribeHandle},
+    registry::PairPool,
+};
+
+pub struct PoolBroadcast {
     pub rpc: Arc<RpcClient>,
+    pub programs: RaydiumPrograms,
+    pub pubsub_url: String,
     pub pubsub: Arc<PubsubClient>,
+    /// Listeners report their pool here when their stream ends
+    dropped: mpsc::UnboundedSender<Pubkey>,
+    /// Price changes of the listened pools, for the candles
+    ticks: mpsc::UnboundedSender<PriceTick>,
     pub subscriptions: HashMap<Pubkey, Subscription>,
+    /// Upper bound of tick arrays a single quote may load
+    pub max_tick_arrays: usize,
+    /// How long a pool without subscribers keeps being listened to
+    pub grace_period: Duration,
 }
 
-#[derive(Clone)]
-pub struct Subscription(pub broadcast::Sender<Pool>, pub Box<Arc<UnsubscribeHandle>>);
-
-impl ClmmBroadcast {
-    pub fn new(rpc: Arc<RpcClient>, pubsub: Arc<PubsubClient>) -> Self {
-        Self {
-            rpc,
-            pubsub,
-            subscriptions: HashMap::new(),
-        }
-    }
+/// PDAs of a CLMM pool identified by its mints and amm config index
+pub struct PoolAddresses {
+    pub amm_config: Pubkey,
+    pub pool_state: Pubkey,
+    pub tick_array_bitmap_extension: Pubkey,
+}
 
-    pub async fn subscribe(
-        &mut self,
-        raydium_program_id: &Pubkey,
-        mint0: Pubkey,
-        mint1: Pubkey,
-        fee_index: u16,
-    ) -> anyhow::Result<broadcast::Receiver<Pool>> {
+impl PoolAddresses {
+    pub fn derive(mint0: Pubkey, mint1: Pubkey, fee_index: u16) -> Self {
         let raydium_amm_v3 = Pubkey::from_str(RAYDIUM_CLMM_PUBKEY).unwrap();
 
         let (amm_config_key, _) = Pubkey::find_program_address(
@@ -43,6 +78,16 @@ impl ClmmBroadcast {
             ],
             &raydium_amm_v3,
         );
+        Self::with_amm_config(&raydium_amm_v3, amm_config_key, mint0, mint1)
+    }
+
+    /// Same as [`PoolAddresses::derive`] for an amm config whose address is known already
+    pub fn with_amm_config(
+        raydium_amm_v3: &Pubkey,
+        amm_config_key: Pubkey,
+        mint0: Pubkey,
+        mint1: Pubkey,
+    ) -> Self {
         let (pool_state, _) = Pubkey::find_program_address(
             &[
                 raydium_amm_v3::states::POOL_SEED.as_bytes(),
@@ -50,47 +95,504 @@ impl ClmmBroadcast {
                 mint0.as_ref(),
                 mint1.as_ref(),
             ],
-            &raydium_amm_v3,
+            raydium_amm_v3,
         );
         let (tick_array_bitmap_extension, _) = Pubkey::find_program_address(
             &[
                 raydium_amm_v3::states::POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(),
                 pool_state.as_ref(),
             ],
-            &raydium_amm_v3,
+            raydium_amm_v3,
         );
 
-        if self.subscriptions.contains_key(&pool_state) {
-            return Ok(self.subscriptions.get(&pool_state).unwrap().0.subscribe());
+        Self {
+            amm_config: amm_config_key,
+            pool_state,
+            tick_array_bitmap_extension,
+        }
+    }
+}
+
+/// What subscribers of a pool receive
+#[derive(Clone)]
+pub enum PoolEvent {
+    Update(AnyPool),
+    /// The PubSub stream of the pool ended, nothing arrives until it is `Resynced`
+    Disconnected,
+    /// Subscribed again after a disconnect, `Pool` was reloaded through RPC and updates
+    /// were missed for the given duration
+    Resynced(AnyPool, Duration),
+}
+
+impl PoolEvent {
+    /// The pool state carried by the event, if any
+    pub fn pool(self) -> Option<AnyPool> {
+        match self {
+            Self::Update(pool) | Self::Resynced(pool, _) => Some(pool),
+            Self::Disconnected => None,
         }
+    }
+}
+
+pub struct Subscription {
+    pub tx: broadcast::Sender<PoolEvent>,
+    pub unsubscribe: UnsubscribeHandle,
+    /// The pool as resolved for the first subscriber, enough to load it again
+    pub pair: PairPool,
+    /// Since when the pool has no receivers left
+    pub idle_since: Option<Instant>,
+}
+
+/// When the supervisor last resubscribed each pool, to notice a connection that accepts
+/// subscriptions but drops them right away
+#[derive(Default)]
+pub struct Resubscriptions(HashMap<Pubkey, Instant>);
+
+impl Resubscriptions {
+    pub fn record(&mut self, pools: impl IntoIterator<Item = Pubkey>, at: Instant) {
+        self.0.extend(pools.into_iter().map(|pool| (pool, at)));
+    }
+
+    /// Whether any of `pools`, dropped at `dropped_at`, was resubscribed within
+    /// [`FLAP_WINDOW`] before. Forgets the resubscriptions older than that.
+    pub fn flapping(&mut self, pools: &HashSet<Pubkey>, dropped_at: Instant) -> bool {
+        self.0
+            .retain(|_, at| dropped_at.saturating_duration_since(*at) < FLAP_WINDOW);
+        pools.iter().any(|pool| self.0.contains_key(pool))
+    }
+}
+
+/// What the admin endpoint reports about a subscription
+#[derive(Serialize)]
+pub struct SubscriptionInfo {
+    pub pool: String,
+    pub kind: PoolKind,
+    pub mint0: String,
+    pub mint1: String,
+    pub fee_index: u16,
+    pub subscribers: usize,
+    /// Seconds since the last subscriber left, `None` while there are subscribers
+    pub idle_secs: Option<u64>,
+}
+
+impl PoolBroadcast {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        rpc: Arc<RpcClient>,
+        programs: RaydiumPrograms,
+        pubsub_url: String,
+        pubsub: Arc<PubsubClient>,
+        dropped: mpsc::UnboundedSender<Pubkey>,
+        ticks: mpsc::UnboundedSender<PriceTick>,
+        max_tick_arrays: usize,
+        grace_period: Duration,
+    ) -> Self {
+        Self {
+            rpc,
+            programs,
+            pubsub_url,
+            pubsub,
+            dropped,
+            ticks,
+            subscriptions: HashMap::new(),
+            max_tick_arrays,
+            grace_period,
+        }
+    }
+
+    pub fn active_subscriptions(&self) -> Vec<SubscriptionInfo> {
+        self.subscriptions
+            .iter()
+            .map(|(pool, subscription)| SubscriptionInfo {
+                pool: pool.to_string(),
+                kind: subscription.pair.kind,
+                mint0: subscription.pair.mint0.to_string(),
+                mint1: subscription.pair.mint1.to_string(),
+                fee_index: subscription.pair.fee_index,
+                subscribers: subscription.tx.receiver_count(),
+                idle_secs: subscription
+                    .idle_since
+                    .map(|idle_since| idle_since.elapsed().as_secs()),
+            })
+            .collect()
+    }
+
+    /// Takes out the subscriptions that had no receivers for the whole grace period, or
+    /// whose listener is gone. Their handles are returned so that unsubscribing does not
+    /// hold the lock.
+    pub fn reap(&mut self) -> Vec<(Pubkey, UnsubscribeHandle)> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        for (pool, subscription) in self.subscriptions.iter_mut() {
+            if subscription.tx.receiver_count() > 0 {
+                subscription.idle_since = None;
+                continue;
+            }
+            let idle_since = *subscription.idle_since.get_or_insert(now);
+            if now - idle_since >= self.grace_period || subscription.unsubscribe.is_finished() {
+                expired.push(*pool);
+            }
+        }
+
+        expired
+            .into_iter()
+            .filter_map(|pool| {
+                self.subscriptions
+                    .remove(&pool)
+                    .map(|subscription| (pool, subscription.unsubscribe))
+            })
+            .collect()
+    }
+
+    /// Periodically tears down subscriptions nobody listens to anymore
+    pub async fn run_reaper(broadcast: Arc<Mutex<Self>>) {
+        let interval = broadcast.lock().await.grace_period.min(REAP_INTERVAL);
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let expired = broadcast.lock().await.reap();
+            for (pool, unsubscribe) in expired {
+                log::info!("Unsubscribing from idle pool {}", pool);
+                unsubscribe.unsubscribe().await;
+            }
+        }
+    }
+
+    /// Watches listeners dropping out and resubscribes them with backoff, telling their
+    /// subscribers about the gap. PubSub is reconnected once subscribing through the
+    /// current connection fails, or when a pool drops again right after it was resubscribed.
+    pub async fn run_supervisor(
+        broadcast: Arc<Mutex<Self>>,
+        mut dropped: mpsc::UnboundedReceiver<Pubkey>,
+    ) {
+        let mut resubscribed = Resubscriptions::default();
+        while let Some(pool) = dropped.recv().await {
+            let since = Instant::now();
+            tokio::time::sleep(RECONNECT_DEBOUNCE).await;
+            let mut pools = HashSet::from([pool]);
+            while let Ok(pool) = dropped.try_recv() {
+                pools.insert(pool);
+            }
+
+            let mut backoff = MIN_BACKOFF;
+            let mut reconnect = resubscribed.flapping(&pools, since);
+            let dropped_pools = pools.clone();
+            loop {
+                match Self::resubscribe(&broadcast, &mut pools, since, reconnect).await {
+                    Ok(()) => {
+                        resubscribed.record(dropped_pools, Instant::now());
+                        break;
+                    }
+                    Err(e) => {
+                        log::warn!("Resubscribing failed, retrying in {:?}: {}", backoff, e);
+                        tokio::time::sleep(backoff).await;
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                        reconnect = true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Resubscribes `pools`, through a fresh PubSub connection with `reconnect`. Pools are
+    /// loaded without holding the lock, which is only taken to swap the listeners in.
+    /// Pools that were reaped meanwhile are skipped, done ones are taken out of `pools`.
+    async fn resubscribe(
+        broadcast: &Mutex<Self>,
+        pools: &mut HashSet<Pubkey>,
+        since: Instant,
+        reconnect: bool,
+    ) -> anyhow::Result<()> {
+        let (mut listener, subscribed) = {
+            let broadcast = broadcast.lock().await;
+            let subscribed: Vec<(Pubkey, PairPool, broadcast::Sender<PoolEvent>)> = pools
+                .iter()
+                .filter_map(|pool_id| {
+                    let subscription = broadcast.subscriptions.get(pool_id)?;
+                    Some((*pool_id, subscription.pair, subscription.tx.clone()))
+                })
+                .collect();
+            (broadcast.listener(), subscribed)
+        };
+        pools.retain(|pool_id| {
+            subscribed
+                .iter()
+                .any(|(subscribed, ..)| subscribed == pool_id)
+        });
+
+        if reconnect && !subscribed.is_empty() {
+            let failed = listener.pubsub.clone();
+            let pubsub = Arc::new(PubsubClient::new(&listener.pubsub_url).await?);
+            let mut broadcast = broadcast.lock().await;
+            // another reconnect may have replaced the failed connection already
+            if Arc::ptr_eq(&broadcast.pubsub, &failed) {
+                broadcast.pubsub = pubsub;
+            }
+            listener.pubsub = broadcast.pubsub.clone();
+        }
+
+        for (pool_id, pair, tx) in subscribed {
+            log::info!("Resubscribing to pool {}", pool_id);
+            let (pool, unsubscribe) = listener.listen(&pair, tx.clone()).await?;
+            pools.remove(&pool_id);
+
+            let previous = {
+                let mut broadcast = broadcast.lock().await;
+                match broadcast.subscriptions.get_mut(&pool_id) {
+                    // still the subscription the pool was dropped from
+                    Some(subscription) if subscription.tx.same_channel(&tx) => {
+                        let _ = tx.send(PoolEvent::Resynced(pool, since.elapsed()));
+                        std::mem::replace(&mut subscription.unsubscribe, unsubscribe)
+                    }
+                    _ => unsubscribe,
+                }
+            };
+            previous.unsubscribe().await;
+        }
+
+        Ok(())
+    }
+
+    /// What starting a listener takes, to do so without the lock
+    fn listener(&self) -> Listener {
+        Listener {
+            rpc: self.rpc.clone(),
+            programs: self.programs,
+            pubsub_url: self.pubsub_url.clone(),
+            pubsub: self.pubsub.clone(),
+            dropped: self.dropped.clone(),
+            ticks: self.ticks.clone(),
+            max_tick_arrays: self.max_tick_arrays,
+        }
+    }
+
+    /// Joins the subscription of `pool`, if there is one
+    fn join(&mut self, pool: &Pubkey) -> Option<broadcast::Receiver<PoolEvent>> {
+        let subscription = self.subscriptions.get_mut(pool)?;
+        subscription.idle_since = None;
+        Some(subscription.tx.subscribe())
+    }
+
+    /// Subscribes to `pair.pool`, sharing the listener with earlier subscribers of it. A
+    /// new pool is loaded without holding the lock, which is only taken to join or insert.
+    pub async fn subscribe(
+        broadcast: &Mutex<Self>,
+        pair: &PairPool,
+    ) -> anyhow::Result<broadcast::Receiver<PoolEvent>> {
+        let listener = {
+            let mut broadcast = broadcast.lock().await;
+            if let Some(rx) = broadcast.join(&pair.pool) {
+                return Ok(rx);
+            }
+            broadcast.listener()
+        };
 
-        let pool = Pool::load(
-            self.rpc.clone(),
-            raydium_program_id,
-            LoadPoolAccounts {
-                mint0,
-                mint1,
-                amm_config: amm_config_key,
-                pool_id: pool_state,
-                tickarray_bitmap_extension: tick_array_bitmap_extension,
-            },
-            true,
-        )
-        .await?;
         let (tx, rx) = broadcast::channel(10);
-        // tx.send(pool.clone());
+        let (_, unsubscribe) = listener.listen(pair, tx.clone()).await?;
+
+        let mut broadcast = broadcast.lock().await;
+        if let Some(rx) = broadcast.join(&pair.pool) {
+            // another subscriber got the pool in first, its listener is kept
+            drop(broadcast);
+            unsubscribe.unsubscribe().await;
+            return Ok(rx);
+        }
+        broadcast.subscriptions.insert(
+            pair.pool,
+            Subscription {
+                tx,
+                unsubscribe,
+                pair: *pair,
+                idle_since: None,
+            },
+        );
+
+        Ok(rx)
+    }
+}
+
+/// The parts of [`PoolBroadcast`] a listener is started with
+struct Listener {
+    rpc: Arc<RpcClient>,
+    programs: RaydiumPrograms,
+    pubsub_url: String,
+    pubsub: Arc<PubsubClient>,
+    dropped: mpsc::UnboundedSender<Pubkey>,
+    ticks: mpsc::UnboundedSender<PriceTick>,
+    max_tick_arrays: usize,
+}
 
+impl Listener {
+    /// Loads the pool through RPC and starts listening to its accounts
+    async fn listen(
+        &self,
+        pair: &PairPool,
+        tx: broadcast::Sender<PoolEvent>,
+    ) -> anyhow::Result<(AnyPool, UnsubscribeHandle)> {
+        let pool =
+            AnyPool::load(self.rpc.clone(), &self.programs, pair, self.max_tick_arrays).await?;
         let unsub = pool
             .listen(
                 self.rpc.clone(),
-                *raydium_program_id,
                 self.pubsub.clone(),
-                tx.clone(),
+                tx,
+                self.dropped.clone(),
+                self.ticks.clone(),
             )
             .await?;
-        self.subscriptions
-            .insert(pool_state, Subscription(tx, Box::new(Arc::new(unsub))));
+        log::debug!("Listening to {:?} pool {}", pool.kind(), pool.id());
 
-        Ok(rx)
+        Ok((pool, unsub))
+    }
+}
+
+/// What a pool listener does with the accounts it follows. [`listen`] takes care of the
+/// task, the shutdown and a dropped stream the same way for every pool kind.
+pub(super) trait AccountListener: Send + 'static {
+    /// Tells the notifications of the followed accounts apart
+    type Account: Copy + Eq + Hash + Send + Sync + 'static;
+
+    /// Subscribes to the accounts followed from the start
+    fn start(
+        &mut self,
+        rpc: &RpcClient,
+        streams: &mut AccountStreams<'_, Self::Account>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Applies a change of `account`, subscribing to or leaving accounts as needed.
+    /// Returns the pool to send to the subscribers, none when the change is ignored.
+    fn update(
+        &mut self,
+        rpc: &RpcClient,
+        streams: &mut AccountStreams<'_, Self::Account>,
+        account: Self::Account,
+        response: RpcResponse<UiAccount>,
+    ) -> impl Future<Output = anyhow::Result<Option<AnyPool>>> + Send;
+}
+
+/// The account subscriptions of one listener merged into a single stream, every
+/// notification comes with the account it is about
+pub(super) struct AccountStreams<'a, K> {
+    pubsub: &'a PubsubClient,
+    streams: SelectAll<BoxStream<'a, (K, RpcResponse<UiAccount>)>>,
+    unsubscribes: HashMap<K, UnsubscribeFn>,
+}
+
+impl<'a, K: Copy + Eq + Hash + Send + 'a> AccountStreams<'a, K> {
+    fn new(pubsub: &'a PubsubClient) -> Self {
+        Self {
+            pubsub,
+            streams: SelectAll::new(),
+            unsubscribes: HashMap::new(),
+        }
+    }
+
+    /// Follows every change of `pubkey` as soon as it is processed
+    pub(super) async fn subscribe(&mut self, account: K, pubkey: &Pubkey) -> anyhow::Result<()> {
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64Zstd),
+            data_slice: None,
+            commitment: Some(CommitmentConfig {
+                commitment: CommitmentLevel::Processed,
+            }),
+            min_context_slot: None,
+        };
+        let (stream, unsubscribe) = self.pubsub.account_subscribe(pubkey, Some(config)).await?;
+        self.streams
+            .push(stream.map(move |response| (account, response)).boxed());
+        self.unsubscribes.insert(account, unsubscribe);
+        Ok(())
+    }
+
+    /// Stops following `account`, notifications already on their way may still arrive
+    pub(super) async fn unsubscribe(&mut self, account: &K) {
+        if let Some(unsubscribe) = self.unsubscribes.remove(account) {
+            unsubscribe().await;
+        }
+    }
+
+    pub(super) fn is_subscribed(&self, account: &K) -> bool {
+        self.unsubscribes.contains_key(account)
+    }
+
+    /// The accounts followed right now
+    pub(super) fn accounts(&self) -> impl Iterator<Item = &K> {
+        self.unsubscribes.keys()
+    }
+
+    async fn next(&mut self) -> Option<(K, RpcResponse<UiAccount>)> {
+        self.streams.next().await
+    }
+
+    async fn unsubscribe_all(self) {
+        for (_, unsubscribe) in self.unsubscribes {
+            unsubscribe().await;
+        }
+    }
+}
+
+/// Runs `listener` of `pool_id` on a task of its own until it is unsubscribed, sending
+/// every update it makes to `tx`. Returns once the listener is subscribed, with the error
+/// if it could not. Once its streams end or break the subscribers are told they are
+/// `Disconnected` and the pool goes to `dropped` for the supervisor.
+pub(super) async fn listen<L: AccountListener>(
+    pool_id: Pubkey,
+    mut listener: L,
+    rpc: Arc<RpcClient>,
+    pubsub: Arc<PubsubClient>,
+    tx: broadcast::Sender<PoolEvent>,
+    dropped: mpsc::UnboundedSender<Pubkey>,
+) -> anyhow::Result<UnsubscribeHandle> {
+    let (shutdown, mut shutdown_rx) = oneshot::channel::<()>();
+    let (started_tx, started_rx) = oneshot::channel::<anyhow::Result<()>>();
+
+    let handle = tokio::spawn(async move {
+        let mut streams = AccountStreams::new(&pubsub);
+        if let Err(e) = listener.start(&rpc, &mut streams).await {
+            // the caller gets the error, e.g. to reconnect a dead socket
+            streams.unsubscribe_all().await;
+            let _ = started_tx.send(Err(e));
+            return Ok(());
+        }
+        let _ = started_tx.send(Ok(()));
+
+        let listened = async {
+            let shutdown = loop {
+                let (account, response) = tokio::select! {
+                    _ = &mut shutdown_rx => break true,
+                    next = streams.next() => match next {
+                        Some(next) => next,
+                        None => break false,
+                    },
+                };
+                let update = listener
+                    .update(&rpc, &mut streams, account, response)
+                    .await?;
+                if let Some(pool) = update {
+                    // nobody listening is fine, the subscription lingers until it is reaped
+                    let _ = tx.send(PoolEvent::Update(pool));
+                }
+            };
+            Ok::<_, anyhow::Error>(shutdown)
+        }
+        .await;
+
+        if matches!(listened, Ok(true)) {
+            streams.unsubscribe_all().await;
+        } else {
+            // the stream ended (or broke), no updates until the supervisor resubscribes
+            let _ = tx.send(PoolEvent::Disconnected);
+            let _ = dropped.send(pool_id);
+        }
+        listened.map(|_| ())
+    });
+
+    match started_rx.await {
+        Ok(Ok(())) => Ok(UnsubscribeHandle::new(handle, shutdown)),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(anyhow::anyhow!(
+            "listener of pool {} stopped while subscribing",
+            pool_id
+        )),
     }
 }
@@ -0,0 +1,561 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anchor_lang::Discriminator;
+use futures::{Future, StreamExt};
+use raydium_amm_v3::states::{AmmConfig, PoolState};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient},
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    account::Account,
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    pubkey::Pubkey,
+};
+use tokio::sync::RwLock;
+
+use crate::error::AppError;
+
+use super::{
+    amm_v4::AmmV4Pool,
+    cpmm::CpmmPool,
+    market::{PoolKind, RaydiumPrograms},
+    pool::deserialize_anchor_account,
+    pubsub::PoolAddresses,
+};
+
+/// Upper bound of accounts a single `getMultipleAccounts` call may ask for
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+/// How long to wait before loading the registry again after the program stream broke
+const RESYNC_DELAY: Duration = Duration::from_secs(5);
+/// How long the pools found for a pair are reused before looking them up again
+const PAIR_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// What the registry knows about a pool
+#[derive(Debug, Clone)]
+pub struct PoolInfo {
+    pub pool: Pubkey,
+    pub mint0: Pubkey,
+    pub mint1: Pubkey,
+    pub amm_config: Pubkey,
+    pub fee_index: u16,
+    pub trade_fee_rate: u32,
+    pub tick_spacing: u16,
+    pub liquidity: u128,
+    pub sqrt_price_x64: u128,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AmmConfigInfo {
+    pub index: u16,
+    pub trade_fee_rate: u32,
+    pub tick_spacing: u16,
+}
+
+/// A pool of a pair as found by [`PoolRegistry::resolve_pools`], mints in pool order
+#[derive(Debug, Clone, Copy)]
+pub struct PairPool {
+    pub pool: Pubkey,
+    pub kind: PoolKind,
+    pub mint0: Pubkey,
+    pub mint1: Pubkey,
+    /// Unused by AMM v4 pools, which have their fee in the pool
+    pub amm_config: Pubkey,
+    pub fee_index: u16,
+    /// Per 1e6 of the input, like CLMM amm configs have it
+    pub trade_fee_rate: u32,
+    /// `sqrt(reserve0 * reserve1)` for constant product pools
+    pub liquidity: u128,
+    /// Whether selling the first requested mint for the second one swaps mint0 for mint1
+    pub zero_for_one: bool,
+}
+
+impl PairPool {
+    /// Pool direction of a request that is `zero_for_one` in the requested mint order
+    pub fn direction(&self, zero_for_one: bool) -> bool {
+        zero_for_one == self.zero_for_one
+    }
+}
+
+/// Criteria of [`PoolRegistry::find`], all given ones have to match
+#[derive(Debug, Default)]
+pub struct PoolFilter {
+    /// Either of the two mints
+    pub mint: Option<Pubkey>,
+    /// Both mints, in any order
+    pub pair: Option<(Pubkey, Pubkey)>,
+    pub fee_index: Option<u16>,
+    pub trade_fee_rate: Option<u32>,
+}
+
+impl PoolFilter {
+    fn matches(&self, pool: &PoolInfo) -> bool {
+        self.mint
+            .map_or(true, |mint| pool.mint0 == mint || pool.mint1 == mint)
+            && self.pair.map_or(true, |(a, b)| {
+                (pool.mint0, pool.mint1) == (a, b) || (pool.mint0, pool.mint1) == (b, a)
+            })
+            && self
+                .fee_index
+                .map_or(true, |fee_index| pool.fee_index == fee_index)
+            && self
+                .trade_fee_rate
+                .map_or(true, |rate| pool.trade_fee_rate == rate)
+    }
+}
+
+/// Every CLMM pool of the program, loaded with `getProgramAccounts` and kept up to date
+/// through `programSubscribe`. Pools of the other kinds are looked up on demand.
+pub struct PoolRegistry {
+    programs: RaydiumPrograms,
+    pools: RwLock<HashMap<Pubkey, PoolInfo>>,
+    amm_configs: RwLock<HashMap<Pubkey, AmmConfigInfo>>,
+    /// Pools found per kind and pair, the mints in ascending order
+    pairs: RwLock<HashMap<(PoolKind, Pubkey, Pubkey), (Instant, Vec<PairPool>)>>,
+    ready: AtomicBool,
+}
+
+impl PoolRegistry {
+    pub fn new(programs: RaydiumPrograms) -> Self {
+        Self {
+            programs,
+            pools: HashMap::new().into(),
+            amm_configs: HashMap::new().into(),
+            pairs: HashMap::new().into(),
+            ready: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the initial load finished
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    pub async fn find(&self, filter: &PoolFilter) -> Vec<PoolInfo> {
+        self.pools
+            .read()
+            .await
+            .values()
+            .filter(|pool| filter.matches(pool))
+            .cloned()
+            .collect()
+    }
+
+    pub async fn amm_configs(&self) -> HashMap<Pubkey, AmmConfigInfo> {
+        self.amm_configs.read().await.clone()
+    }
+
+    /// CLMM pools of the pair in either mint order, deepest first, of `fee_index` only if
+    /// given. Probes the pool address of every known amm config, so pools the registry did
+    /// not pick up yet are found too, and keeps what it found for [`PAIR_CACHE_TTL`].
+    pub async fn resolve_pair(
+        &self,
+        rpc: &RpcClient,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+        fee_index: Option<u16>,
+    ) -> anyhow::Result<Vec<PairPool>> {
+        if mint_a == mint_b {
+            anyhow::bail!("a pair needs two different mints");
+        }
+        let pools = self
+            .cached(
+                PoolKind::Clmm,
+                mint_a,
+                mint_b,
+                self.probe_pair(rpc, mint_a, mint_b),
+            )
+            .await?;
+
+        Ok(pools
+            .into_iter()
+            .filter(|pool| fee_index.map_or(true, |index| pool.fee_index == index))
+            .collect())
+    }
+
+    /// Pools of the pair `find` looks up, or the ones it found within the last
+    /// [`PAIR_CACHE_TTL`]. Failed lookups are not kept. The pools come oriented for
+    /// selling `mint_a`.
+    async fn cached(
+        &self,
+        kind: PoolKind,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+        find: impl Future<Output = anyhow::Result<Vec<PairPool>>>,
+    ) -> anyhow::Result<Vec<PairPool>> {
+        let key = (kind, mint_a.min(mint_b), mint_a.max(mint_b));
+        let cached = match self.pairs.read().await.get(&key) {
+            Some((found_at, pools)) if found_at.elapsed() < PAIR_CACHE_TTL => Some(pools.clone()),
+            _ => None,
+        };
+        let pools = match cached {
+            Some(pools) => pools,
+            None => {
+                let pools = find.await?;
+                let mut pairs = self.pairs.write().await;
+                pairs.retain(|_, (found_at, _)| found_at.elapsed() < PAIR_CACHE_TTL);
+                pairs.insert(key, (Instant::now(), pools.clone()));
+                pools
+            }
+        };
+
+        Ok(pools
+            .into_iter()
+            .map(|pool| PairPool {
+                zero_for_one: mint_a == pool.mint0,
+                ..pool
+            })
+            .collect())
+    }
+
+    /// Every CLMM pool of the pair, looked up through the known amm configs
+    async fn probe_pair(
+        &self,
+        rpc: &RpcClient,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+    ) -> anyhow::Result<Vec<PairPool>> {
+        // the program only creates pools with mint0 < mint1
+        let (mint0, mint1) = if mint_a < mint_b {
+            (mint_a, mint_b)
+        } else {
+            (mint_b, mint_a)
+        };
+
+        if self.amm_configs.read().await.is_empty() {
+            self.load_amm_configs(rpc).await?;
+        }
+        let amm_configs: Vec<_> = self
+            .amm_configs
+            .read()
+            .await
+            .iter()
+            .map(|(key, amm_config)| {
+                let addresses =
+                    PoolAddresses::with_amm_config(&self.programs.clmm, *key, mint0, mint1);
+                (addresses.pool_state, *key, *amm_config)
+            })
+            .collect();
+
+        let mut pools = Vec::new();
+        for chunk in amm_configs.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let keys: Vec<_> = chunk.iter().map(|(pool, _, _)| *pool).collect();
+            let accounts = rpc.get_multiple_accounts(&keys).await?;
+            for ((pool, amm_config_key, amm_config), account) in chunk.iter().zip(accounts) {
+                let Some(account) = account else {
+                    continue;
+                };
+                let pool_state = deserialize_anchor_account::<PoolState>(&account)?;
+                pools.push(PairPool {
+                    pool: *pool,
+                    kind: PoolKind::Clmm,
+                    mint0,
+                    mint1,
+                    amm_config: *amm_config_key,
+                    fee_index: amm_config.index,
+                    trade_fee_rate: amm_config.trade_fee_rate,
+                    liquidity: pool_state.liquidity,
+                    zero_for_one: mint_a == mint0,
+                });
+            }
+        }
+
+        pools.sort_by(|a, b| b.liquidity.cmp(&a.liquidity));
+        Ok(pools)
+    }
+
+    /// Pools of the pair across the pool kinds, or of `kind` only, deepest first. AMM v4
+    /// pools have no fee index, so they are left out when one is given. Without a `kind`
+    /// a kind whose lookup fails is skipped, so one unreachable program does not take the
+    /// others down. The program account scans of CPMM and AMM v4 are kept for
+    /// [`PAIR_CACHE_TTL`] like the CLMM probes.
+    pub async fn resolve_pools(
+        &self,
+        rpc: &RpcClient,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+        kind: Option<PoolKind>,
+        fee_index: Option<u16>,
+    ) -> anyhow::Result<Vec<PairPool>> {
+        let wanted = |candidate: PoolKind| kind.map_or(true, |kind| kind == candidate);
+
+        let mut pools = Vec::new();
+        if wanted(PoolKind::Clmm) {
+            pools.extend(self.resolve_pair(rpc, mint_a, mint_b, fee_index).await?);
+        }
+        if wanted(PoolKind::Cpmm) {
+            let find = CpmmPool::find_pairs(rpc, &self.programs.cpmm, mint_a, mint_b);
+            let found = self.cached(PoolKind::Cpmm, mint_a, mint_b, find).await;
+            pools.extend(
+                lookup(PoolKind::Cpmm, kind, found)?
+                    .into_iter()
+                    .filter(|pool| fee_index.map_or(true, |index| pool.fee_index == index)),
+            );
+        }
+        if wanted(PoolKind::AmmV4) && fee_index.is_none() {
+            let find = AmmV4Pool::find_pairs(rpc, &self.programs.amm_v4, mint_a, mint_b);
+            let found = self.cached(PoolKind::AmmV4, mint_a, mint_b, find).await;
+            pools.extend(lookup(PoolKind::AmmV4, kind, found)?);
+        }
+
+        pools.sort_by(|a, b| b.liquidity.cmp(&a.liquidity));
+        Ok(pools)
+    }
+
+    /// The deepest pool of the pair, see [`PoolRegistry::resolve_pools`]
+    pub async fn deepest_pool(
+        &self,
+        rpc: &RpcClient,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+        kind: Option<PoolKind>,
+        fee_index: Option<u16>,
+    ) -> anyhow::Result<PairPool> {
+        self.resolve_pools(rpc, mint_a, mint_b, kind, fee_index)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| AppError::PoolNotFound(mint_a, mint_b).into())
+    }
+
+    /// Keeps the registry in sync for as long as the server runs
+    pub async fn run(self: Arc<Self>, rpc: Arc<RpcClient>, pubsub_url: String) {
+        loop {
+            if let Err(e) = self.sync(&rpc, &pubsub_url).await {
+                log::warn!(
+                    "Pool registry out of sync, reloading in {:?}: {}",
+                    RESYNC_DELAY,
+                    e
+                );
+            }
+            tokio::time::sleep(RESYNC_DELAY).await;
+        }
+    }
+
+    /// Subscribes to the program, loads all pools and applies notifications until the
+    /// stream ends. Subscribing first means no pool created during the load is missed.
+    async fn sync(&self, rpc: &RpcClient, pubsub_url: &str) -> anyhow::Result<()> {
+        let pubsub = PubsubClient::new(pubsub_url).await?;
+        let (mut notifications, unsubscribe) = pubsub
+            .program_subscribe(
+                &self.programs.clmm,
+                Some(accounts_config::<PoolState>(PoolState::LEN)),
+            )
+            .await?;
+
+        self.load_amm_configs(rpc).await?;
+
+        let pools = rpc
+            .get_program_accounts_with_config(
+                &self.programs.clmm,
+                accounts_config::<PoolState>(PoolState::LEN),
+            )
+            .await?;
+        log::info!("Pool registry loaded {} pools", pools.len());
+        for (pubkey, account) in pools {
+            self.insert_pool(rpc, pubkey, &account).await?;
+        }
+        self.ready.store(true, Ordering::Relaxed);
+
+        while let Some(notification) = notifications.next().await {
+            let pubkey = Pubkey::from_str(&notification.value.pubkey)?;
+            let account = notification
+                .value
+                .account
+                .decode::<Account>()
+                .ok_or_else(|| anyhow::anyhow!("unable to decode pool {}", pubkey))?;
+            self.insert_pool(rpc, pubkey, &account).await?;
+        }
+
+        unsubscribe().await;
+        anyhow::bail!("programSubscribe stream ended")
+    }
+
+    /// A given pool of the pair, e.g. one picked from [`PoolRegistry::resolve_pools`]
+    /// before, of whichever kind its owner says
+    pub async fn pair_pool(
+        &self,
+        rpc: &RpcClient,
+        pool: Pubkey,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+    ) -> anyhow::Result<PairPool> {
+        let not_found = || AppError::PoolNotFound(mint_a, mint_b);
+        let account = rpc
+            .get_multiple_accounts(&[pool])
+            .await?
+            .pop()
+            .flatten()
+            .ok_or_else(not_found)?;
+
+        let pair = match self.programs.kind_of(&account.owner) {
+            Some(PoolKind::Clmm) => self.clmm_pair(rpc, pool, &account, mint_a).await?,
+            Some(PoolKind::Cpmm) => CpmmPool::load(rpc, &self.programs.cpmm, pool)
+                .await?
+                .pair(mint_a),
+            Some(PoolKind::AmmV4) => AmmV4Pool::load(rpc, &self.programs.amm_v4, pool)
+                .await?
+                .pair(mint_a),
+            None => return Err(not_found().into()),
+        };
+        if (mint_a, mint_b) != (pair.mint0, pair.mint1)
+            && (mint_b, mint_a) != (pair.mint0, pair.mint1)
+        {
+            return Err(not_found().into());
+        }
+        Ok(pair)
+    }
+
+    async fn clmm_pair(
+        &self,
+        rpc: &RpcClient,
+        pool: Pubkey,
+        account: &Account,
+        mint_a: Pubkey,
+    ) -> anyhow::Result<PairPool> {
+        let pool_state = deserialize_anchor_account::<PoolState>(account)?;
+        let amm_config_key = pool_state.amm_config;
+        let amm_config = self.amm_config(rpc, amm_config_key).await?;
+
+        Ok(PairPool {
+            pool,
+            kind: PoolKind::Clmm,
+            mint0: pool_state.token_mint_0,
+            mint1: pool_state.token_mint_1,
+            amm_config: amm_config_key,
+            fee_index: amm_config.index,
+            trade_fee_rate: amm_config.trade_fee_rate,
+            liquidity: pool_state.liquidity,
+            zero_for_one: mint_a == pool_state.token_mint_0,
+        })
+    }
+
+    async fn load_amm_configs(&self, rpc: &RpcClient) -> anyhow::Result<()> {
+        let amm_configs = rpc
+            .get_program_accounts_with_config(
+                &self.programs.clmm,
+                accounts_config::<AmmConfig>(AmmConfig::LEN),
+            )
+            .await?;
+        for (pubkey, account) in amm_configs {
+            self.insert_amm_config(pubkey, &account).await?;
+        }
+        Ok(())
+    }
+
+    /// Fetches amm configs created after the initial load on first use
+    async fn amm_config(&self, rpc: &RpcClient, key: Pubkey) -> anyhow::Result<AmmConfigInfo> {
+        if let Some(amm_config) = self.amm_configs.read().await.get(&key) {
+            return Ok(*amm_config);
+        }
+        let account = rpc.get_account(&key).await?;
+        self.insert_amm_config(key, &account).await
+    }
+
+    async fn insert_amm_config(
+        &self,
+        pubkey: Pubkey,
+        account: &Account,
+    ) -> anyhow::Result<AmmConfigInfo> {
+        let amm_config = deserialize_anchor_account::<AmmConfig>(account)?;
+        let info = AmmConfigInfo {
+            index: amm_config.index,
+            trade_fee_rate: amm_config.trade_fee_rate,
+            tick_spacing: amm_config.tick_spacing,
+        };
+        self.amm_configs.write().await.insert(pubkey, info);
+        Ok(info)
+    }
+
+    async fn insert_pool(
+        &self,
+        rpc: &RpcClient,
+        pubkey: Pubkey,
+        account: &Account,
+    ) -> anyhow::Result<()> {
+        let pool_state = deserialize_anchor_account::<PoolState>(account)?;
+
+        let amm_config_key = pool_state.amm_config;
+        let amm_config = self.amm_config(rpc, amm_config_key).await?;
+
+        let created = self.pools.write().await.insert(
+            pubkey,
+            PoolInfo {
+                pool: pubkey,
+                mint0: pool_state.token_mint_0,
+                mint1: pool_state.token_mint_1,
+                amm_config: amm_config_key,
+                fee_index: amm_config.index,
+                trade_fee_rate: amm_config.trade_fee_rate,
+                tick_spacing: pool_state.tick_spacing,
+                liquidity: pool_state.liquidity,
+                sqrt_price_x64: pool_state.sqrt_price_x64,
+            },
+        );
+        // a new pool of a pair looked up before, pools only change their state otherwise
+        if created.is_none() {
+            let pair = (
+                PoolKind::Clmm,
+                pool_state.token_mint_0,
+                pool_state.token_mint_1,
+            );
+            self.pairs.write().await.remove(&pair);
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of looking up pools of `candidate`, failures only count when that kind was
+/// asked for explicitly
+fn lookup(
+    candidate: PoolKind,
+    kind: Option<PoolKind>,
+    found: anyhow::Result<Vec<PairPool>>,
+) -> anyhow::Result<Vec<PairPool>> {
+    match found {
+        Ok(pools) => Ok(pools),
+        Err(e) if kind.is_none() => {
+            log::warn!(
+                "Looking up {:?} pools failed, skipping them: {}",
+                candidate,
+                e
+            );
+            Ok(Vec::new())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Program accounts of type `T`, told apart by size and anchor discriminator
+fn accounts_config<T: Discriminator>(len: usize) -> RpcProgramAccountsConfig {
+    program_accounts_config(vec![
+        RpcFilterType::DataSize(len as u64),
+        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(0, &T::DISCRIMINATOR)),
+    ])
+}
+
+/// Program accounts matching all of `filters`
+pub(super) fn program_accounts_config(filters: Vec<RpcFilterType>) -> RpcProgramAccountsConfig {
+    RpcProgramAccountsConfig {
+        filters: Some(filters),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64Zstd),
+            data_slice: None,
+            commitment: Some(CommitmentConfig {
+                commitment: CommitmentLevel::Confirmed,
+            }),
+            min_context_slot: None,
+        },
+        with_context: None,
+    }
+}
@@ -0,0 +1,423 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use anchor_client::{Client, Cluster};
+use futures::future::join_all;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey, signature::Keypair, signer::Signer};
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+
+use crate::{
+    constants::MAX_ROUTE_CANDIDATES,
+    error::AppError,
+    solclient::utils::{amount_with_slippage, TransactionResult},
+};
+
+use super::{
+    compute_budget::{self, PriorityFee},
+    pool::{LoadPoolAccounts, Pool, Quote},
+    pubsub::PoolAddresses,
+    registry::PoolInfo,
+    utils,
+};
+
+/// One pool of a route, oriented in swap direction
+#[derive(Debug, Clone, Copy)]
+pub struct Hop {
+    pub pool: Pubkey,
+    pub amm_config: Pubkey,
+    pub fee_index: u16,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    pub zero_for_one: bool,
+    pub liquidity: u128,
+}
+
+impl Hop {
+    fn new(pool: &PoolInfo, input_mint: Pubkey) -> Self {
+        let zero_for_one = pool.mint0 == input_mint;
+        Self {
+            pool: pool.pool,
+            amm_config: pool.amm_config,
+            fee_index: pool.fee_index,
+            input_mint,
+            output_mint: if zero_for_one { pool.mint1 } else { pool.mint0 },
+            zero_for_one,
+            liquidity: pool.liquidity,
+        }
+    }
+}
+
+/// A quoted hop, `tick_arrays` are the start indexes the swap passes to the program
+#[derive(Debug, Clone)]
+pub struct Leg {
+    pub hop: Hop,
+    pub quote: Quote,
+    pub tick_arrays: VecDeque<i32>,
+}
+
+/// Exact input quote of a whole route, each leg spends what the previous one received
+#[derive(Debug, Clone)]
+pub struct RouteQuote {
+    pub legs: Vec<Leg>,
+}
+
+/// What a swap along a route asks for besides the route itself, the amount is the one
+/// the route was quoted for
+#[derive(Debug, Clone, Copy)]
+pub struct RouteSwapParams<'a> {
+    /// Token accounts of the payer the route starts and ends in
+    pub input_token: Pubkey,
+    pub output_token: Pubkey,
+    /// Runs ahead of the swap, e.g. to create the token accounts
+    pub setup: &'a [Instruction],
+    /// Applies to every leg
+    pub slippage: f64,
+    /// Compute unit price of the swap transaction, none by default
+    pub priority_fee: Option<PriorityFee>,
+}
+
+impl RouteQuote {
+    pub fn amount_in(&self) -> u64 {
+        self.legs.first().map_or(0, |leg| leg.quote.amount_in)
+    }
+
+    pub fn amount_out(&self) -> u64 {
+        self.legs.last().map_or(0, |leg| leg.quote.amount_out)
+    }
+}
+
+/// Paths of 1 up to `max_hops` pools from `input_mint` to `output_mint` that visit no mint
+/// twice. The most promising [`MAX_ROUTE_CANDIDATES`] are kept: fewer hops first, then
+/// the ones whose shallowest pool is deepest.
+pub fn find_paths(
+    pools: &[PoolInfo],
+    input_mint: Pubkey,
+    output_mint: Pubkey,
+    max_hops: usize,
+) -> Vec<Vec<Hop>> {
+    let mut graph = Graph::default();
+    for pool in pools.iter().filter(|pool| pool.liquidity > 0) {
+        graph.neighbours.entry(pool.mint0).or_default().push(pool);
+        graph.neighbours.entry(pool.mint1).or_default().push(pool);
+        graph
+            .pairs
+            .entry((pool.mint0, pool.mint1))
+            .or_default()
+            .push(pool);
+    }
+
+    let mut paths = Vec::new();
+    let mut path = Vec::new();
+    let mut visited = HashSet::from([input_mint]);
+    graph.walk(
+        input_mint,
+        output_mint,
+        max_hops,
+        &mut visited,
+        &mut path,
+        &mut paths,
+    );
+
+    paths.sort_by_key(|path: &Vec<Hop>| {
+        let depth = path.iter().map(|hop| hop.liquidity).min().unwrap_or(0);
+        (path.len(), std::cmp::Reverse(depth))
+    });
+    paths.truncate(MAX_ROUTE_CANDIDATES);
+    paths
+}
+
+#[derive(Default)]
+struct Graph<'a> {
+    /// Pools by each of their mints
+    neighbours: HashMap<Pubkey, Vec<&'a PoolInfo>>,
+    /// Pools by their mints in pool order
+    pairs: HashMap<(Pubkey, Pubkey), Vec<&'a PoolInfo>>,
+}
+
+impl Graph<'_> {
+    fn walk(
+        &self,
+        mint: Pubkey,
+        output_mint: Pubkey,
+        hops_left: usize,
+        visited: &mut HashSet<Pubkey>,
+        path: &mut Vec<Hop>,
+        paths: &mut Vec<Vec<Hop>>,
+    ) {
+        // the last hop has to end in the output mint, hub mints have too many pools to
+        // look at all of them
+        if hops_left == 1 {
+            let pair = if mint < output_mint {
+                (mint, output_mint)
+            } else {
+                (output_mint, mint)
+            };
+            for pool in self.pairs.get(&pair).into_iter().flatten() {
+                let mut found = path.clone();
+                found.push(Hop::new(pool, mint));
+                paths.push(found);
+            }
+            return;
+        }
+
+        for pool in self.neighbours.get(&mint).into_iter().flatten() {
+            let hop = Hop::new(pool, mint);
+            if hop.output_mint == output_mint {
+                let mut found = path.clone();
+                found.push(hop);
+                paths.push(found);
+                continue;
+            }
+            if !visited.insert(hop.output_mint) {
+                continue;
+            }
+            path.push(hop);
+            self.walk(
+                hop.output_mint,
+                output_mint,
+                hops_left - 1,
+                visited,
+                path,
+                paths,
+            );
+            path.pop();
+            visited.remove(&hop.output_mint);
+        }
+    }
+}
+
+/// Quotes routes and executes them, pools are loaded once per router
+pub struct Router {
+    rpc: Arc<RpcClient>,
+    raydium_program_id: Pubkey,
+    max_tick_arrays: usize,
+    pools: HashMap<Pubkey, Pool>,
+}
+
+impl Router {
+    pub fn new(rpc: Arc<RpcClient>, raydium_program_id: Pubkey, max_tick_arrays: usize) -> Self {
+        Self {
+            rpc,
+            raydium_program_id,
+            max_tick_arrays,
+            pools: HashMap::new(),
+        }
+    }
+
+    /// Quotes the candidate paths through `pools` and returns the one with the most
+    /// output. Paths whose pools cannot be loaded or cannot fill the amount are skipped.
+    pub async fn best_route(
+        &mut self,
+        pools: &[PoolInfo],
+        input_mint: Pubkey,
+        output_mint: Pubkey,
+        max_hops: usize,
+        amount: u64,
+    ) -> anyhow::Result<RouteQuote> {
+        let paths = find_paths(pools, input_mint, output_mint, max_hops);
+        let hops: Vec<Hop> = paths.iter().flatten().copied().collect();
+        self.load_pools(&hops).await;
+
+        let epoch = self.rpc.get_epoch_info().await?.epoch;
+        let mut best: Option<RouteQuote> = None;
+        for path in &paths {
+            match self.quote_path(path, epoch, amount).await {
+                Ok(route) => {
+                    if best
+                        .as_ref()
+                        .map_or(true, |best| route.amount_out() > best.amount_out())
+                    {
+                        best = Some(route);
+                    }
+                }
+                Err(e) => log::debug!("Skipping route through {:?}: {}", path_pools(path), e),
+            }
+        }
+
+        best.ok_or_else(|| AppError::NoRoute(input_mint, output_mint).into())
+    }
+
+    /// The pool of a hop, loaded by [`Router::best_route`]
+    pub fn pool(&self, hop: &Hop) -> anyhow::Result<&Pool> {
+        self.pools
+            .get(&hop.pool)
+            .ok_or_else(|| anyhow::anyhow!("pool {} is not loaded", hop.pool))
+    }
+
+    /// Chains exact input `swap_v2` instructions of the route in one transaction. Every leg
+    /// after the first spends the minimum output of the previous one, so slippage applies
+    /// per leg and what the pools return on top of it stays in the intermediate accounts.
+    /// Those are the payer's associated token accounts and are created when missing, `setup`
+    /// runs before all of it. The compute unit limit is sized by simulating the transaction.
+    pub async fn swap(
+        &self,
+        route: &RouteQuote,
+        payer: Keypair,
+        params: RouteSwapParams<'_>,
+        simulate: bool,
+    ) -> anyhow::Result<TransactionResult> {
+        let mut instructions = params.setup.to_vec();
+
+        // intermediate token accounts, created with the token program owning the mint
+        let intermediate_mints: Vec<Pubkey> = route.legs[..route.legs.len() - 1]
+            .iter()
+            .map(|leg| leg.hop.output_mint)
+            .collect();
+        let mint_accounts = self.rpc.get_multiple_accounts(&intermediate_mints).await?;
+        let mut intermediate_tokens = Vec::with_capacity(intermediate_mints.len());
+        for (mint, account) in intermediate_mints.iter().zip(mint_accounts) {
+            let token_program = account
+                .ok_or_else(|| anyhow::anyhow!("mint {} does not exist", mint))?
+                .owner;
+            instructions.push(create_associated_token_account_idempotent(
+                &payer.pubkey(),
+                &payer.pubkey(),
+                mint,
+                &token_program,
+            ));
+            intermediate_tokens.push(get_associated_token_address_with_program_id(
+                &payer.pubkey(),
+                mint,
+                &token_program,
+            ));
+        }
+
+        let epoch = self.rpc.get_epoch_info().await?.epoch;
+        let cluster = Cluster::Custom("".to_string(), "".to_string());
+        let client = Client::new(cluster, &payer);
+        let mut amount = route.amount_in();
+        for (i, leg) in route.legs.iter().enumerate() {
+            let pool = self.pool(&leg.hop)?;
+            // later legs spend less than quoted, their tick arrays may differ as well
+            let (quote, tick_arrays) = if i == 0 {
+                (leg.quote, leg.tick_arrays.clone())
+            } else {
+                pool.simulate_swap_loading(
+                    &self.rpc,
+                    epoch,
+                    amount,
+                    None,
+                    leg.hop.zero_for_one,
+                    true,
+                )
+                .await?
+            };
+            let minimum_out = amount_with_slippage(quote.amount_out, params.slippage, false);
+
+            let input = if i == 0 {
+                params.input_token
+            } else {
+                intermediate_tokens[i - 1]
+            };
+            let output = intermediate_tokens
+                .get(i)
+                .copied()
+                .unwrap_or(params.output_token);
+            instructions.extend(pool.swap_instruction(
+                client.program(self.raydium_program_id)?,
+                input,
+                output,
+                leg.hop.zero_for_one,
+                pool.remaining_accounts(&self.raydium_program_id, tick_arrays)?,
+                amount,
+                minimum_out,
+                None,
+                true,
+            )?);
+            amount = minimum_out;
+        }
+
+        let instructions = compute_budget::with_compute_budget(
+            &self.rpc,
+            &payer,
+            instructions,
+            params.priority_fee,
+        )
+        .await?;
+        utils::send_or_simulate(&self.rpc, &instructions, &payer, simulate).await
+    }
+
+    /// Loads all pools of `hops` that are not loaded yet, concurrently
+    async fn load_pools(&mut self, hops: &[Hop]) {
+        let mut missing: Vec<&Hop> = hops
+            .iter()
+            .filter(|hop| !self.pools.contains_key(&hop.pool))
+            .collect();
+        missing.sort_by_key(|hop| hop.pool);
+        missing.dedup_by_key(|hop| hop.pool);
+
+        let loaded = join_all(missing.into_iter().map(|hop| {
+            let (mint0, mint1) = if hop.zero_for_one {
+                (hop.input_mint, hop.output_mint)
+            } else {
+                (hop.output_mint, hop.input_mint)
+            };
+            let addresses = PoolAddresses::with_amm_config(
+                &self.raydium_program_id,
+                hop.amm_config,
+                mint0,
+                mint1,
+            );
+            Pool::load(
+                self.rpc.clone(),
+                &self.raydium_program_id,
+                LoadPoolAccounts {
+                    amm_config: addresses.amm_config,
+                    pool_id: addresses.pool_state,
+                    tickarray_bitmap_extension: addresses.tick_array_bitmap_extension,
+                    mint0,
+                    mint1,
+                },
+                self.max_tick_arrays,
+            )
+        }))
+        .await;
+
+        for pool in loaded {
+            match pool {
+                Ok(pool) => {
+                    self.pools.insert(pool.id(), pool);
+                }
+                Err(e) => log::debug!("Unable to load pool for routing: {}", e),
+            }
+        }
+    }
+
+    async fn quote_path(
+        &self,
+        path: &[Hop],
+        epoch: u64,
+        amount: u64,
+    ) -> anyhow::Result<RouteQuote> {
+        let mut legs = Vec::with_capacity(path.len());
+        let mut amount = amount;
+        for hop in path {
+            let (quote, tick_arrays) = self
+                .pool(hop)?
+                .simulate_swap_loading(&self.rpc, epoch, amount, None, hop.zero_for_one, true)
+                .await?;
+            // a partial fill cannot feed the next leg
+            if quote.limited || quote.amount_out == 0 {
+                anyhow::bail!("pool {} cannot fill {}", hop.pool, amount);
+            }
+            amount = quote.amount_out;
+            legs.push(Leg {
+                hop: *hop,
+                quote,
+                tick_arrays,
+            });
+        }
+
+        Ok(RouteQuote { legs })
+    }
+}
+
+fn path_pools(path: &[Hop]) -> Vec<Pubkey> {
+    path.iter().map(|hop| hop.pool).collect()
+}
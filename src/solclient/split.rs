@@ -0,0 +1,183 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use anchor_client::{Client, Cluster};
+use futures::future::join_all;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey, signature::Keypair};
+
+use crate::{constants::SPLIT_STEPS, error::SwapError};
+
+use super::{
+    pool::{LoadPoolAccounts, Pool, Quote},
+    pubsub::PoolAddresses,
+    registry::PairPool,
+    utils::amount_with_slippage,
+};
+
+/// Share of a split order going through one pool
+#[derive(Debug, Clone)]
+pub struct SplitLeg {
+    pub pool: PairPool,
+    pub quote: Quote,
+    /// Start indexes of the tick arrays the leg passes to the program
+    pub tick_arrays: VecDeque<i32>,
+}
+
+/// Exact input order split across pools of one pair, all legs swap in the same direction
+#[derive(Debug, Clone)]
+pub struct SplitQuote {
+    pub zero_for_one: bool,
+    pub legs: Vec<SplitLeg>,
+}
+
+impl SplitQuote {
+    pub fn amount_in(&self) -> u64 {
+        self.legs.iter().map(|leg| leg.quote.amount_in).sum()
+    }
+
+    pub fn amount_out(&self) -> u64 {
+        self.legs.iter().map(|leg| leg.quote.amount_out).sum()
+    }
+}
+
+/// Divides `amount` into [`SPLIT_STEPS`] chunks and hands each one to the pool whose
+/// output grows most by it. `quote(pool, amount)` is the output of `pool` for `amount`,
+/// `None` when the pool cannot fill it. Returns the amount per pool, `None` when the
+/// pools together cannot fill the order.
+pub fn allocate(
+    amount: u64,
+    pools: usize,
+    quote: impl Fn(usize, u64) -> Option<u64>,
+) -> Option<Vec<u64>> {
+    let steps = SPLIT_STEPS.min(amount.max(1));
+    let mut allocated = vec![0u64; pools];
+    let mut received = vec![0u64; pools];
+    for step in 0..steps {
+        let chunk = amount / steps + u64::from(step < amount % steps);
+        let (pool, amount_out) = (0..pools)
+            .filter_map(|pool| {
+                quote(pool, allocated[pool] + chunk).map(|amount_out| (pool, amount_out))
+            })
+            .max_by_key(|(pool, amount_out)| amount_out.saturating_sub(received[*pool]))?;
+        allocated[pool] += chunk;
+        received[pool] = amount_out;
+    }
+
+    Some(allocated)
+}
+
+/// Loads the pools of a pair with the tick arrays an exact input of `amount` may need in
+/// any of them. Pools that fail to load are left out.
+pub async fn load_pools(
+    rpc: &Arc<RpcClient>,
+    raydium_program_id: &Pubkey,
+    pairs: &[PairPool],
+    max_tick_arrays: usize,
+    epoch: u64,
+    amount: u64,
+    zero_for_one: bool,
+) -> Vec<(PairPool, Pool)> {
+    let loaded = join_all(pairs.iter().map(|pair| async move {
+        let addresses = PoolAddresses::with_amm_config(
+            raydium_program_id,
+            pair.amm_config,
+            pair.mint0,
+            pair.mint1,
+        );
+        let pool = Pool::load(
+            rpc.clone(),
+            raydium_program_id,
+            LoadPoolAccounts {
+                amm_config: addresses.amm_config,
+                pool_id: addresses.pool_state,
+                tickarray_bitmap_extension: addresses.tick_array_bitmap_extension,
+                mint0: pair.mint0,
+                mint1: pair.mint1,
+            },
+            max_tick_arrays,
+        )
+        .await?;
+        pool.preload_tick_arrays(rpc, epoch, amount, zero_for_one, true)
+            .await?;
+        anyhow::Ok((*pair, pool))
+    }))
+    .await;
+
+    loaded
+        .into_iter()
+        .filter_map(|loaded| {
+            loaded
+                .map_err(|e| log::debug!("Unable to load pool for splitting: {}", e))
+                .ok()
+        })
+        .collect()
+}
+
+/// Best split of an exact input `amount` across `pools`, see [`allocate`]
+pub fn split_order(
+    pools: &[(PairPool, Pool)],
+    epoch: u64,
+    amount: u64,
+    zero_for_one: bool,
+) -> anyhow::Result<SplitQuote> {
+    let allocated = allocate(amount, pools.len(), |i, amount| {
+        pools[i]
+            .1
+            .quote_at_epoch(epoch, amount, None, zero_for_one, true)
+            .ok()
+            .filter(|quote| !quote.limited)
+            .map(|quote| quote.amount_out)
+    })
+    .ok_or(SwapError::InsufficientLiquidity)?;
+
+    let mut legs = Vec::new();
+    for ((pair, pool), amount) in pools.iter().zip(allocated) {
+        if amount == 0 {
+            continue;
+        }
+        let (quote, tick_arrays) = pool.simulate_swap(epoch, amount, None, zero_for_one, true)?;
+        legs.push(SplitLeg {
+            pool: *pair,
+            quote,
+            tick_arrays,
+        });
+    }
+
+    Ok(SplitQuote { zero_for_one, legs })
+}
+
+/// One `swap_v2` per leg, all from `input_token` to `output_token`, each with its own
+/// minimum output, compute budget left to the caller
+pub fn swap_instructions(
+    pools: &[(PairPool, Pool)],
+    split: &SplitQuote,
+    payer: &Keypair,
+    raydium_program_id: &Pubkey,
+    input_token: Pubkey,
+    output_token: Pubkey,
+    slippage: f64,
+) -> anyhow::Result<Vec<Instruction>> {
+    let mut instructions = vec![];
+    let cluster = Cluster::Custom("".to_string(), "".to_string());
+    let client = Client::new(cluster, payer);
+
+    for leg in &split.legs {
+        let (_, pool) = pools
+            .iter()
+            .find(|(pair, _)| pair.pool == leg.pool.pool)
+            .ok_or_else(|| anyhow::anyhow!("pool {} is not loaded", leg.pool.pool))?;
+        instructions.extend(pool.swap_instruction(
+            client.program(*raydium_program_id)?,
+            input_token,
+            output_token,
+            split.zero_for_one,
+            pool.remaining_accounts(raydium_program_id, leg.tick_arrays.clone())?,
+            leg.quote.amount_in,
+            amount_with_slippage(leg.quote.amount_out, slippage, false),
+            None,
+            true,
+        )?);
+    }
+
+    Ok(instructions)
+}
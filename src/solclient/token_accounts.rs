@@ -0,0 +1,175 @@
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::Instruction, program_pack::Pack, pubkey::Pubkey, system_instruction,
+    system_program,
+};
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+use spl_token::{instruction as token_instruction, native_mint};
+
+/// What the swap endpoint takes as the mint of native SOL, swapped as WSOL
+pub const NATIVE_SOL: Pubkey = system_program::ID;
+
+/// Token accounts of the payer a swap spends from and receives into
+#[derive(Debug, Clone)]
+pub struct SwapAccounts {
+    pub input_token: Pubkey,
+    pub output_token: Pubkey,
+    /// Runs before the swap in the same transaction, creates the output account if missing
+    pub setup: Vec<Instruction>,
+    /// Runs after the swap in the same transaction, closes temporary WSOL accounts
+    pub cleanup: Vec<Instruction>,
+}
+
+impl SwapAccounts {
+    /// Token accounts of the payer for a swap, the given ones and the payer's associated
+    /// token accounts of the mints for the ones left out. Explicit accounts are used as they
+    /// are, a derived output account that does not exist yet is created by the swap
+    /// transaction.
+    pub async fn resolve(
+        rpc: &RpcClient,
+        payer: &Pubkey,
+        input_mint: &Pubkey,
+        output_mint: &Pubkey,
+        input_token: Option<Pubkey>,
+        output_token: Option<Pubkey>,
+    ) -> anyhow::Result<Self> {
+        let programs = token_programs(rpc, &[*input_mint, *output_mint]).await?;
+        let mut accounts = Self::derive(
+            payer,
+            input_mint,
+            &programs[0],
+            output_mint,
+            &programs[1],
+            input_token,
+            output_token,
+        );
+        if !accounts.setup.is_empty()
+            && rpc
+                .get_account_with_commitment(&accounts.output_token, rpc.commitment())
+                .await?
+                .value
+                .is_some()
+        {
+            accounts.setup.clear();
+        }
+        Ok(accounts)
+    }
+
+    /// Accounts of mints owned by the given token programs, either token or token-2022,
+    /// whose associated token accounts differ. A derived output account comes with its
+    /// idempotent creation.
+    pub fn derive(
+        payer: &Pubkey,
+        input_mint: &Pubkey,
+        input_program: &Pubkey,
+        output_mint: &Pubkey,
+        output_program: &Pubkey,
+        input_token: Option<Pubkey>,
+        output_token: Option<Pubkey>,
+    ) -> Self {
+        let input_token = input_token.unwrap_or_else(|| {
+            get_associated_token_address_with_program_id(payer, input_mint, input_program)
+        });
+        let (output_token, setup) = match output_token {
+            Some(output_token) => (output_token, vec![]),
+            None => (
+                get_associated_token_address_with_program_id(payer, output_mint, output_program),
+                vec![create_associated_token_account_idempotent(
+                    payer,
+                    payer,
+                    output_mint,
+                    output_program,
+                )],
+            ),
+        };
+
+        Self {
+            input_token,
+            output_token,
+            setup,
+            cleanup: vec![],
+        }
+    }
+
+    /// Adds the creation and the closing of a temporary WSOL account around the swap
+    pub fn wrap(&mut self, native: NativeAccount) {
+        self.setup.extend(native.setup);
+        self.cleanup.extend(native.cleanup);
+    }
+}
+
+/// A temporary WSOL account of the payer standing in for native SOL on one side of a swap.
+/// It is created at an address derived from the payer and `seed`, so the payer is its only
+/// signer, and closed after the swap with everything left in it going back to the payer.
+#[derive(Debug, Clone)]
+pub struct NativeAccount {
+    pub address: Pubkey,
+    pub setup: Vec<Instruction>,
+    pub cleanup: Vec<Instruction>,
+}
+
+impl NativeAccount {
+    /// Wraps `lamports` into the account, none for the output side. `rent` is the rent
+    /// exemption of a token account.
+    pub fn new(payer: &Pubkey, seed: &str, rent: u64, lamports: u64) -> anyhow::Result<Self> {
+        let token_program = spl_token::id();
+        let address = Pubkey::create_with_seed(payer, seed, &token_program)?;
+
+        let mut setup = vec![
+            system_instruction::create_account_with_seed(
+                payer,
+                &address,
+                payer,
+                seed,
+                rent,
+                spl_token::state::Account::LEN as u64,
+                &token_program,
+            ),
+            token_instruction::initialize_account3(
+                &token_program,
+                &address,
+                &native_mint::id(),
+                payer,
+            )?,
+        ];
+        if lamports > 0 {
+            setup.push(system_instruction::transfer(payer, &address, lamports));
+            setup.push(token_instruction::sync_native(&token_program, &address)?);
+        }
+        let cleanup = vec![token_instruction::close_account(
+            &token_program,
+            &address,
+            payer,
+            payer,
+            &[],
+        )?];
+
+        Ok(Self {
+            address,
+            setup,
+            cleanup,
+        })
+    }
+
+    /// A seed no other swap in flight uses, seeds are limited to 32 bytes
+    pub fn unique_seed() -> String {
+        format!("wsol{:016x}", rand::random::<u64>())
+    }
+}
+
+/// The token program owning each of `mints`
+pub async fn token_programs(rpc: &RpcClient, mints: &[Pubkey]) -> anyhow::Result<Vec<Pubkey>> {
+    rpc.get_multiple_accounts(mints)
+        .await?
+        .into_iter()
+        .zip(mints)
+        .map(|(account, mint)| {
+            account
+                .map(|account| account.owner)
+                .ok_or_else(|| anyhow::anyhow!("mint {} does not exist", mint))
+        })
+        .collect()
+}
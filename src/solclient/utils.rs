@@ -7,7 +7,7 @@ use anchor_client::Program;
 use anchor_lang::prelude::AccountMeta;
 use raydium_amm_v3::{
     accounts as raydium_accounts, instruction as raydium_instruction,
-    libraries::{liquidity_math, swap_math, tick_math, MAX_TICK, MIN_TICK},
+    libraries::{fixed_point_64, liquidity_math, swap_math, tick_math, MAX_TICK, MIN_TICK},
     states::{AmmConfig, PoolState, TickArrayBitmapExtension, TickArrayState, TickState},
 };
 use serde::{Deserialize, Serialize};
@@ -18,19 +18,28 @@ use solana_client::{
     rpc_response::{RpcResult, RpcSimulateTransactionResult},
 };
 use solana_sdk::{
+    account::Account,
     commitment_config::CommitmentConfig,
+    hash::hash,
     instruction::Instruction,
+    packet::PACKET_DATA_SIZE,
     pubkey::Pubkey,
     signature::{Keypair, Signature},
+    signer::Signer,
     transaction::Transaction,
 };
-use spl_token_2022::extension::{
-    transfer_fee::{TransferFeeConfig, MAX_FEE_BASIS_POINTS},
-    BaseState, BaseStateWithExtensions, StateWithExtensionsMut,
+use spl_token_2022::{
+    extension::{
+        transfer_fee::{TransferFeeConfig, MAX_FEE_BASIS_POINTS},
+        BaseState, BaseStateWithExtensions, StateWithExtensions, StateWithExtensionsMut,
+    },
+    state::{Account as TokenAccount, Mint},
 };
 
+use crate::error::SwapError;
+
 // the top level state of the swap, the results of which are recorded in storage at the end
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct SwapState {
     // the amount remaining to be swapped in/out of the input/output asset
     pub amount_specified_remaining: u64,
@@ -42,6 +51,10 @@ pub struct SwapState {
     pub tick: i32,
     // the current liquidity in range
     pub liquidity: u128,
+    // the trade fee paid to the pool, in the input asset
+    pub fee_amount: u64,
+    // how many initialized ticks the swap crossed
+    pub ticks_crossed: u32,
 }
 #[derive(Default)]
 struct StepComputations {
@@ -71,12 +84,12 @@ pub fn get_out_put_amount_and_remaining_accounts(
     pool_state: &PoolState,
     tickarray_bitmap_extension: &TickArrayBitmapExtension,
     tick_arrays: &mut VecDeque<TickArrayState>,
-) -> Result<(u64, VecDeque<i32>), &'static str> {
+) -> Result<(SwapState, VecDeque<i32>), SwapError> {
     let (is_pool_current_tick_array, current_vaild_tick_array_start_index) = pool_state
         .get_first_initialized_tick_array(&Some(*tickarray_bitmap_extension), zero_for_one)
-        .unwrap();
+        .map_err(|_| SwapError::InsufficientLiquidity)?;
 
-    let (amount_calculated, tick_array_start_index_vec) = swap_compute(
+    let (state, tick_array_start_index_vec) = swap_compute(
         zero_for_one,
         is_base_input,
         is_pool_current_tick_array,
@@ -88,9 +101,8 @@ pub fn get_out_put_amount_and_remaining_accounts(
         tickarray_bitmap_extension,
         tick_arrays,
     )?;
-    println!("tick_array_start_index:{:?}", tick_array_start_index_vec);
 
-    Ok((amount_calculated, tick_array_start_index_vec))
+    Ok((state, tick_array_start_index_vec))
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -100,15 +112,16 @@ fn swap_compute(
     is_pool_current_tick_array: bool,
     fee: u32,
     amount_specified: u64,
-    current_vaild_tick_array_start_index: i32,
+    mut current_vaild_tick_array_start_index: i32,
     sqrt_price_limit_x64: u128,
     pool_state: &PoolState,
     tickarray_bitmap_extension: &TickArrayBitmapExtension,
     tick_arrays: &mut VecDeque<TickArrayState>,
-) -> Result<(u64, VecDeque<i32>), &'static str> {
+) -> Result<(SwapState, VecDeque<i32>), SwapError> {
     if amount_specified == 0 {
-        return Result::Err("amountSpecified must not be 0");
+        return Err(SwapError::InvalidInput("amountSpecified must not be 0"));
     }
+    let has_sqrt_price_limit = sqrt_price_limit_x64 != 0;
     let sqrt_price_limit_x64 = if sqrt_price_limit_x64 == 0 {
         if zero_for_one {
             tick_math::MIN_SQRT_PRICE_X64 + 1
@@ -120,17 +133,25 @@ fn swap_compute(
     };
     if zero_for_one {
         if sqrt_price_limit_x64 < tick_math::MIN_SQRT_PRICE_X64 {
-            return Result::Err("sqrt_price_limit_x64 must greater than MIN_SQRT_PRICE_X64");
+            return Err(SwapError::InvalidInput(
+                "sqrt_price_limit_x64 must greater than MIN_SQRT_PRICE_X64",
+            ));
         }
         if sqrt_price_limit_x64 >= pool_state.sqrt_price_x64 {
-            return Result::Err("sqrt_price_limit_x64 must smaller than current");
+            return Err(SwapError::InvalidInput(
+                "sqrt_price_limit_x64 must smaller than current",
+            ));
         }
     } else {
         if sqrt_price_limit_x64 > tick_math::MAX_SQRT_PRICE_X64 {
-            return Result::Err("sqrt_price_limit_x64 must smaller than MAX_SQRT_PRICE_X64");
+            return Err(SwapError::InvalidInput(
+                "sqrt_price_limit_x64 must smaller than MAX_SQRT_PRICE_X64",
+            ));
         }
         if sqrt_price_limit_x64 <= pool_state.sqrt_price_x64 {
-            return Result::Err("sqrt_price_limit_x64 must greater than current");
+            return Err(SwapError::InvalidInput(
+                "sqrt_price_limit_x64 must greater than current",
+            ));
         }
     }
     let mut tick_match_current_tick_array = is_pool_current_tick_array;
@@ -141,24 +162,20 @@ fn swap_compute(
         sqrt_price_x64: pool_state.sqrt_price_x64,
         tick: pool_state.tick_current,
         liquidity: pool_state.liquidity,
+        fee_amount: 0,
+        ticks_crossed: 0,
     };
 
-    let mut tick_array_current = tick_arrays.pop_front().unwrap();
-    if tick_array_current.start_tick_index != current_vaild_tick_array_start_index {
-        return Result::Err("tick array start tick index does not match");
-    }
+    let mut tick_array_current =
+        next_tick_array(tick_arrays, current_vaild_tick_array_start_index)?;
     let mut tick_array_start_index_vec = VecDeque::new();
     tick_array_start_index_vec.push_back(tick_array_current.start_tick_index);
-    let mut loop_count = 0;
     // loop across ticks until input liquidity is consumed, or the limit price is reached
     while state.amount_specified_remaining != 0
         && state.sqrt_price_x64 != sqrt_price_limit_x64
         && state.tick < tick_math::MAX_TICK
         && state.tick > tick_math::MIN_TICK
     {
-        if loop_count > 10 {
-            return Result::Err("loop_count limit");
-        }
         let mut step = StepComputations::default();
         step.sqrt_price_start_x64 = state.sqrt_price_x64;
         // save the bitmap, and the tick account if it is initialized
@@ -178,21 +195,17 @@ fn swap_compute(
             Box::new(TickState::default())
         };
         if !next_initialized_tick.is_initialized() {
-            let current_vaild_tick_array_start_index = pool_state
+            // no initialized tick left in this tick array, continue with the next one
+            current_vaild_tick_array_start_index = pool_state
                 .next_initialized_tick_array_start_index(
                     &Some(*tickarray_bitmap_extension),
                     current_vaild_tick_array_start_index,
                     zero_for_one,
                 )
-                .unwrap();
-            tick_array_current = tick_arrays.pop_front().unwrap();
-            if current_vaild_tick_array_start_index.is_none() {
-                return Result::Err("tick array start tick index out of range limit");
-            }
-            if tick_array_current.start_tick_index != current_vaild_tick_array_start_index.unwrap()
-            {
-                return Result::Err("tick array start tick index does not match");
-            }
+                .map_err(|_| SwapError::InsufficientLiquidity)?
+                .ok_or(SwapError::InsufficientLiquidity)?;
+            tick_array_current =
+                next_tick_array(tick_arrays, current_vaild_tick_array_start_index)?;
             tick_array_start_index_vec.push_back(tick_array_current.start_tick_index);
             let mut first_initialized_tick = tick_array_current
                 .first_initialized_tick(zero_for_one)
@@ -228,6 +241,7 @@ fn swap_compute(
         step.amount_in = swap_step.amount_in;
         step.amount_out = swap_step.amount_out;
         step.fee_amount = swap_step.fee_amount;
+        state.fee_amount = state.fee_amount.checked_add(step.fee_amount).unwrap();
 
         if is_base_input {
             state.amount_specified_remaining = state
@@ -258,6 +272,7 @@ fn swap_compute(
                 }
                 state.liquidity =
                     liquidity_math::add_delta(state.liquidity, liquidity_net).unwrap();
+                state.ticks_crossed += 1;
             }
 
             state.tick = if zero_for_one {
@@ -269,10 +284,52 @@ fn swap_compute(
             // recompute unless we're on a lower tick boundary (i.e. already transitioned ticks), and haven't moved
             state.tick = tick_math::get_tick_at_sqrt_price(state.sqrt_price_x64).unwrap();
         }
-        loop_count += 1;
     }
+    // without a price limit an exact output swap has to be filled completely, otherwise
+    // amount_calculated is only the input for the part of the output the pool can provide
+    if !is_base_input && !has_sqrt_price_limit && state.amount_specified_remaining != 0 {
+        return Err(SwapError::InsufficientLiquidity);
+    }
+
+    Ok((state, tick_array_start_index_vec))
+}
 
-    Ok((state.amount_calculated, tick_array_start_index_vec))
+/// Converts a human readable price of mint0 denominated in mint1 into a Q64.64 sqrt price.
+/// Prices that are not positive, or outside of what a pool can reach, are rejected.
+pub fn price_to_sqrt_price_x64(price: f64, decimals_0: u8, decimals_1: u8) -> anyhow::Result<u128> {
+    if !(price.is_finite() && price > 0f64) {
+        anyhow::bail!("price {} has to be a positive number", price);
+    }
+    let price_with_decimals = price * 10f64.powi(decimals_1 as i32 - decimals_0 as i32);
+    let sqrt_price_x64 = price_with_decimals.sqrt() * fixed_point_64::Q64 as f64;
+    if !(tick_math::MIN_SQRT_PRICE_X64 as f64..=tick_math::MAX_SQRT_PRICE_X64 as f64)
+        .contains(&sqrt_price_x64)
+    {
+        anyhow::bail!("price {} is out of the range of a pool", price);
+    }
+    Ok(sqrt_price_x64 as u128)
+}
+
+/// Inverse of [`price_to_sqrt_price_x64`]
+pub fn sqrt_price_x64_to_price(sqrt_price_x64: u128, decimals_0: u8, decimals_1: u8) -> f64 {
+    let sqrt_price = sqrt_price_x64 as f64 / fixed_point_64::Q64 as f64;
+    sqrt_price * sqrt_price * 10f64.powi(decimals_0 as i32 - decimals_1 as i32)
+}
+
+/// Takes the next tick array the swap walks into, it has to start at `start_index`
+fn next_tick_array(
+    tick_arrays: &mut VecDeque<TickArrayState>,
+    start_index: i32,
+) -> Result<TickArrayState, SwapError> {
+    let tick_array = tick_arrays
+        .pop_front()
+        .ok_or(SwapError::TickArrayNotLoaded(start_index))?;
+    if tick_array.start_tick_index != start_index {
+        return Err(SwapError::InvalidInput(
+            "tick array start tick index does not match",
+        ));
+    }
+    Ok(tick_array)
 }
 
 // 根据滑点（slippage）和方向（是否向上取整 round_up），计算交易时考虑滑点后的金额（amount_with_slippage）
@@ -350,35 +407,10 @@ pub fn swap_v2_instr(
     Ok(instructions)
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct SimulateDebugResult {
-    pub simulation: serde_json::Value,
-    pub debug: DebugSwapParams,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct DebugSwapParams {
-    pub amm_config: String,
-    pub pool_id: String,
-    pub input_vault: String,
-    pub output_vault: String,
-    pub observation_state: String,
-    pub user_input_token: String,
-    pub user_output_token: String,
-    pub input_vault_mint: String,
-    pub output_vault_mint: String,
-    pub remaining_accounts: Vec<String>,
-    pub amount: u64,
-    pub other_amount_threshold: u64,
-    pub sqrt_price_limit_x64: Option<u128>,
-    pub is_base_input: bool,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum TransactionResult {
     Simulate(RpcSimulateTransactionResult),
-    SimulateDebug(SimulateDebugResult),
     Send(String),
 }
 
@@ -399,6 +431,70 @@ pub async fn simulate_transaction(
         .await
 }
 
+/// Decimals of each of `mints`, token-2022 mints included
+pub async fn mint_decimals(client: &RpcClient, mints: &[Pubkey]) -> anyhow::Result<Vec<u8>> {
+    client
+        .get_multiple_accounts(mints)
+        .await?
+        .into_iter()
+        .zip(mints)
+        .map(|(account, mint)| {
+            let account = account.ok_or_else(|| anyhow::anyhow!("mint {} does not exist", mint))?;
+            Ok(StateWithExtensions::<Mint>::unpack(&account.data)?
+                .base
+                .decimals)
+        })
+        .collect()
+}
+
+/// Balance of a token account, token-2022 ones included
+pub fn token_amount(account: &Account) -> anyhow::Result<u64> {
+    Ok(StateWithExtensions::<TokenAccount>::unpack(&account.data)?
+        .base
+        .amount)
+}
+
+/// First 8 bytes of the sha256 of `preimage`, which is how anchor programs tell accounts
+/// (`account:<Name>`) and instructions (`global:<name>`) apart
+pub fn anchor_discriminator(preimage: &str) -> [u8; 8] {
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash(preimage.as_bytes()).to_bytes()[..8]);
+    discriminator
+}
+
+/// Signs `instructions` with the payer, then simulates or sends them. Transactions that
+/// do not fit into a packet are rejected before they reach the RPC.
+pub async fn send_or_simulate(
+    client: &RpcClient,
+    instructions: &[Instruction],
+    payer: &Keypair,
+    simulate: bool,
+) -> anyhow::Result<TransactionResult> {
+    let recent_hash = client.get_latest_blockhash().await?;
+    let txn = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_hash,
+    );
+    let size = bincode::serialized_size(&txn)? as usize;
+    if size > PACKET_DATA_SIZE {
+        anyhow::bail!(
+            "swap needs a {} byte transaction, at most {} fit",
+            size,
+            PACKET_DATA_SIZE
+        );
+    }
+
+    if simulate {
+        let ret = simulate_transaction(client, &txn, true, CommitmentConfig::confirmed()).await?;
+        return Ok(TransactionResult::Simulate(ret.value));
+    }
+
+    let signature = send_txn(client, &txn, true).await?;
+    Ok(TransactionResult::Send(signature.to_string()))
+}
+
 pub async fn send_txn(
     client: &RpcClient,
     txn: &Transaction,
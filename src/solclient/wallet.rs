@@ -0,0 +1,186 @@
+use std::str::FromStr;
+
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_request::RpcRequest,
+    rpc_response::{Response, RpcKeyedAccount},
+};
+use solana_sdk::{
+    account::Account, instruction::Instruction, message::Message, pubkey::Pubkey,
+    signature::Keypair, signer::Signer, system_instruction,
+};
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+use spl_token_2022::{extension::StateWithExtensions, state::Account as TokenAccount};
+
+use super::utils::{self, TransactionResult};
+
+/// Token accounts emptied per sweep transaction, each one takes an idempotent ATA
+/// creation, a transfer and a close
+pub const SWEEP_ACCOUNTS_PER_TX: usize = 4;
+
+/// A token account of a wallet
+#[derive(Debug, Clone)]
+pub struct TokenBalance {
+    pub account: Pubkey,
+    pub mint: Pubkey,
+    /// The token program the account belongs to, token or token-2022
+    pub program_id: Pubkey,
+    pub amount: u64,
+    pub decimals: u8,
+}
+
+/// Every token account `owner` has with either token program, empty ones included
+pub async fn token_balances(rpc: &RpcClient, owner: &Pubkey) -> anyhow::Result<Vec<TokenBalance>> {
+    // the typed client method asks for jsonParsed, raw accounts decode like everywhere else
+    let config = serde_json::json!({
+        "encoding": UiAccountEncoding::Base64,
+        "commitment": rpc.commitment().commitment,
+    });
+    let mut accounts = vec![];
+    for program_id in [spl_token::id(), spl_token_2022::id()] {
+        let filter = serde_json::json!({"programId": program_id.to_string()});
+        let response: Response<Vec<RpcKeyedAccount>> = rpc
+            .send(
+                RpcRequest::GetTokenAccountsByOwner,
+                serde_json::json!([owner.to_string(), filter, config]),
+            )
+            .await?;
+        for keyed in response.value {
+            let account = keyed.account.decode::<Account>().ok_or_else(|| {
+                anyhow::anyhow!("unable to decode token account {}", keyed.pubkey)
+            })?;
+            let state = StateWithExtensions::<TokenAccount>::unpack(&account.data)?;
+            accounts.push((
+                Pubkey::from_str(&keyed.pubkey)?,
+                program_id,
+                state.base.mint,
+                state.base.amount,
+            ));
+        }
+    }
+
+    let mints: Vec<Pubkey> = accounts.iter().map(|(_, _, mint, _)| *mint).collect();
+    let decimals = utils::mint_decimals(rpc, &mints).await?;
+    Ok(accounts
+        .into_iter()
+        .zip(decimals)
+        .map(
+            |((account, program_id, mint, amount), decimals)| TokenBalance {
+                account,
+                mint,
+                program_id,
+                amount,
+                decimals,
+            },
+        )
+        .collect())
+}
+
+/// Moves every token and then all SOL of `wallet` to `destination`, see
+/// `sweep_token_instructions` and `sweep_sol_instruction`. Returns the signatures of the
+/// transactions sent, in order.
+pub async fn sweep(
+    rpc: &RpcClient,
+    wallet: &Keypair,
+    destination: &Pubkey,
+) -> anyhow::Result<Vec<String>> {
+    let owner = wallet.pubkey();
+    let mut signatures = vec![];
+
+    let tokens = token_balances(rpc, &owner).await?;
+    for instructions in sweep_token_instructions(&owner, &tokens, destination)? {
+        signatures.push(send(rpc, &instructions, wallet).await?);
+    }
+
+    // whatever is left after the fees of the token transactions, minus the fee of this one
+    let balance = rpc.get_balance(&owner).await?;
+    let message = Message::new(
+        &[system_instruction::transfer(&owner, destination, balance)],
+        Some(&owner),
+    );
+    let fee = rpc.get_fee_for_message(&message).await?;
+    if let Some(transfer) = sweep_sol_instruction(&owner, destination, balance, fee) {
+        signatures.push(send(rpc, &[transfer], wallet).await?);
+    }
+    Ok(signatures)
+}
+
+/// The token transactions of a sweep of `owner`, `SWEEP_ACCOUNTS_PER_TX` token accounts
+/// each. Tokens go to the associated token accounts of `destination`, created when
+/// missing, and the emptied token accounts are closed with their rent going to
+/// `destination` as well. Token-2022 accounts are left open, fees withheld in them would
+/// make the close fail.
+pub fn sweep_token_instructions(
+    owner: &Pubkey,
+    tokens: &[TokenBalance],
+    destination: &Pubkey,
+) -> anyhow::Result<Vec<Vec<Instruction>>> {
+    let mut transactions = vec![];
+    for chunk in tokens.chunks(SWEEP_ACCOUNTS_PER_TX) {
+        let mut instructions = vec![];
+        for token in chunk {
+            if token.amount > 0 {
+                let target = get_associated_token_address_with_program_id(
+                    destination,
+                    &token.mint,
+                    &token.program_id,
+                );
+                instructions.push(create_associated_token_account_idempotent(
+                    owner,
+                    destination,
+                    &token.mint,
+                    &token.program_id,
+                ));
+                instructions.push(spl_token_2022::instruction::transfer_checked(
+                    &token.program_id,
+                    &token.account,
+                    &token.mint,
+                    &target,
+                    owner,
+                    &[],
+                    token.amount,
+                    token.decimals,
+                )?);
+            }
+            if token.program_id == spl_token::id() {
+                instructions.push(spl_token_2022::instruction::close_account(
+                    &token.program_id,
+                    &token.account,
+                    destination,
+                    owner,
+                    &[],
+                )?);
+            }
+        }
+        if !instructions.is_empty() {
+            transactions.push(instructions);
+        }
+    }
+    Ok(transactions)
+}
+
+/// The transfer of the `balance` of `owner` left after the token transactions, minus the
+/// `fee` of its own transaction. None when nothing would be left.
+pub fn sweep_sol_instruction(
+    owner: &Pubkey,
+    destination: &Pubkey,
+    balance: u64,
+    fee: u64,
+) -> Option<Instruction> {
+    (balance > fee).then(|| system_instruction::transfer(owner, destination, balance - fee))
+}
+
+async fn send(
+    rpc: &RpcClient,
+    instructions: &[Instruction],
+    wallet: &Keypair,
+) -> anyhow::Result<String> {
+    match utils::send_or_simulate(rpc, instructions, wallet, false).await? {
+        TransactionResult::Send(signature) => Ok(signature),
+        _ => anyhow::bail!("transaction was simulated instead of sent"),
+    }
+}
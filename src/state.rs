@@ -1,34 +1,49 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use solana_client::nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient};
 use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Keypair};
 use sqlx::PgPool;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 
-use crate::{error::Result, solclient::pubsub::ClmmBroadcast};
+use crate::{
+    error::Result,
+    models::{account::Keyring, price_tick},
+    solclient::{market::RaydiumPrograms, pubsub::PoolBroadcast, registry::PoolRegistry},
+};
 
 pub type Signer = Arc<Keypair>;
 
 #[derive(Clone)]
 pub struct AppState {
     pub rpc: Arc<RpcClient>,
-    pub broadcast: Arc<Mutex<ClmmBroadcast>>,
+    pub broadcast: Arc<Mutex<PoolBroadcast>>,
+    pub registry: Arc<PoolRegistry>,
     pub pool: PgPool,
-    // pub jwt_secret: String,
-    // pub masterkey: Vec<u8>,
+    pub jwt_secret: String,
+    /// Accounts allowed to use the admin endpoints
+    pub admin_accounts: Vec<i32>,
+    /// Keys the wallets are sealed with
+    pub masterkeys: Keyring,
+    /// The CLMM program, i.e. `programs.clmm`
     pub raydium_program_id: Pubkey,
+    pub programs: RaydiumPrograms,
+    pub max_tick_arrays: usize,
 }
 
 impl AppState {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         solana_url: String,
         pool: PgPool,
-        // jwt_secret: String,
-        // masterkey: Vec<u8>,
-        raydium_program_id: Pubkey,
+        jwt_secret: String,
+        admin_accounts: Vec<i32>,
+        masterkeys: Keyring,
+        programs: RaydiumPrograms,
+        max_tick_arrays: usize,
+        grace_period: Duration,
     ) -> Result<Self> {
-        let pubsub =
-            Arc::new(PubsubClient::new(&solana_url.as_str().replace("https://", "wss://")).await?);
+        let pubsub_url = solana_url.as_str().replace("https://", "wss://");
+        let pubsub = Arc::new(PubsubClient::new(&pubsub_url).await?);
 
         let rpc = Arc::new(RpcClient::new_with_commitment(
             solana_url,
@@ -37,15 +52,37 @@ impl AppState {
             },
         ));
 
-        let broadcast = Arc::new(Mutex::new(ClmmBroadcast::new(rpc.clone(), pubsub)));
+        let registry = Arc::new(PoolRegistry::new(programs));
+        tokio::spawn(registry.clone().run(rpc.clone(), pubsub_url.clone()));
+
+        let (ticks_tx, ticks_rx) = mpsc::unbounded_channel();
+        tokio::spawn(price_tick::record(pool.clone(), ticks_rx));
+
+        let (dropped_tx, dropped_rx) = mpsc::unbounded_channel();
+        let broadcast = Arc::new(Mutex::new(PoolBroadcast::new(
+            rpc.clone(),
+            programs,
+            pubsub_url,
+            pubsub,
+            dropped_tx,
+            ticks_tx,
+            max_tick_arrays,
+            grace_period,
+        )));
+        tokio::spawn(PoolBroadcast::run_reaper(broadcast.clone()));
+        tokio::spawn(PoolBroadcast::run_supervisor(broadcast.clone(), dropped_rx));
 
         Ok(Self {
             rpc,
             broadcast,
+            registry,
             pool,
-            // jwt_secret,
-            // masterkey,
-            raydium_program_id,
+            jwt_secret,
+            admin_accounts,
+            masterkeys,
+            raydium_program_id: programs.clmm,
+            programs,
+            max_tick_arrays,
         })
     }
 }
@@ -0,0 +1,109 @@
+use actix_web::{http::StatusCode, test::TestRequest, Error};
+use jsonwebtoken::{EncodingKey, Header};
+use pricefeeder::{
+    extractors::account::{AdminGuard, AuthorizationGuard},
+    models::account::{Account, AccountClaims, Masterkey},
+};
+
+const JWT_SECRET: &[u8] = b"secret";
+
+fn masterkey() -> Masterkey {
+    Masterkey::new(vec![7; 32]).unwrap()
+}
+
+fn authorize(header: Option<&str>) -> Result<i32, Error> {
+    let mut req = TestRequest::default();
+    if let Some(header) = header {
+        req = req.insert_header(("Authorization", header));
+    }
+    AuthorizationGuard::from_header(&req.to_http_request(), JWT_SECRET).map(|guard| guard.0)
+}
+
+fn assert_unauthorized(result: Result<i32, Error>, message: &str) {
+    let err = result.err().expect("request should be rejected");
+    assert_eq!(
+        err.as_response_error().status_code(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(err.to_string(), message);
+}
+
+#[test]
+fn issued_token_authenticates_its_account() {
+    let mut account = Account::new_unique(&masterkey(), chrono::Duration::minutes(5)).unwrap();
+    account.id = 42;
+    let token = account.access_token(JWT_SECRET).unwrap();
+
+    assert_eq!(authorize(Some(&format!("Bearer {}", token))).unwrap(), 42);
+}
+
+#[test]
+fn missing_token_is_unauthorized() {
+    assert_unauthorized(authorize(None), "missing bearer token");
+}
+
+#[test]
+fn token_of_a_dead_wallet_is_expired() {
+    let account = Account::new_unique(&masterkey(), chrono::Duration::seconds(-10)).unwrap();
+    let token = account.access_token(JWT_SECRET).unwrap();
+
+    assert_unauthorized(
+        authorize(Some(&format!("Bearer {}", token))),
+        "token expired",
+    );
+}
+
+#[test]
+fn malformed_tokens_are_unauthorized() {
+    let account = Account::new_unique(&masterkey(), chrono::Duration::minutes(5)).unwrap();
+    let token = account.access_token(JWT_SECRET).unwrap();
+    let foreign = account.access_token(b"another secret").unwrap();
+    let claims = AccountClaims {
+        sub: "admin".to_string(),
+        exp: account.die_at.timestamp() as usize,
+    };
+    let not_an_id = jsonwebtoken::encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(JWT_SECRET),
+    )
+    .unwrap();
+
+    assert_unauthorized(authorize(Some(&token)), "missing bearer token");
+    for header in [
+        "Bearer not.a.jwt".to_string(),
+        "Bearer ".to_string(),
+        format!("Bearer {}", foreign),
+        format!("Bearer {}", not_an_id),
+    ] {
+        assert_unauthorized(authorize(Some(&header)), "invalid token");
+    }
+}
+
+#[test]
+fn only_admin_accounts_pass_the_admin_guard() {
+    let mut account = Account::new_unique(&masterkey(), chrono::Duration::minutes(5)).unwrap();
+    account.id = 42;
+    let token = account.access_token(JWT_SECRET).unwrap();
+    let req = TestRequest::default()
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_http_request();
+
+    assert_eq!(
+        AdminGuard::from_header(&req, JWT_SECRET, &[1, 42])
+            .unwrap()
+            .0,
+        42
+    );
+    for admins in [&[][..], &[1]] {
+        let err = AdminGuard::from_header(&req, JWT_SECRET, admins)
+            .err()
+            .expect("request should be rejected");
+        assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+    }
+    let anonymous = TestRequest::default().to_http_request();
+    assert_unauthorized(
+        AdminGuard::from_header(&anonymous, JWT_SECRET, &[42]).map(|guard| guard.0),
+        "missing bearer token",
+    );
+}
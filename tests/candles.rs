@@ -0,0 +1,63 @@
+use pricefeeder::{
+    models::price_tick::Resolution,
+    solclient::pool::{PriceTick, TickSource},
+};
+use raydium_amm_v3::states::PoolState;
+use solana_sdk::pubkey::Pubkey;
+
+fn pool_state(sqrt_price_x64: u128, liquidity: u128) -> PoolState {
+    let mut pool_state = PoolState::default();
+    pool_state.sqrt_price_x64 = sqrt_price_x64;
+    pool_state.liquidity = liquidity;
+    pool_state.mint_decimals_0 = 6;
+    pool_state.mint_decimals_1 = 6;
+    pool_state
+}
+
+#[test]
+fn resolutions_parse_like_the_query_gives_them() {
+    for (text, resolution) in [
+        ("\"1m\"", Resolution::OneMinute),
+        ("\"5m\"", Resolution::FiveMinutes),
+        ("\"1h\"", Resolution::OneHour),
+        ("\"1d\"", Resolution::OneDay),
+    ] {
+        assert_eq!(
+            serde_json::from_str::<Resolution>(text).unwrap(),
+            resolution
+        );
+    }
+    assert!(serde_json::from_str::<Resolution>("\"15m\"").is_err());
+}
+
+#[test]
+fn buckets_start_on_the_resolution() {
+    assert_eq!(Resolution::OneMinute.bucket(1_700_000_059), 1_700_000_040);
+    assert_eq!(Resolution::FiveMinutes.bucket(1_700_000_100), 1_700_000_100);
+    assert_eq!(Resolution::OneHour.bucket(1_700_003_599), 1_700_002_800);
+    assert_eq!(Resolution::OneDay.bucket(1_700_000_000), 1_699_920_000);
+}
+
+#[test]
+fn tick_volume_is_the_change_of_the_virtual_reserve() {
+    let previous = pool_state(1 << 64, 1_000_000_000);
+    let current = pool_state(2 << 64, 1_000_000_000);
+
+    let tick = PriceTick::new(Pubkey::new_unique(), 42, &previous, &current);
+
+    assert_eq!(tick.slot, 42);
+    assert_eq!(tick.source, TickSource::Live);
+    assert_eq!(tick.price, 4f64);
+    // L / sqrt(P) goes from 1e9 to 5e8, in units of 1e6
+    assert!((tick.volume0 - 500f64).abs() < 1e-9);
+}
+
+#[test]
+fn unchanged_price_has_no_volume() {
+    let state = pool_state(1 << 64, 1_000_000_000);
+
+    assert_eq!(
+        PriceTick::new(Pubkey::new_unique(), 1, &state, &state).volume0,
+        0f64
+    );
+}
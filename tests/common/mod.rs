@@ -0,0 +1,97 @@
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+
+use pricefeeder::solclient::utils::{get_out_put_amount_and_remaining_accounts, SwapState};
+use raydium_amm_v3::{
+    libraries::tick_math,
+    states::{AmmConfig, PoolState, TickArrayBitmapExtension, TickArrayState, TickState},
+};
+
+pub const TICK_SPACING: u16 = 10;
+pub const LIQUIDITY: u128 = 1_000_000_000_000;
+pub const TICK_LOWER: i32 = -590;
+pub const TICK_UPPER: i32 = 590;
+
+pub struct Fixture {
+    pub amm_config: AmmConfig,
+    pub pool_state: PoolState,
+    pub bitmap_extension: TickArrayBitmapExtension,
+    pub tick_arrays: VecDeque<TickArrayState>,
+}
+
+fn tick_array(start_tick_index: i32, tick: i32, liquidity_net: i128) -> TickArrayState {
+    let mut tick_array = TickArrayState::default();
+    tick_array.start_tick_index = start_tick_index;
+
+    let mut tick_state = TickState::default();
+    tick_state.tick = tick;
+    tick_state.liquidity_net = liquidity_net;
+    tick_state.liquidity_gross = LIQUIDITY;
+
+    let mut ticks = tick_array.ticks;
+    ticks[((tick - start_tick_index) / i32::from(TICK_SPACING)) as usize] = tick_state;
+    tick_array.ticks = ticks;
+    tick_array
+}
+
+/// A single position over [TICK_LOWER, TICK_UPPER], priced at tick 5 with a 0.25% trade fee.
+pub fn fixture(zero_for_one: bool) -> Fixture {
+    let mut amm_config = AmmConfig::default();
+    amm_config.trade_fee_rate = 2500;
+    amm_config.tick_spacing = TICK_SPACING;
+
+    let mut pool_state = PoolState::default();
+    pool_state.tick_spacing = TICK_SPACING;
+    pool_state.liquidity = LIQUIDITY;
+    pool_state.tick_current = 5;
+    pool_state.sqrt_price_x64 = tick_math::get_sqrt_price_at_tick(5).unwrap();
+
+    let mut bitmap = pool_state.tick_array_bitmap;
+    for start_tick_index in [-600, 0] {
+        let offset = pool_state.get_tick_array_offset(start_tick_index).unwrap();
+        bitmap[offset / 64] |= 1 << (offset % 64);
+    }
+    pool_state.tick_array_bitmap = bitmap;
+
+    let lower = tick_array(-600, TICK_LOWER, LIQUIDITY as i128);
+    let upper = tick_array(0, TICK_UPPER, -(LIQUIDITY as i128));
+    // tick arrays are consumed in swap direction, starting with the current one
+    let tick_arrays = if zero_for_one {
+        VecDeque::from([upper, lower])
+    } else {
+        VecDeque::from([upper])
+    };
+
+    Fixture {
+        amm_config,
+        pool_state,
+        bitmap_extension: TickArrayBitmapExtension::default(),
+        tick_arrays,
+    }
+}
+
+pub fn swap(
+    amount: u64,
+    sqrt_price_limit_x64: Option<u128>,
+    zero_for_one: bool,
+    is_base_input: bool,
+) -> SwapState {
+    let mut fixture = fixture(zero_for_one);
+    let (state, _) = get_out_put_amount_and_remaining_accounts(
+        amount,
+        sqrt_price_limit_x64,
+        zero_for_one,
+        is_base_input,
+        &fixture.amm_config,
+        &fixture.pool_state,
+        &fixture.bitmap_extension,
+        &mut fixture.tick_arrays,
+    )
+    .unwrap();
+    state
+}
+
+pub fn quote(amount: u64, zero_for_one: bool, is_base_input: bool) -> u64 {
+    swap(amount, None, zero_for_one, is_base_input).amount_calculated
+}
@@ -0,0 +1,37 @@
+use pricefeeder::solclient::compute_budget::{
+    compute_unit_limit, fee_percentile, PriorityFee, MAX_COMPUTE_UNIT_LIMIT,
+};
+
+#[test]
+fn limit_leaves_a_margin_over_the_simulated_units() {
+    assert_eq!(compute_unit_limit(Some(100_000)), 120_000);
+    assert_eq!(compute_unit_limit(Some(0)), 0);
+}
+
+#[test]
+fn limit_is_the_maximum_without_a_simulation_or_above_it() {
+    assert_eq!(compute_unit_limit(None), MAX_COMPUTE_UNIT_LIMIT);
+    assert_eq!(compute_unit_limit(Some(1_300_000)), MAX_COMPUTE_UNIT_LIMIT);
+}
+
+#[test]
+fn fee_percentile_picks_the_nearest_rank_below() {
+    let fees = [500, 0, 10_000, 1_000, 2_000];
+
+    assert_eq!(fee_percentile(&fees, 0).unwrap(), 0);
+    assert_eq!(fee_percentile(&fees, 50).unwrap(), 1_000);
+    assert_eq!(fee_percentile(&fees, 75).unwrap(), 2_000);
+    assert_eq!(fee_percentile(&fees, 100).unwrap(), 10_000);
+    assert_eq!(fee_percentile(&[], 75).unwrap(), 0);
+    assert!(fee_percentile(&fees, 101).is_err());
+}
+
+#[test]
+fn priority_fee_is_fixed_or_recent() {
+    let fixed: PriorityFee = serde_json::from_str(r#"{"fixed": 10000}"#).unwrap();
+    let recent: PriorityFee = serde_json::from_str(r#"{"recent": 75}"#).unwrap();
+
+    assert!(matches!(fixed, PriorityFee::Fixed(10_000)));
+    assert!(matches!(recent, PriorityFee::Recent(75)));
+    assert!(serde_json::from_str::<PriorityFee>(r#"{"auto": 75}"#).is_err());
+}
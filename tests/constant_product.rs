@@ -0,0 +1,107 @@
+use pricefeeder::{
+    error::SwapError,
+    solclient::constant_product::{swap_base_input, swap_base_output, Fee, Reserves},
+};
+
+/// 0.25%, the most common CPMM fee tier
+const FEE: Fee = Fee {
+    numerator: 2_500,
+    denominator: 1_000_000,
+};
+const NO_FEE: Fee = Fee {
+    numerator: 0,
+    denominator: 1_000_000,
+};
+
+fn reserves(reserve0: u64, reserve1: u64, fee: Fee) -> Reserves {
+    Reserves {
+        reserve0,
+        reserve1,
+        fee,
+    }
+}
+
+#[test]
+fn base_input_takes_the_fee_first() {
+    let swap = swap_base_input(1_000_000, 1_000_000, FEE, 10_000).unwrap();
+
+    assert_eq!(swap.fee_amount, 25);
+    assert_eq!(swap.amount_in, 10_000);
+    // 9_975 * 1_000_000 / 1_009_975, rounded down
+    assert_eq!(swap.amount_out, 9_876);
+}
+
+#[test]
+fn fee_is_rounded_up() {
+    assert_eq!(FEE.of(1), 1);
+    assert_eq!(FEE.of(400), 1);
+    assert_eq!(FEE.of(401), 2);
+}
+
+#[test]
+fn base_output_is_the_inverse_of_base_input() {
+    let swap = swap_base_output(1_000_000, 1_000_000, FEE, 9_876).unwrap();
+
+    assert_eq!(swap.amount_out, 9_876);
+    assert_eq!(swap.amount_in, 10_000);
+    assert_eq!(
+        swap_base_input(1_000_000, 1_000_000, FEE, swap.amount_in)
+            .unwrap()
+            .amount_out,
+        9_876
+    );
+}
+
+#[test]
+fn equal_reserves_are_priced_at_one() {
+    let pool = reserves(1_000_000, 1_000_000, FEE);
+
+    assert_eq!(pool.sqrt_price_x64(), 1u128 << 64);
+    assert_eq!(pool.liquidity(), 1_000_000);
+}
+
+#[test]
+fn price_limit_caps_the_swap() {
+    let pool = reserves(1_000_000, 1_000_000, NO_FEE);
+    let limit = ((1u128 << 64) as f64 * 0.9) as u128;
+
+    let (swap, limited) = pool.swap(500_000, Some(limit), true, true).unwrap();
+
+    assert!(limited);
+    // sqrt(k) / 0.9 - reserve0
+    assert!((111_100..=111_112).contains(&swap.amount_in));
+    assert!(pool.after(&swap, true).sqrt_price_x64() >= limit - (1 << 40));
+
+    let (swap, limited) = pool.swap(50_000, Some(limit), true, true).unwrap();
+    assert!(!limited);
+    assert_eq!(swap.amount_in, 50_000);
+}
+
+#[test]
+fn price_limit_on_the_wrong_side_is_rejected() {
+    let pool = reserves(1_000_000, 1_000_000, FEE);
+    let above = (1u128 << 64) + 1;
+
+    assert!(matches!(
+        pool.swap(1_000, Some(above), true, true),
+        Err(SwapError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        pool.swap(1_000, Some(1u128 << 63), false, true),
+        Err(SwapError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn empty_pool_has_no_liquidity() {
+    let pool = reserves(0, 1_000_000, FEE);
+
+    assert!(matches!(
+        pool.swap(1_000, None, true, true),
+        Err(SwapError::InsufficientLiquidity)
+    ));
+    assert!(matches!(
+        swap_base_output(1_000_000, 1_000, FEE, 1_000),
+        Err(SwapError::InsufficientLiquidity)
+    ));
+}
@@ -0,0 +1,92 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use pricefeeder::solclient::{
+    events::{swap_events, SwapEvent},
+    pool::TickSource,
+    utils::anchor_discriminator,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+fn swap_event(pool: &Pubkey, amount_0: u64, sqrt_price_x64: u128) -> Vec<u8> {
+    let mut data = Vec::with_capacity(SwapEvent::LEN);
+    data.extend_from_slice(&anchor_discriminator("event:SwapEvent"));
+    data.extend_from_slice(pool.as_ref());
+    for _ in 0..3 {
+        data.extend_from_slice(Pubkey::new_unique().as_ref());
+    }
+    data.extend_from_slice(&amount_0.to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.extend_from_slice(&7u64.to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(&sqrt_price_x64.to_le_bytes());
+    data.extend_from_slice(&1_000u128.to_le_bytes());
+    data.extend_from_slice(&(-42i32).to_le_bytes());
+    data
+}
+
+fn program_data(data: &[u8]) -> String {
+    format!("Program data: {}", STANDARD.encode(data))
+}
+
+#[test]
+fn decodes_the_event_layout() {
+    let pool = Pubkey::new_unique();
+    let data = swap_event(&pool, 1_500_000, 2 << 64);
+    assert_eq!(data.len(), SwapEvent::LEN);
+
+    let event = SwapEvent::decode(&data).unwrap();
+
+    assert_eq!(event.pool_state, pool);
+    assert_eq!(event.amount_0, 1_500_000);
+    assert_eq!(event.amount_1, 7);
+    assert!(event.zero_for_one);
+    assert_eq!(event.sqrt_price_x64, 2 << 64);
+    assert_eq!(event.liquidity, 1_000);
+    assert_eq!(event.tick, -42);
+    assert!(SwapEvent::decode(&data[..100]).is_err());
+}
+
+#[test]
+fn finds_the_swaps_of_the_pool_in_the_logs() {
+    let pool = Pubkey::new_unique();
+    let mut not_an_event = swap_event(&pool, 1, 1 << 64);
+    not_an_event[0] ^= 1;
+    let logs = vec![
+        "Program CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK invoke [1]".to_string(),
+        program_data(&swap_event(&pool, 1, 1 << 64)),
+        program_data(&swap_event(&Pubkey::new_unique(), 2, 1 << 64)),
+        program_data(&not_an_event),
+        "Program data: not base64".to_string(),
+        program_data(&swap_event(&pool, 3, 1 << 64)),
+    ];
+
+    let amounts: Vec<_> = swap_events(&logs, &pool)
+        .map(|event| event.amount_0)
+        .collect();
+
+    assert_eq!(amounts, vec![1, 3]);
+}
+
+#[test]
+fn swap_becomes_a_tick_at_its_block_time() {
+    let pool = Pubkey::new_unique();
+    let event = SwapEvent::decode(&swap_event(&pool, 1_500_000, 2 << 64)).unwrap();
+    let signature = Signature::new_unique();
+
+    let tick = event.price_tick(signature, 1, 42, 1_700_000_000, 6, 6);
+
+    assert_eq!(tick.pool, pool);
+    assert_eq!(tick.slot, 42);
+    assert_eq!(tick.observed_at, 1_700_000_000_000);
+    assert_eq!(tick.price, 4f64);
+    assert_eq!(tick.tick, -42);
+    assert_eq!(tick.volume0, 1.5);
+    assert_eq!(
+        tick.source,
+        TickSource::Backfill {
+            signature,
+            event_index: 1
+        }
+    );
+    assert_eq!(tick.source.name(), "backfill");
+}
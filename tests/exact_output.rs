@@ -0,0 +1,141 @@
+mod common;
+
+use common::{fixture, quote};
+use pricefeeder::{
+    constants::MAX_TICK_ARRAYS,
+    solclient::{engine::QuoteEngine, pool::Pool},
+};
+use solana_sdk::pubkey::Pubkey;
+use spl_token_2022::{
+    extension::{
+        transfer_fee::{TransferFee, TransferFeeConfig},
+        ExtensionType, StateWithExtensionsMut,
+    },
+    state::Mint,
+};
+
+const EPOCH: u64 = 500;
+
+fn assert_round_trip(zero_for_one: bool) {
+    for amount_in in [1_000, 1_000_000, 12_345_678, 1_000_000_000] {
+        let amount_out = quote(amount_in, zero_for_one, true);
+        let amount_in_back = quote(amount_out, zero_for_one, false);
+
+        // both directions round against the user, so they may only differ by rounding
+        assert!(
+            amount_in.abs_diff(amount_in_back) <= 1,
+            "exact in {} -> {}, exact out {} -> {}",
+            amount_in,
+            amount_out,
+            amount_out,
+            amount_in_back
+        );
+    }
+}
+
+#[test]
+fn exact_output_matches_exact_input_zero_for_one() {
+    assert_round_trip(true);
+}
+
+#[test]
+fn exact_output_matches_exact_input_one_for_zero() {
+    assert_round_trip(false);
+}
+
+#[test]
+fn exact_output_costs_more_than_it_returns() {
+    let amount_out = 1_000_000;
+    let amount_in = quote(amount_out, true, false);
+
+    // price is ~1.0005 token1 per token0 and the fee is 0.25%
+    assert!(amount_in > amount_out * 99 / 100);
+    assert!(amount_in < amount_out * 101 / 100);
+}
+
+/// A token-2022 mint, with a transfer fee of `transfer_fee_bps` since epoch 0 if any
+fn mint(transfer_fee_bps: Option<u16>) -> Vec<u8> {
+    let extensions = match transfer_fee_bps {
+        Some(_) => vec![ExtensionType::TransferFeeConfig],
+        None => vec![],
+    };
+    let mut data = vec![0; ExtensionType::try_calculate_account_len::<Mint>(&extensions).unwrap()];
+    let mut state = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+    if let Some(transfer_fee_bps) = transfer_fee_bps {
+        let transfer_fee = TransferFee {
+            epoch: 0.into(),
+            maximum_fee: u64::MAX.into(),
+            transfer_fee_basis_points: transfer_fee_bps.into(),
+        };
+        let config = state.init_extension::<TransferFeeConfig>(true).unwrap();
+        config.older_transfer_fee = transfer_fee;
+        config.newer_transfer_fee = transfer_fee;
+    }
+    state.base = Mint {
+        decimals: 6,
+        is_initialized: true,
+        ..Mint::default()
+    };
+    state.pack_base();
+    state.init_account_type().unwrap();
+    data
+}
+
+/// The fixture pool with a 1% transfer fee on mint0
+fn fee_bearing_pool() -> Pool {
+    let fixture = fixture(true);
+    Pool::from_engine(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        mint(Some(100)),
+        mint(None),
+        QuoteEngine::new(
+            fixture.amm_config,
+            fixture.pool_state,
+            fixture.bitmap_extension,
+            fixture.tick_arrays,
+        ),
+        MAX_TICK_ARRAYS,
+    )
+}
+
+fn assert_round_trip_with_transfer_fee(zero_for_one: bool) {
+    let pool = fee_bearing_pool();
+    for amount_in in [1_000_000, 12_345_678, 1_000_000_000] {
+        let exact_in = pool
+            .quote_at_epoch(EPOCH, amount_in, None, zero_for_one, true)
+            .unwrap();
+        let exact_out = pool
+            .quote_at_epoch(EPOCH, exact_in.amount_out, None, zero_for_one, false)
+            .unwrap();
+
+        // the transfer fee of mint0 applies on the way in or out, both ways
+        let without_fee = quote(amount_in, zero_for_one, true);
+        assert!(
+            exact_in.amount_out < without_fee * 995 / 1000,
+            "{} out with a transfer fee, {} without",
+            exact_in.amount_out,
+            without_fee
+        );
+        assert_eq!(exact_out.amount_out, exact_in.amount_out);
+        // inverting the transfer fee may round up a unit on each side
+        assert!(
+            amount_in.abs_diff(exact_out.amount_in) <= 2,
+            "exact in {} -> {}, exact out {} -> {}",
+            amount_in,
+            exact_in.amount_out,
+            exact_in.amount_out,
+            exact_out.amount_in
+        );
+    }
+}
+
+#[test]
+fn exact_output_matches_exact_input_with_input_transfer_fee() {
+    assert_round_trip_with_transfer_fee(true);
+}
+
+#[test]
+fn exact_output_matches_exact_input_with_output_transfer_fee() {
+    assert_round_trip_with_transfer_fee(false);
+}
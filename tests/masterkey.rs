@@ -0,0 +1,59 @@
+use pricefeeder::models::account::{Keyring, Masterkey};
+use solana_sdk::{signature::Keypair, signer::Signer};
+
+fn masterkey(byte: u8) -> Masterkey {
+    Masterkey::new(vec![byte; 32]).unwrap()
+}
+
+#[test]
+fn key_id_is_a_fingerprint_of_the_key() {
+    assert_eq!(masterkey(1).id, masterkey(1).id);
+    assert_ne!(masterkey(1).id, masterkey(2).id);
+    assert_eq!(masterkey(1).id.len(), 16);
+    assert!(Masterkey::new(vec![1; 31]).is_err());
+}
+
+#[test]
+fn keyring_unseals_with_the_key_of_the_id() {
+    let (old, new) = (masterkey(1), masterkey(2));
+    let keypair = Keypair::new();
+    let (ciphertext, nonce) = old.seal_keypair(&keypair).unwrap();
+    let keyring = Keyring::new(vec![new.clone(), old.clone()]).unwrap();
+
+    assert_eq!(keyring.current().id, new.id);
+    let (unsealed, used) = keyring
+        .unseal_keypair(Some(&old.id), &ciphertext, &nonce)
+        .unwrap();
+    assert_eq!(unsealed.pubkey(), keypair.pubkey());
+    assert_eq!(used.id, old.id);
+}
+
+#[test]
+fn wallets_without_key_id_try_every_key() {
+    let (old, new) = (masterkey(1), masterkey(2));
+    let keypair = Keypair::new();
+    let (ciphertext, nonce) = old.seal_keypair(&keypair).unwrap();
+    let keyring = Keyring::new(vec![new, old.clone()]).unwrap();
+
+    let (unsealed, used) = keyring.unseal_keypair(None, &ciphertext, &nonce).unwrap();
+
+    assert_eq!(unsealed.pubkey(), keypair.pubkey());
+    assert_eq!(used.id, old.id);
+}
+
+#[test]
+fn missing_or_wrong_key_fails() {
+    let (old, new) = (masterkey(1), masterkey(2));
+    let (ciphertext, nonce) = old.seal_keypair(&Keypair::new()).unwrap();
+    let keyring = Keyring::new(vec![new.clone()]).unwrap();
+
+    assert!(keyring
+        .unseal_keypair(Some(&old.id), &ciphertext, &nonce)
+        .is_err());
+    assert!(keyring.unseal_keypair(None, &ciphertext, &nonce).is_err());
+    // a key id pointing at a key that did not seal it
+    assert!(keyring
+        .unseal_keypair(Some(&new.id), &ciphertext, &nonce)
+        .is_err());
+    assert!(Keyring::new(vec![]).is_err());
+}
@@ -0,0 +1,119 @@
+use pricefeeder::{
+    error::OracleError,
+    solclient::{
+        oracle::{Observation, ObservationState, OBSERVATION_NUM},
+        utils::anchor_discriminator,
+    },
+};
+use solana_sdk::pubkey::Pubkey;
+
+/// Observations in slots 0.., the last one given being the most recent
+fn observations(written: &[(u32, i64)]) -> ObservationState {
+    let mut observations = vec![Observation::default(); OBSERVATION_NUM];
+    for (slot, (block_timestamp, tick_cumulative)) in written.iter().enumerate() {
+        observations[slot] = Observation {
+            block_timestamp: *block_timestamp,
+            tick_cumulative: *tick_cumulative,
+        };
+    }
+    ObservationState {
+        initialized: !written.is_empty(),
+        recent_epoch: 0,
+        observation_index: written.len().saturating_sub(1) as u16,
+        pool_id: Pubkey::new_unique(),
+        observations,
+    }
+}
+
+#[test]
+fn constant_tick_averages_to_itself() {
+    let state = observations(&[(1_000, 0), (1_100, 50 * 100)]);
+
+    let twap = state.twap(100, 1_200, 50).unwrap();
+
+    assert_eq!(twap.window_secs, 100);
+    assert_eq!(twap.average_tick, 50);
+}
+
+#[test]
+fn window_start_between_observations_is_interpolated() {
+    // tick 10 for 100s, then 30 for 100s
+    let state = observations(&[(1_000, 0), (1_100, 1_000), (1_200, 4_000)]);
+
+    assert_eq!(state.tick_cumulative_at(1_050, 30), Ok(500));
+    // (4_000 - 500) / 150
+    assert_eq!(state.twap(150, 1_200, 30).unwrap().average_tick, 23);
+}
+
+#[test]
+fn negative_average_rounds_down() {
+    let state = observations(&[(1_000, 0), (1_002, -3)]);
+
+    assert_eq!(state.twap(2, 1_002, -2).unwrap().average_tick, -2);
+}
+
+#[test]
+fn window_beyond_the_oldest_observation_fails() {
+    let state = observations(&[(1_000, 0), (1_100, 5_000)]);
+
+    assert_eq!(
+        state.twap(300, 1_200, 50),
+        Err(OracleError::HistoryTooShort(1_000))
+    );
+    assert_eq!(state.twap(0, 1_200, 50), Err(OracleError::EmptyWindow));
+}
+
+#[test]
+fn uninitialized_observations_have_no_price() {
+    let state = observations(&[]);
+
+    assert_eq!(state.twap(60, 1_200, 0), Err(OracleError::NotInitialized));
+}
+
+#[test]
+fn ring_buffer_wraps_around() {
+    // every slot written, the most recent one is slot 1 so slot 2 is the oldest
+    let mut state = observations(&[]);
+    state.initialized = true;
+    state.observation_index = 1;
+    for (slot, observation) in state.observations.iter_mut().enumerate() {
+        let age = (slot + OBSERVATION_NUM - 2) % OBSERVATION_NUM;
+        observation.block_timestamp = 1_000 + age as u32 * 15;
+        observation.tick_cumulative = age as i64 * 15 * 7;
+    }
+
+    assert_eq!(state.oldest_timestamp(), Some(1_000));
+    let timestamps: Vec<_> = state
+        .chronological()
+        .map(|observation| observation.block_timestamp)
+        .collect();
+    assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(state.twap(600, 1_000 + 99 * 15, 7).unwrap().average_tick, 7);
+}
+
+#[test]
+fn decodes_the_account_layout() {
+    let mut data = vec![0u8; ObservationState::LEN];
+    data[..8].copy_from_slice(&anchor_discriminator("account:ObservationState"));
+    data[8] = 1;
+    data[17..19].copy_from_slice(&1u16.to_le_bytes());
+    let pool_id = Pubkey::new_unique();
+    data[19..51].copy_from_slice(pool_id.as_ref());
+    // second observation, 51 + 44
+    data[95..99].copy_from_slice(&1_100u32.to_le_bytes());
+    data[99..107].copy_from_slice(&(-4_200i64).to_le_bytes());
+
+    let state = ObservationState::decode(&data).unwrap();
+
+    assert!(state.initialized);
+    assert_eq!(state.observation_index, 1);
+    assert_eq!(state.pool_id, pool_id);
+    assert_eq!(
+        state.observations[1],
+        Observation {
+            block_timestamp: 1_100,
+            tick_cumulative: -4_200,
+        }
+    );
+    assert!(ObservationState::decode(&data[..100]).is_err());
+}
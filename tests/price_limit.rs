@@ -0,0 +1,58 @@
+mod common;
+
+use common::swap;
+use pricefeeder::solclient::utils::price_to_sqrt_price_x64;
+use raydium_amm_v3::libraries::tick_math;
+
+#[test]
+fn price_limit_stops_swap_early() {
+    let amount = 10_000_000_000;
+    let sqrt_price_limit_x64 = tick_math::get_sqrt_price_at_tick(-50).unwrap();
+
+    let unlimited = swap(amount, None, true, true);
+    let limited = swap(amount, Some(sqrt_price_limit_x64), true, true);
+
+    assert_eq!(unlimited.amount_specified_remaining, 0);
+    assert_eq!(limited.sqrt_price_x64, sqrt_price_limit_x64);
+    assert!(limited.amount_specified_remaining > 0);
+    assert!(limited.amount_calculated < unlimited.amount_calculated);
+
+    // filling only what the limit allowed gives the same output
+    let consumed = amount - limited.amount_specified_remaining;
+    let refill = swap(consumed, None, true, true);
+    assert!(refill.amount_calculated.abs_diff(limited.amount_calculated) <= 1);
+}
+
+#[test]
+fn price_limit_out_of_reach_fills_completely() {
+    let sqrt_price_limit_x64 = tick_math::get_sqrt_price_at_tick(100).unwrap();
+
+    let state = swap(1_000_000, Some(sqrt_price_limit_x64), false, true);
+
+    assert_eq!(state.amount_specified_remaining, 0);
+    assert!(state.sqrt_price_x64 < sqrt_price_limit_x64);
+}
+
+#[test]
+fn exact_output_price_limit_fills_partially() {
+    let sqrt_price_limit_x64 = tick_math::get_sqrt_price_at_tick(-50).unwrap();
+
+    let state = swap(10_000_000_000, Some(sqrt_price_limit_x64), true, false);
+
+    assert_eq!(state.sqrt_price_x64, sqrt_price_limit_x64);
+    assert!(state.amount_specified_remaining > 0);
+}
+
+#[test]
+fn price_limits_a_pool_cannot_reach_are_rejected() {
+    for price in [0f64, -1.5, f64::NAN, f64::INFINITY, 1e-40, 1e40] {
+        assert!(
+            price_to_sqrt_price_x64(price, 6, 9).is_err(),
+            "price {} should be rejected",
+            price
+        );
+    }
+
+    let sqrt_price_x64 = price_to_sqrt_price_x64(1.0, 6, 6).unwrap();
+    assert!(sqrt_price_x64.abs_diff(tick_math::get_sqrt_price_at_tick(0).unwrap()) < 1 << 20);
+}
@@ -0,0 +1,50 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use pricefeeder::solclient::pubsub::{Resubscriptions, FLAP_WINDOW};
+use solana_sdk::pubkey::Pubkey;
+
+#[test]
+fn a_pool_dropping_right_after_its_resubscription_is_flapping() {
+    let pool = Pubkey::new_unique();
+    let resubscribed_at = Instant::now();
+    let mut resubscriptions = Resubscriptions::default();
+    resubscriptions.record([pool], resubscribed_at);
+
+    let dropped_at = resubscribed_at + Duration::from_millis(300);
+    assert!(resubscriptions.flapping(&HashSet::from([pool]), dropped_at));
+}
+
+#[test]
+fn a_pool_dropping_long_after_its_resubscription_is_not_flapping() {
+    let pool = Pubkey::new_unique();
+    let resubscribed_at = Instant::now();
+    let mut resubscriptions = Resubscriptions::default();
+    resubscriptions.record([pool], resubscribed_at);
+
+    let dropped_at = resubscribed_at + FLAP_WINDOW;
+    assert!(!resubscriptions.flapping(&HashSet::from([pool]), dropped_at));
+    // forgotten, an earlier drop does not bring it back
+    assert!(!resubscriptions.flapping(&HashSet::from([pool]), resubscribed_at));
+}
+
+#[test]
+fn other_pools_resubscribed_recently_do_not_count() {
+    let resubscribed = Pubkey::new_unique();
+    let dropped = Pubkey::new_unique();
+    let now = Instant::now();
+    let mut resubscriptions = Resubscriptions::default();
+    resubscriptions.record([resubscribed], now);
+
+    assert!(!resubscriptions.flapping(&HashSet::from([dropped]), now));
+    assert!(resubscriptions.flapping(&HashSet::from([dropped, resubscribed]), now));
+}
+
+#[test]
+fn pools_never_resubscribed_are_not_flapping() {
+    let mut resubscriptions = Resubscriptions::default();
+    let pools = HashSet::from([Pubkey::new_unique(), Pubkey::new_unique()]);
+    assert!(!resubscriptions.flapping(&pools, Instant::now()));
+}
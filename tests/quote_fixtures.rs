@@ -0,0 +1,116 @@
+use std::{fs, path::Path};
+
+use pricefeeder::solclient::engine::{PoolSnapshot, QuoteEngine};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Fixture {
+    description: String,
+    snapshot: PoolSnapshot,
+    swaps: Vec<SwapCase>,
+}
+
+#[derive(Deserialize)]
+struct SwapCase {
+    amount: u64,
+    sqrt_price_limit_x64: Option<u128>,
+    zero_for_one: bool,
+    is_base_input: bool,
+    /// Either the expected swap result, what the swap did on-chain or the expected error
+    /// message
+    expected: Option<Expected>,
+    observed: Option<Observed>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+struct Expected {
+    amount_calculated: u64,
+    amount_specified_remaining: u64,
+    sqrt_price_x64: u128,
+    fee_amount: u64,
+    ticks_crossed: u32,
+}
+
+/// The swap that followed a captured mainnet snapshot, see `capture-fixture`
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+struct Observed {
+    amount_calculated: u64,
+    amount_specified_remaining: u64,
+    sqrt_price_x64: u128,
+}
+
+#[test]
+fn quotes_match_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut checked = 0;
+
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(true, |extension| extension != "json") {
+            continue;
+        }
+        let fixture: Fixture = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        let engine = QuoteEngine::from_snapshot(&fixture.snapshot).unwrap();
+
+        for (i, swap) in fixture.swaps.iter().enumerate() {
+            let result = engine.quote(
+                swap.amount,
+                swap.sqrt_price_limit_x64,
+                swap.zero_for_one,
+                swap.is_base_input,
+            );
+
+            match (result, &swap.error) {
+                (Ok((state, _)), None) => {
+                    assert!(
+                        swap.expected.is_some() || swap.observed.is_some(),
+                        "{} swap #{}: nothing to compare with",
+                        path.display(),
+                        i
+                    );
+                    if let Some(expected) = &swap.expected {
+                        assert_eq!(
+                            &Expected {
+                                amount_calculated: state.amount_calculated,
+                                amount_specified_remaining: state.amount_specified_remaining,
+                                sqrt_price_x64: state.sqrt_price_x64,
+                                fee_amount: state.fee_amount,
+                                ticks_crossed: state.ticks_crossed,
+                            },
+                            expected,
+                            "{} swap #{} ({})",
+                            path.display(),
+                            i,
+                            fixture.description
+                        );
+                    }
+                    if let Some(observed) = &swap.observed {
+                        assert_eq!(
+                            &Observed {
+                                amount_calculated: state.amount_calculated,
+                                amount_specified_remaining: state.amount_specified_remaining,
+                                sqrt_price_x64: state.sqrt_price_x64,
+                            },
+                            observed,
+                            "{} swap #{} ({})",
+                            path.display(),
+                            i,
+                            fixture.description
+                        );
+                    }
+                }
+                (Err(e), Some(error)) => {
+                    assert_eq!(&e.to_string(), error, "{} swap #{}", path.display(), i)
+                }
+                (Ok(_), Some(error)) => {
+                    panic!("{} swap #{}: expected error {}", path.display(), i, error)
+                }
+                (Err(e), None) => panic!("{} swap #{}: {}", path.display(), i, e),
+            }
+            checked += 1;
+        }
+    }
+
+    assert!(checked > 0, "no fixtures found");
+}
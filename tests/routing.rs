@@ -0,0 +1,119 @@
+use pricefeeder::solclient::{registry::PoolInfo, router::find_paths};
+use solana_sdk::pubkey::Pubkey;
+
+/// Mints in ascending order, so pools can be built in pool order
+fn mints<const N: usize>() -> [Pubkey; N] {
+    let mut mints = [(); N].map(|_| Pubkey::new_unique());
+    mints.sort();
+    mints
+}
+
+fn pool(mint_a: Pubkey, mint_b: Pubkey, liquidity: u128) -> PoolInfo {
+    let (mint0, mint1) = if mint_a < mint_b {
+        (mint_a, mint_b)
+    } else {
+        (mint_b, mint_a)
+    };
+    PoolInfo {
+        pool: Pubkey::new_unique(),
+        mint0,
+        mint1,
+        amm_config: Pubkey::new_unique(),
+        fee_index: 0,
+        trade_fee_rate: 2500,
+        tick_spacing: 60,
+        liquidity,
+        sqrt_price_x64: 1 << 64,
+    }
+}
+
+fn route(path: &[pricefeeder::solclient::router::Hop]) -> Vec<Pubkey> {
+    path.iter().map(|hop| hop.pool).collect()
+}
+
+#[test]
+fn finds_direct_and_multi_hop_paths() {
+    let [x, sol, usdc, y] = mints();
+    let direct = pool(x, y, 10);
+    let x_sol = pool(x, sol, 1_000);
+    let sol_y = pool(sol, y, 1_000);
+    let sol_usdc = pool(sol, usdc, 1_000);
+    let usdc_y = pool(usdc, y, 1_000);
+    let pools = vec![
+        direct.clone(),
+        x_sol.clone(),
+        sol_y.clone(),
+        sol_usdc.clone(),
+        usdc_y.clone(),
+    ];
+
+    let paths = find_paths(&pools, x, y, 3);
+
+    assert_eq!(
+        paths.iter().map(|path| route(path)).collect::<Vec<_>>(),
+        vec![
+            vec![direct.pool],
+            vec![x_sol.pool, sol_y.pool],
+            vec![x_sol.pool, sol_usdc.pool, usdc_y.pool],
+        ]
+    );
+}
+
+#[test]
+fn hops_are_oriented_in_swap_direction() {
+    let [a, b, c] = mints();
+    // c -> b -> a runs against pool order on both hops
+    let pools = vec![pool(a, b, 1), pool(b, c, 1)];
+
+    let paths = find_paths(&pools, c, a, 2);
+
+    assert_eq!(paths.len(), 1);
+    let hops = &paths[0];
+    assert_eq!((hops[0].input_mint, hops[0].output_mint), (c, b));
+    assert!(!hops[0].zero_for_one);
+    assert_eq!((hops[1].input_mint, hops[1].output_mint), (b, a));
+    assert!(!hops[1].zero_for_one);
+}
+
+#[test]
+fn respects_max_hops() {
+    let [x, a, b, y] = mints();
+    let pools = vec![pool(x, a, 1), pool(a, b, 1), pool(b, y, 1)];
+
+    assert!(find_paths(&pools, x, y, 2).is_empty());
+    assert_eq!(find_paths(&pools, x, y, 3).len(), 1);
+}
+
+#[test]
+fn skips_empty_pools_and_cycles() {
+    let [x, a, b, y] = mints();
+    let pools = vec![
+        pool(x, y, 0),
+        pool(x, a, 1),
+        pool(a, x, 1),
+        pool(a, b, 1),
+        pool(b, x, 1),
+    ];
+
+    // no liquid path reaches y, and going back to x is not a route
+    assert!(find_paths(&pools, x, y, 3).is_empty());
+}
+
+#[test]
+fn prefers_deeper_paths_of_the_same_length() {
+    let [x, shallow, deep, y] = mints();
+    let x_shallow = pool(x, shallow, 1_000);
+    let x_deep = pool(x, deep, 1_000);
+    let pools = vec![
+        x_shallow.clone(),
+        pool(shallow, y, 5),
+        x_deep.clone(),
+        pool(deep, y, 500),
+    ];
+
+    let paths = find_paths(&pools, x, y, 2);
+
+    assert_eq!(paths.len(), 2);
+    assert_eq!(paths[0][0].pool, x_deep.pool);
+    assert_eq!(paths[1][0].pool, x_shallow.pool);
+}
@@ -0,0 +1,67 @@
+use pricefeeder::solclient::split::allocate;
+
+/// Constant product output of a pool holding `reserve` of both mints
+fn pool(reserve: u64) -> impl Fn(u64) -> Option<u64> {
+    move |amount| {
+        Some((amount as u128 * reserve as u128 / (reserve as u128 + amount as u128)) as u64)
+    }
+}
+
+#[test]
+fn single_pool_takes_everything() {
+    let quote = pool(1_000_000);
+
+    assert_eq!(
+        allocate(10_000, 1, |_, amount| quote(amount)),
+        Some(vec![10_000])
+    );
+}
+
+#[test]
+fn equal_pools_share_the_order() {
+    let quote = pool(1_000_000);
+
+    let allocated = allocate(100_000, 2, |_, amount| quote(amount)).unwrap();
+
+    assert_eq!(allocated.iter().sum::<u64>(), 100_000);
+    assert_eq!(allocated, vec![50_000, 50_000]);
+}
+
+#[test]
+fn deeper_pool_gets_the_larger_share() {
+    let (shallow, deep) = (pool(100_000), pool(1_000_000));
+
+    let allocated = allocate(100_000, 2, |i, amount| {
+        if i == 0 {
+            shallow(amount)
+        } else {
+            deep(amount)
+        }
+    })
+    .unwrap();
+
+    assert_eq!(allocated.iter().sum::<u64>(), 100_000);
+    assert!(allocated[1] > allocated[0]);
+    assert!(allocated[0] > 0);
+}
+
+#[test]
+fn pools_that_cannot_fill_are_skipped() {
+    let quote = pool(1_000_000);
+
+    let allocated = allocate(10_000, 2, |i, amount| {
+        (i == 1).then(|| quote(amount)).flatten()
+    });
+
+    assert_eq!(allocated, Some(vec![0, 10_000]));
+    assert_eq!(allocate(10_000, 2, |_, _| None), None);
+}
+
+#[test]
+fn small_orders_are_not_split_into_empty_chunks() {
+    let quote = pool(1_000_000);
+
+    let allocated = allocate(3, 2, |_, amount| quote(amount)).unwrap();
+
+    assert_eq!(allocated.iter().sum::<u64>(), 3);
+}
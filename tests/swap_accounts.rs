@@ -0,0 +1,61 @@
+mod common;
+
+use std::collections::VecDeque;
+
+use common::fixture;
+use pricefeeder::{
+    constants::{MAX_SWAP_TICK_ARRAYS, MAX_TICK_ARRAYS},
+    error::SwapError,
+    solclient::{engine::QuoteEngine, pool::Pool},
+};
+use solana_sdk::pubkey::Pubkey;
+
+fn pool() -> Pool {
+    let fixture = fixture(true);
+    Pool::from_engine(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        vec![],
+        vec![],
+        QuoteEngine::new(
+            fixture.amm_config,
+            fixture.pool_state,
+            fixture.bitmap_extension,
+            fixture.tick_arrays,
+        ),
+        MAX_TICK_ARRAYS,
+    )
+}
+
+/// Start indexes of `count` tick arrays in a row
+fn tick_arrays(count: usize) -> VecDeque<i32> {
+    (0..count as i32).map(|i| i * -600).collect()
+}
+
+#[test]
+fn swap_passes_the_bitmap_extension_and_its_tick_arrays() {
+    let pool = pool();
+    let program = Pubkey::new_unique();
+
+    let accounts = pool
+        .remaining_accounts(&program, tick_arrays(MAX_SWAP_TICK_ARRAYS))
+        .unwrap();
+
+    assert_eq!(accounts.len(), MAX_SWAP_TICK_ARRAYS + 1);
+    assert!(!accounts[0].is_writable);
+    assert!(accounts[1..].iter().all(|account| account.is_writable));
+}
+
+#[test]
+fn swap_crossing_more_tick_arrays_than_a_transaction_fits_fails() {
+    let pool = pool();
+    let program = Pubkey::new_unique();
+
+    assert_eq!(
+        pool.remaining_accounts(&program, tick_arrays(MAX_SWAP_TICK_ARRAYS + 1)),
+        Err(SwapError::TooManyTickArrays(
+            MAX_SWAP_TICK_ARRAYS + 1,
+            MAX_SWAP_TICK_ARRAYS
+        ))
+    );
+}
@@ -0,0 +1,137 @@
+use pricefeeder::solclient::token_accounts::{NativeAccount, SwapAccounts};
+use solana_sdk::{pubkey::Pubkey, system_program};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+
+#[test]
+fn derives_associated_accounts_with_the_program_of_each_mint() {
+    let (payer, input_mint, output_mint) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+
+    let accounts = SwapAccounts::derive(
+        &payer,
+        &input_mint,
+        &spl_token::id(),
+        &output_mint,
+        &spl_token_2022::id(),
+        None,
+        None,
+    );
+
+    assert_eq!(
+        accounts.input_token,
+        get_associated_token_address_with_program_id(&payer, &input_mint, &spl_token::id())
+    );
+    assert_eq!(
+        accounts.output_token,
+        get_associated_token_address_with_program_id(&payer, &output_mint, &spl_token_2022::id())
+    );
+    assert_ne!(
+        accounts.output_token,
+        get_associated_token_address_with_program_id(&payer, &output_mint, &spl_token::id())
+    );
+}
+
+#[test]
+fn derived_output_account_is_created_idempotently() {
+    let (payer, input_mint, output_mint) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+
+    let accounts = SwapAccounts::derive(
+        &payer,
+        &input_mint,
+        &spl_token::id(),
+        &output_mint,
+        &spl_token_2022::id(),
+        None,
+        None,
+    );
+
+    let [create] = accounts.setup.as_slice() else {
+        panic!("expected a single setup instruction");
+    };
+    assert_eq!(create.program_id, spl_associated_token_account::id());
+    // CreateIdempotent
+    assert_eq!(create.data, vec![1]);
+    let keys: Vec<_> = create.accounts.iter().map(|meta| meta.pubkey).collect();
+    assert!(keys.contains(&accounts.output_token));
+    assert!(keys.contains(&output_mint));
+    assert!(keys.contains(&spl_token_2022::id()));
+}
+
+#[test]
+fn explicit_accounts_are_used_as_given() {
+    let (input_token, output_token) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    let accounts = SwapAccounts::derive(
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &spl_token::id(),
+        &Pubkey::new_unique(),
+        &spl_token::id(),
+        Some(input_token),
+        Some(output_token),
+    );
+
+    assert_eq!(accounts.input_token, input_token);
+    assert_eq!(accounts.output_token, output_token);
+    assert!(accounts.setup.is_empty());
+}
+
+#[test]
+fn native_input_is_wrapped_before_and_closed_after_the_swap() {
+    let payer = Pubkey::new_unique();
+    let seed = NativeAccount::unique_seed();
+    assert!(seed.len() <= 32);
+
+    let native = NativeAccount::new(&payer, &seed, 2_039_280, 500_000_000).unwrap();
+
+    assert_eq!(
+        native.address,
+        Pubkey::create_with_seed(&payer, &seed, &spl_token::id()).unwrap()
+    );
+    let programs: Vec<_> = native.setup.iter().map(|ix| ix.program_id).collect();
+    // create, initialize, transfer, sync_native
+    assert_eq!(
+        programs,
+        vec![
+            system_program::id(),
+            spl_token::id(),
+            system_program::id(),
+            spl_token::id()
+        ]
+    );
+    let [close] = native.cleanup.as_slice() else {
+        panic!("expected a single cleanup instruction");
+    };
+    assert_eq!(close.program_id, spl_token::id());
+    assert_eq!(close.accounts[0].pubkey, native.address);
+    assert_eq!(close.accounts[1].pubkey, payer);
+}
+
+#[test]
+fn native_output_is_only_created_and_closed() {
+    let payer = Pubkey::new_unique();
+    let native = NativeAccount::new(&payer, "wsol0000000000000001", 2_039_280, 0).unwrap();
+
+    assert_eq!(native.setup.len(), 2);
+    assert_eq!(native.cleanup.len(), 1);
+
+    let mut accounts = SwapAccounts::derive(
+        &payer,
+        &Pubkey::new_unique(),
+        &spl_token::id(),
+        &spl_token::native_mint::id(),
+        &spl_token::id(),
+        None,
+        Some(native.address),
+    );
+    accounts.wrap(native);
+    assert_eq!(accounts.setup.len(), 2);
+    assert_eq!(accounts.cleanup.len(), 1);
+}
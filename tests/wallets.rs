@@ -0,0 +1,195 @@
+use pricefeeder::{
+    constants::MAX_WALLET_DAYS,
+    models::account::{Account, Masterkey},
+    solclient::wallet::{
+        sweep_sol_instruction, sweep_token_instructions, TokenBalance, SWEEP_ACCOUNTS_PER_TX,
+    },
+};
+use solana_sdk::{pubkey::Pubkey, system_instruction};
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+
+fn token(program_id: Pubkey, amount: u64) -> TokenBalance {
+    TokenBalance {
+        account: Pubkey::new_unique(),
+        mint: Pubkey::new_unique(),
+        program_id,
+        amount,
+        decimals: 6,
+    }
+}
+
+fn account(id: i32, owner_id: Option<i32>, duration: chrono::Duration) -> Account {
+    let masterkey = Masterkey::new(vec![3; 32]).unwrap();
+    let mut account = Account::new_unique(&masterkey, duration).unwrap();
+    account.id = id;
+    account.owner_id = owner_id;
+    account
+}
+
+#[test]
+fn wallet_is_managed_by_itself_and_its_owner() {
+    let wallet = account(7, Some(1), chrono::Duration::minutes(5));
+
+    assert!(wallet.is_managed_by(7));
+    assert!(wallet.is_managed_by(1));
+    assert!(!wallet.is_managed_by(2));
+}
+
+#[test]
+fn wallet_without_owner_is_only_managed_by_itself() {
+    let wallet = account(1, None, chrono::Duration::minutes(5));
+
+    assert!(wallet.is_managed_by(1));
+    assert!(!wallet.is_managed_by(0));
+}
+
+#[test]
+fn only_the_owner_may_extend_a_wallet() {
+    let wallet = account(7, Some(1), chrono::Duration::minutes(5));
+
+    assert!(wallet.is_owned_by(1));
+    assert!(!wallet.is_owned_by(7));
+    assert!(!wallet.is_owned_by(2));
+    assert!(!account(1, None, chrono::Duration::minutes(5)).is_owned_by(1));
+}
+
+#[test]
+fn extending_counts_from_now_once_expired() {
+    let expired = account(1, None, chrono::Duration::days(-10));
+
+    let die_at = expired.extended_die_at(chrono::Duration::days(1)).unwrap();
+
+    let from_now = die_at - chrono::Utc::now();
+    assert!(from_now > chrono::Duration::hours(23) && from_now <= chrono::Duration::days(1));
+}
+
+#[test]
+fn extending_is_capped() {
+    let wallet = account(1, None, chrono::Duration::days(MAX_WALLET_DAYS - 1));
+
+    assert!(wallet
+        .extended_die_at(chrono::Duration::hours(12))
+        .is_some());
+    assert!(wallet.extended_die_at(chrono::Duration::days(2)).is_none());
+    assert!(wallet
+        .extended_die_at(chrono::Duration::days(280_000 * 365))
+        .is_none());
+}
+
+#[test]
+fn wallet_beyond_the_calendar_is_refused() {
+    let masterkey = Masterkey::new(vec![3; 32]).unwrap();
+
+    assert!(Account::new_unique(&masterkey, chrono::Duration::days(280_000 * 365)).is_err());
+}
+
+#[test]
+fn wallet_expires_at_its_die_at() {
+    assert!(!account(1, None, chrono::Duration::minutes(5)).is_expired());
+    assert!(account(1, None, chrono::Duration::seconds(-1)).is_expired());
+}
+
+#[test]
+fn sweep_moves_each_token_to_the_destination_ata_and_closes_the_account() {
+    let (owner, destination) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let balance = token(spl_token::id(), 1_500_000);
+
+    let transactions = sweep_token_instructions(&owner, &[balance.clone()], &destination).unwrap();
+
+    let target =
+        get_associated_token_address_with_program_id(&destination, &balance.mint, &spl_token::id());
+    assert_eq!(
+        transactions,
+        vec![vec![
+            create_associated_token_account_idempotent(
+                &owner,
+                &destination,
+                &balance.mint,
+                &spl_token::id()
+            ),
+            spl_token_2022::instruction::transfer_checked(
+                &spl_token::id(),
+                &balance.account,
+                &balance.mint,
+                &target,
+                &owner,
+                &[],
+                1_500_000,
+                6,
+            )
+            .unwrap(),
+            spl_token_2022::instruction::close_account(
+                &spl_token::id(),
+                &balance.account,
+                &destination,
+                &owner,
+                &[],
+            )
+            .unwrap(),
+        ]]
+    );
+}
+
+#[test]
+fn sweep_closes_empty_accounts_and_leaves_token_2022_open() {
+    let (owner, destination) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let empty = token(spl_token::id(), 0);
+    let token_2022 = token(spl_token_2022::id(), 7);
+
+    let transactions = sweep_token_instructions(
+        &owner,
+        &[empty.clone(), token(spl_token_2022::id(), 0), token_2022],
+        &destination,
+    )
+    .unwrap();
+
+    assert_eq!(transactions.len(), 1);
+    let programs: Vec<Pubkey> = transactions[0]
+        .iter()
+        .map(|instruction| instruction.program_id)
+        .collect();
+    // the close of the empty account, then ATA creation and transfer of the token-2022 one
+    assert_eq!(
+        programs,
+        vec![
+            spl_token::id(),
+            spl_associated_token_account::id(),
+            spl_token_2022::id()
+        ]
+    );
+    assert_eq!(transactions[0][0].accounts[0].pubkey, empty.account);
+}
+
+#[test]
+fn sweep_splits_token_accounts_over_transactions() {
+    let (owner, destination) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let tokens: Vec<_> = (0..SWEEP_ACCOUNTS_PER_TX + 1)
+        .map(|_| token(spl_token::id(), 1))
+        .collect();
+
+    let transactions = sweep_token_instructions(&owner, &tokens, &destination).unwrap();
+
+    let sizes: Vec<usize> = transactions.iter().map(Vec::len).collect();
+    assert_eq!(sizes, vec![3 * SWEEP_ACCOUNTS_PER_TX, 3]);
+    assert!(sweep_token_instructions(&owner, &[], &destination)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn sweep_sends_the_sol_left_minus_the_fee() {
+    let (owner, destination) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    assert_eq!(
+        sweep_sol_instruction(&owner, &destination, 1_000_000, 5_000),
+        Some(system_instruction::transfer(&owner, &destination, 995_000))
+    );
+    assert_eq!(
+        sweep_sol_instruction(&owner, &destination, 5_000, 5_000),
+        None
+    );
+    assert_eq!(sweep_sol_instruction(&owner, &destination, 0, 5_000), None);
+}